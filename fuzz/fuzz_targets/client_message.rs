@@ -0,0 +1,14 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use satrunner_protocol::messages::ClientMessage;
+use speedy::{Readable, Writable};
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(message) = ClientMessage::read_from_buffer(data) else {
+        return;
+    };
+
+    let bytes = message.write_to_vec().unwrap();
+    assert_eq!(ClientMessage::read_from_buffer(&bytes).unwrap(), message);
+});
@@ -0,0 +1,24 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use satrunner_protocol::{
+    delta::{self, Frame},
+    messages::NetworkMessage,
+};
+use speedy::{Readable, Writable};
+
+// what the client does with every frame from the server
+fuzz_target!(|data: &[u8]| {
+    let Ok(message) = NetworkMessage::read_from_buffer(data) else {
+        return;
+    };
+
+    let bytes = message.write_to_vec().unwrap();
+    assert_eq!(NetworkMessage::read_from_buffer(&bytes).unwrap(), message);
+
+    if let NetworkMessage::Snapshot(snapshot) = message {
+        let baseline = delta::apply(None, &snapshot).unwrap_or_default();
+        let _ = delta::apply(Some(&Frame::new()), &snapshot);
+        let _ = delta::apply(Some(&baseline), &snapshot);
+    }
+});
@@ -0,0 +1,21 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use satrunner_protocol::messages::{ClientMessage, Handshake};
+use satrunner_server::game::Game;
+use speedy::Writable;
+
+// a handshaken client sending arbitrary frames, split on 0xfe, while the
+// game keeps ticking
+fuzz_target!(|data: &[u8]| {
+    let mut game = Game::new(1, 2);
+    game.connect(0);
+    let hello = ClientMessage::Hello(Handshake::client()).write_to_vec().unwrap();
+    game.receive(0, &hello, 0);
+
+    for (now_ms, frame) in data.split(|b| *b == 0xfe).enumerate() {
+        game.receive(0, frame, now_ms as u64);
+        game.step(now_ms as u64);
+        game.drain_outbox();
+    }
+});
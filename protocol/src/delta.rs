@@ -0,0 +1,236 @@
+use std::collections::BTreeMap;
+
+use uuid::Uuid;
+
+use crate::messages::{EntityDelta, NewPos, PlayerState, Snapshot};
+
+// 1/16th of a world unit, enough to cover +-2047 in an i16
+pub const POSITION_SCALE: f32 = 16.0;
+
+pub fn quantize(v: [f32; 2]) -> [i16; 2] {
+    v.map(|c| {
+        (c * POSITION_SCALE)
+            .round()
+            .clamp(i16::MIN as f32, i16::MAX as f32) as i16
+    })
+}
+
+pub fn dequantize(v: [i16; 2]) -> [f32; 2] {
+    v.map(|c| c as f32 / POSITION_SCALE)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EntityState {
+    pub id: Uuid,
+    pub name: Option<String>,
+    pub pos: [i16; 2],
+    pub target: [i16; 2],
+    pub score: u16,
+    pub time_alive: u64,
+    pub alive: bool,
+    pub rtt_ms: u16,
+}
+
+impl EntityState {
+    pub fn new(id: Uuid) -> Self {
+        Self {
+            id,
+            name: None,
+            pos: [0, 0],
+            target: [0, 0],
+            score: 0,
+            time_alive: 0,
+            alive: false,
+            rtt_ms: 0,
+        }
+    }
+
+    pub fn from_player_state(player: &PlayerState) -> Self {
+        Self {
+            id: player.id,
+            name: player.name.clone(),
+            pos: quantize(player.pos),
+            target: quantize(player.target),
+            score: player.score.min(u16::MAX as usize) as u16,
+            time_alive: player.time_alive,
+            alive: player.alive,
+            rtt_ms: player.rtt_ms,
+        }
+    }
+
+    pub fn to_player_state(&self) -> PlayerState {
+        PlayerState {
+            pos: dequantize(self.pos),
+            target: dequantize(self.target),
+            score: self.score as usize,
+            name: self.name.clone(),
+            id: self.id,
+            time_alive: self.time_alive,
+            alive: self.alive,
+            rtt_ms: self.rtt_ms,
+        }
+    }
+}
+
+// full state of every player at one tick, keyed by session index
+pub type Frame = BTreeMap<u16, EntityState>;
+
+fn changed<T: PartialEq + Clone>(old: Option<&T>, new: &T) -> Option<T> {
+    if old == Some(new) {
+        None
+    } else {
+        Some(new.clone())
+    }
+}
+
+pub fn encode(tick: u64, baseline: Option<(u64, &Frame)>, current: &Frame) -> Snapshot {
+    let empty = Frame::new();
+    let (baseline_tick, base) = match baseline {
+        Some((tick, frame)) => (Some(tick), frame),
+        None => (None, &empty),
+    };
+
+    // an index handed to a different player counts as removed and re-added
+    let removed = base
+        .iter()
+        .filter(|(index, old)| !matches!(current.get(index), Some(new) if new.id == old.id))
+        .map(|(index, _)| *index)
+        .collect();
+
+    let entities = current
+        .iter()
+        .filter_map(|(index, new)| {
+            let old = base.get(index).filter(|old| old.id == new.id);
+            let delta = EntityDelta {
+                index: *index,
+                id: old.is_none().then_some(new.id),
+                name: changed(old.map(|o| &o.name), &new.name),
+                pos: changed(old.map(|o| &o.pos), &new.pos),
+                target: changed(old.map(|o| &o.target), &new.target),
+                score: changed(old.map(|o| &o.score), &new.score),
+                time_alive: changed(old.map(|o| &o.time_alive), &new.time_alive),
+                alive: changed(old.map(|o| &o.alive), &new.alive),
+                rtt_ms: changed(old.map(|o| &o.rtt_ms), &new.rtt_ms),
+            };
+            let unchanged = EntityDelta {
+                index: *index,
+                ..Default::default()
+            };
+            (delta != unchanged).then_some(delta)
+        })
+        .collect();
+
+    Snapshot {
+        tick,
+        baseline: baseline_tick,
+        removed,
+        entities,
+    }
+}
+
+// None when the snapshot refers to a baseline we no longer have or is malformed
+pub fn apply(baseline: Option<&Frame>, snapshot: &Snapshot) -> Option<Frame> {
+    let mut frame = match snapshot.baseline {
+        Some(_) => baseline?.clone(),
+        None => Frame::new(),
+    };
+
+    for index in &snapshot.removed {
+        frame.remove(index);
+    }
+
+    for delta in &snapshot.entities {
+        if let Some(id) = delta.id {
+            frame.insert(delta.index, EntityState::new(id));
+        }
+        let state = frame.get_mut(&delta.index)?;
+
+        if let Some(name) = &delta.name {
+            state.name = name.clone();
+        }
+        if let Some(pos) = delta.pos {
+            state.pos = pos;
+        }
+        if let Some(target) = delta.target {
+            state.target = target;
+        }
+        if let Some(score) = delta.score {
+            state.score = score;
+        }
+        if let Some(time_alive) = delta.time_alive {
+            state.time_alive = time_alive;
+        }
+        if let Some(alive) = delta.alive {
+            state.alive = alive;
+        }
+        if let Some(rtt_ms) = delta.rtt_ms {
+            state.rtt_ms = rtt_ms;
+        }
+    }
+
+    Some(frame)
+}
+
+// only living players move
+pub fn positions(tick: u64, frame: &Frame) -> Vec<NewPos> {
+    frame
+        .values()
+        .filter(|state| state.alive)
+        .map(|state| NewPos {
+            input: dequantize(state.target),
+            tick,
+            id: state.id,
+            pos: dequantize(state.pos),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::{apply, encode, EntityState, Frame};
+
+    fn player(id: u128, x: i16) -> EntityState {
+        EntityState {
+            name: Some(format!("p{}", id)),
+            pos: [x, 0],
+            alive: true,
+            ..EntityState::new(Uuid::from_u128(id))
+        }
+    }
+
+    #[test]
+    fn delta_against_baseline_round_trips() {
+        let first: Frame = [(0, player(1, 0)), (1, player(2, 5))].into();
+        let full = encode(10, None, &first);
+        assert_eq!(apply(None, &full).unwrap(), first);
+
+        let mut second = first.clone();
+        second.get_mut(&0).unwrap().pos = [16, 0];
+        second.remove(&1);
+        second.insert(1, player(3, 7));
+        second.insert(2, player(4, 9));
+
+        let delta = encode(11, Some((10, &first)), &second);
+        assert_eq!(delta.removed, vec![1]);
+        assert_eq!(delta.entities[0].id, None);
+        assert_eq!(delta.entities[0].name, None);
+        assert_eq!(delta.entities[0].pos, Some([16, 0]));
+        assert_eq!(apply(Some(&first), &delta).unwrap(), second);
+    }
+
+    #[test]
+    fn unchanged_players_are_omitted() {
+        let frame: Frame = [(0, player(1, 0))].into();
+        let delta = encode(2, Some((1, &frame)), &frame);
+        assert!(delta.entities.is_empty() && delta.removed.is_empty());
+    }
+
+    #[test]
+    fn missing_baseline_is_rejected() {
+        let frame: Frame = [(0, player(1, 0))].into();
+        let delta = encode(2, Some((1, &frame)), &frame);
+        assert!(apply(None, &delta).is_none());
+    }
+}
@@ -0,0 +1,3 @@
+// Wire format shared by the game client and the server.
+pub mod delta;
+pub mod messages;
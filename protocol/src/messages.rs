@@ -0,0 +1,403 @@
+use satrunner_sim::{Field, GameMode, Object, ObjectId, ObjectKind, Power};
+use speedy::{Readable, Writable};
+use uuid::Uuid;
+
+pub const PROTOCOL_VERSION: u32 = 6;
+
+// capability bits exchanged in Hello/Welcome, only the intersection is used
+pub const CAP_RESUME: u32 = 1 << 0;
+pub const CAP_RTT: u32 = 1 << 1;
+pub const CAP_DELTA: u32 = 1 << 2;
+pub const CAP_INPUT_BATCH: u32 = 1 << 3;
+pub const CAP_CHECKSUM: u32 = 1 << 4;
+// sent alone by a latency probe: the server answers with Welcome and hangs up
+// without starting a game, older servers just treat it as a client
+pub const CAP_PROBE: u32 = 1 << 5;
+
+pub const CLIENT_CAPABILITIES: u32 =
+    CAP_RESUME | CAP_RTT | CAP_DELTA | CAP_INPUT_BATCH | CAP_CHECKSUM;
+
+// Network messages
+//
+// Tags are part of the wire format: never renumber or reuse one. The handshake
+// variants sit on a reserved tag and their layout must never change, so any
+// client can always tell it is out of date.
+#[derive(Readable, Writable, Debug, Clone, PartialEq)]
+pub enum NetworkMessage {
+    #[speedy(tag = 0)]
+    GameUpdate(Vec<NewPos>),
+    #[speedy(tag = 1)]
+    GameState(Vec<PlayerState>),
+    #[speedy(tag = 2)]
+    NewGame(NewGame),
+    #[speedy(tag = 3)]
+    Ping(u64),
+    #[speedy(tag = 4)]
+    DamagePlayer(Damage),
+    #[speedy(tag = 5)]
+    ScoreUpdate(Score),
+    #[speedy(tag = 6)]
+    SyncClient(SyncMessage),
+    #[speedy(tag = 7)]
+    Resumed(Option<ResumeState>),
+    #[speedy(tag = 8)]
+    Pong(u64),
+    #[speedy(tag = 9)]
+    Snapshot(Snapshot),
+    // every input up to and including this tick has been simulated
+    #[speedy(tag = 10)]
+    InputAck(u64),
+    #[speedy(tag = 11)]
+    Checksum(Checksum),
+    // the server's objects, in reply to RequestObjects
+    #[speedy(tag = 12)]
+    Objects(ObjectSync),
+    #[speedy(tag = 13)]
+    PickUp(PickUp),
+    #[speedy(tag = 14)]
+    PowerExpired(PowerExpired),
+    #[speedy(tag = 65535)]
+    Welcome(Handshake),
+}
+
+#[derive(Readable, Writable, Debug, Clone, PartialEq)]
+pub enum ClientMessage {
+    #[speedy(tag = 0)]
+    PlayerInput(PlayerInput),
+    #[speedy(tag = 1)]
+    PlayerName(String),
+    #[speedy(tag = 2)]
+    Resume(ResumeRequest),
+    #[speedy(tag = 3)]
+    Ping(u64),
+    #[speedy(tag = 4)]
+    Pong(u64),
+    #[speedy(tag = 5)]
+    SnapshotAck(u64),
+    // the newest unacked inputs, oldest first, so any single batch that
+    // arrives fills the gaps left by lost ones
+    #[speedy(tag = 6)]
+    InputBatch(Vec<PlayerInput>),
+    // our objects stopped matching the server's at this tick
+    #[speedy(tag = 7)]
+    RequestObjects(u64),
+    #[speedy(tag = 65535)]
+    Hello(Handshake),
+}
+
+#[derive(Readable, Writable, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Handshake {
+    pub protocol_version: u32,
+    pub capabilities: u32,
+}
+
+impl Handshake {
+    pub fn client() -> Self {
+        Self {
+            protocol_version: PROTOCOL_VERSION,
+            capabilities: CLIENT_CAPABILITIES,
+        }
+    }
+
+    pub fn probe() -> Self {
+        Self {
+            protocol_version: PROTOCOL_VERSION,
+            capabilities: CAP_PROBE,
+        }
+    }
+}
+
+#[derive(Readable, Writable, Debug, Clone, Default, PartialEq)]
+pub struct NewPos {
+    pub input: [f32; 2],
+    pub tick: u64,
+    pub id: Uuid,
+    pub pos: [f32; 2],
+}
+
+#[derive(Readable, Writable, Debug, Clone, Default, PartialEq)]
+pub struct SyncMessage {
+    pub tick_adjustment: i64,
+    pub server_tick: u64,
+}
+
+#[derive(Readable, Writable, Debug, Clone, PartialEq)]
+pub struct PlayerInput {
+    pub target: [f32; 2],
+    pub id: Uuid,
+    pub tick: u64,
+    pub in_game: bool,
+}
+
+impl PlayerInput {
+    pub fn new(target: [f32; 2], id: Uuid, tick: u64, in_game: bool) -> Self {
+        Self {
+            target,
+            id,
+            tick,
+            in_game,
+        }
+    }
+}
+
+#[derive(Readable, Writable, Debug, Clone, PartialEq)]
+pub struct NewGame {
+    pub id: Uuid,
+    pub server_tick: u64,
+    pub rng_seed: u64,
+    pub mode: ModeMsg,
+    pub high_scores: Vec<(String, u64)>,
+    // The field is rebuilt from the seed with Field::at, so only what was
+    // taken off it while still falling is sent. It is as of `server_tick - 1`.
+    pub removed: Vec<ObjectIdMsg>,
+    pub resume_token: u64,
+}
+
+#[derive(Readable, Writable, Debug, Clone, PartialEq)]
+pub struct ResumeRequest {
+    pub id: Uuid,
+    pub resume_token: u64,
+}
+
+#[derive(Readable, Writable, Debug, Clone, PartialEq)]
+pub struct ResumeState {
+    pub id: Uuid,
+    pub pos: [f32; 2],
+    pub score: usize,
+    pub secs_alive: u64,
+}
+
+#[derive(Readable, Writable, Debug, Clone, PartialEq)]
+pub struct ObjectMsg {
+    pub objects: Vec<(ObjectIdMsg, [f32; 2])>,
+}
+
+impl ObjectMsg {
+    pub fn from_field(field: &Field) -> Self {
+        Self {
+            objects: field
+                .iter()
+                .map(|object| (object.id.into(), object.pos))
+                .collect(),
+        }
+    }
+
+    pub fn to_field(&self, mode: GameMode) -> Field {
+        let mut field = Field::with_mode(mode);
+        for &(id, pos) in &self.objects {
+            let id = ObjectId::from(id);
+            field.get_mut(id.kind).push(Object { id, pos });
+        }
+        field
+    }
+}
+
+#[derive(Readable, Writable, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KindMsg {
+    #[speedy(tag = 0)]
+    Rain,
+    #[speedy(tag = 1)]
+    Bolt,
+    #[speedy(tag = 2)]
+    Shield,
+    #[speedy(tag = 3)]
+    Speed,
+    #[speedy(tag = 4)]
+    Magnet,
+    #[speedy(tag = 5)]
+    Storm,
+}
+
+impl From<ObjectKind> for KindMsg {
+    fn from(kind: ObjectKind) -> Self {
+        match kind {
+            ObjectKind::Rain => KindMsg::Rain,
+            ObjectKind::Bolt => KindMsg::Bolt,
+            ObjectKind::Shield => KindMsg::Shield,
+            ObjectKind::Speed => KindMsg::Speed,
+            ObjectKind::Magnet => KindMsg::Magnet,
+            ObjectKind::Storm => KindMsg::Storm,
+        }
+    }
+}
+
+impl From<KindMsg> for ObjectKind {
+    fn from(kind: KindMsg) -> Self {
+        match kind {
+            KindMsg::Rain => ObjectKind::Rain,
+            KindMsg::Bolt => ObjectKind::Bolt,
+            KindMsg::Shield => ObjectKind::Shield,
+            KindMsg::Speed => ObjectKind::Speed,
+            KindMsg::Magnet => ObjectKind::Magnet,
+            KindMsg::Storm => ObjectKind::Storm,
+        }
+    }
+}
+
+// satrunner_sim::ObjectId on the wire
+#[derive(Readable, Writable, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ObjectIdMsg {
+    pub tick: u64,
+    pub index: u8,
+    pub kind: KindMsg,
+}
+
+impl From<ObjectId> for ObjectIdMsg {
+    fn from(id: ObjectId) -> Self {
+        Self {
+            tick: id.tick,
+            index: id.index,
+            kind: id.kind.into(),
+        }
+    }
+}
+
+impl From<ObjectIdMsg> for ObjectId {
+    fn from(id: ObjectIdMsg) -> Self {
+        ObjectId::new(id.kind.into(), id.tick, id.index)
+    }
+}
+
+#[derive(Readable, Writable, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModeMsg {
+    #[speedy(tag = 0)]
+    Classic,
+    #[speedy(tag = 1)]
+    Rush,
+    #[speedy(tag = 2)]
+    Steady,
+}
+
+impl From<GameMode> for ModeMsg {
+    fn from(mode: GameMode) -> Self {
+        match mode {
+            GameMode::Classic => ModeMsg::Classic,
+            GameMode::Rush => ModeMsg::Rush,
+            GameMode::Steady => ModeMsg::Steady,
+        }
+    }
+}
+
+impl From<ModeMsg> for GameMode {
+    fn from(mode: ModeMsg) -> Self {
+        match mode {
+            ModeMsg::Classic => GameMode::Classic,
+            ModeMsg::Rush => GameMode::Rush,
+            ModeMsg::Steady => GameMode::Steady,
+        }
+    }
+}
+
+#[derive(Readable, Writable, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerMsg {
+    #[speedy(tag = 0)]
+    Shield,
+    #[speedy(tag = 1)]
+    Speed,
+    #[speedy(tag = 2)]
+    Magnet,
+}
+
+impl From<Power> for PowerMsg {
+    fn from(power: Power) -> Self {
+        match power {
+            Power::Shield => PowerMsg::Shield,
+            Power::Speed => PowerMsg::Speed,
+            Power::Magnet => PowerMsg::Magnet,
+        }
+    }
+}
+
+impl From<PowerMsg> for Power {
+    fn from(power: PowerMsg) -> Self {
+        match power {
+            PowerMsg::Shield => Power::Shield,
+            PowerMsg::Speed => Power::Speed,
+            PowerMsg::Magnet => Power::Magnet,
+        }
+    }
+}
+
+// A player took the power-up `object`, timed ones run out on `until`. Powers
+// still held after a resume are restated without an object.
+#[derive(Readable, Writable, Debug, Clone, PartialEq)]
+pub struct PickUp {
+    pub id: Uuid,
+    pub power: PowerMsg,
+    pub object: Option<ObjectIdMsg>,
+    pub until: Option<u64>,
+}
+
+// a timed power ran out or a shield was used up on the object `absorbed`
+#[derive(Readable, Writable, Debug, Clone, PartialEq)]
+pub struct PowerExpired {
+    pub id: Uuid,
+    pub power: PowerMsg,
+    pub absorbed: Option<ObjectIdMsg>,
+}
+
+// satrunner_sim::checksum of the objects once `tick` has been simulated
+#[derive(Readable, Writable, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Checksum {
+    pub tick: u64,
+    pub hash: u64,
+}
+
+#[derive(Readable, Writable, Debug, Clone, PartialEq)]
+pub struct ObjectSync {
+    pub tick: u64,
+    pub objects: ObjectMsg,
+}
+
+#[derive(Readable, Writable, Debug, Clone, PartialEq)]
+pub struct Damage {
+    pub id: Uuid,
+    pub object: Option<ObjectIdMsg>,
+    pub secs_alive: u64,
+    pub high_scores: Option<Vec<(String, u64)>>,
+    pub pos: [f32; 2],
+    pub score: usize,
+}
+
+#[derive(Readable, Writable, Debug, Clone, PartialEq)]
+pub struct Score {
+    pub id: Uuid,
+    pub score: usize,
+    pub object: ObjectIdMsg,
+}
+
+#[derive(Readable, Writable, Debug, Clone, PartialEq)]
+pub struct PlayerState {
+    pub pos: [f32; 2],
+    pub target: [f32; 2],
+    pub score: usize,
+    pub name: Option<String>,
+    pub id: Uuid,
+    pub time_alive: u64,
+    pub alive: bool,
+    pub rtt_ms: u16,
+}
+
+// Replaces GameState/GameUpdate when CAP_DELTA is negotiated. Players are
+// addressed by a per-session index, positions are quantized (see `delta`) and
+// only fields that differ from the acknowledged `baseline` snapshot are sent.
+#[derive(Readable, Writable, Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub tick: u64,
+    pub baseline: Option<u64>,
+    pub removed: Vec<u16>,
+    pub entities: Vec<EntityDelta>,
+}
+
+#[derive(Readable, Writable, Debug, Clone, Default, PartialEq)]
+pub struct EntityDelta {
+    pub index: u16,
+    pub id: Option<Uuid>,
+    pub name: Option<Option<String>>,
+    pub pos: Option<[i16; 2]>,
+    pub target: Option<[i16; 2]>,
+    pub score: Option<u16>,
+    pub time_alive: Option<u64>,
+    pub alive: Option<bool>,
+    pub rtt_ms: Option<u16>,
+}
@@ -0,0 +1,332 @@
+// Every message variant is pinned to the exact bytes in tests/fixtures. A
+// failure here means live clients or servers can no longer read the frame;
+// if that is intended, bump PROTOCOL_VERSION and rerun with UPDATE_GOLDEN=1.
+use std::{fmt::Debug, fs, path::PathBuf};
+
+use satrunner_protocol::{
+    delta,
+    messages::{
+        Checksum, ClientMessage, Damage, EntityDelta, Handshake, KindMsg, ModeMsg, NetworkMessage,
+        NewGame, NewPos, ObjectIdMsg, ObjectMsg, ObjectSync, PickUp, PlayerInput, PlayerState,
+        PowerExpired, PowerMsg, ResumeRequest, ResumeState, Score, Snapshot, SyncMessage,
+        PROTOCOL_VERSION,
+    },
+};
+use speedy::{LittleEndian, Readable, Writable};
+use uuid::Uuid;
+
+fn fixture(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(format!("{}.hex", name))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes
+        .chunks(16)
+        .map(|line| {
+            line.iter()
+                .map(|b| format!("{:02x}", b))
+                .collect::<String>()
+                + "\n"
+        })
+        .collect()
+}
+
+fn from_hex(hex: &str) -> Vec<u8> {
+    let digits: Vec<u8> = hex.bytes().filter(|b| b.is_ascii_hexdigit()).collect();
+    digits
+        .chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).unwrap(), 16).unwrap())
+        .collect()
+}
+
+fn golden(name: &str) -> Vec<u8> {
+    let path = fixture(name);
+    let hex = fs::read_to_string(&path)
+        .unwrap_or_else(|_| panic!("missing {}, run with UPDATE_GOLDEN=1", path.display()));
+    from_hex(&hex)
+}
+
+fn check<T>(name: &str, message: T)
+where
+    T: for<'a> Readable<'a, LittleEndian> + Writable<LittleEndian> + PartialEq + Debug,
+{
+    let bytes = message.write_to_vec().unwrap();
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::write(fixture(name), to_hex(&bytes)).unwrap();
+    }
+
+    let golden = golden(name);
+    assert_eq!(
+        to_hex(&bytes),
+        to_hex(&golden),
+        "{} changed on the wire",
+        name
+    );
+    assert_eq!(T::read_from_buffer(&golden).unwrap(), message);
+}
+
+fn id(n: u128) -> Uuid {
+    Uuid::from_u128(0x0123_4567_89ab_cdef_0000_0000_0000_0000 | n)
+}
+
+fn object(kind: KindMsg, tick: u64, index: u8) -> ObjectIdMsg {
+    ObjectIdMsg { tick, index, kind }
+}
+
+fn network_messages() -> Vec<(&'static str, NetworkMessage)> {
+    vec![
+        (
+            "game_update",
+            NetworkMessage::GameUpdate(vec![NewPos {
+                input: [120.5, -40.0],
+                tick: 1234,
+                id: id(1),
+                pos: [100.0, -12.25],
+            }]),
+        ),
+        (
+            "game_state",
+            NetworkMessage::GameState(vec![PlayerState {
+                pos: [1.5, 2.5],
+                target: [-3.0, 4.0],
+                score: 7,
+                name: Some("runner".to_string()),
+                id: id(2),
+                time_alive: 42,
+                alive: true,
+                rtt_ms: 85,
+            }]),
+        ),
+        (
+            "new_game",
+            NetworkMessage::NewGame(NewGame {
+                id: id(3),
+                server_tick: 90_000,
+                rng_seed: 0xdead_beef,
+                mode: ModeMsg::Rush,
+                high_scores: vec![("fast".to_string(), 61), ("slow".to_string(), 300)],
+                removed: vec![
+                    object(KindMsg::Shield, 89_950, 0),
+                    object(KindMsg::Storm, 89_992, 1),
+                    object(KindMsg::Bolt, 89_995, 0),
+                ],
+                resume_token: 0x0102_0304_0506_0708,
+            }),
+        ),
+        ("ping", NetworkMessage::Ping(123_456)),
+        (
+            "damage_player",
+            NetworkMessage::DamagePlayer(Damage {
+                id: id(4),
+                object: Some(object(KindMsg::Storm, 89_992, 1)),
+                secs_alive: 75,
+                high_scores: Some(vec![("fast".to_string(), 61)]),
+                pos: [5.0, -6.0],
+                score: 12,
+            }),
+        ),
+        (
+            "score_update",
+            NetworkMessage::ScoreUpdate(Score {
+                id: id(5),
+                score: 8,
+                object: object(KindMsg::Bolt, 89_990, 0),
+            }),
+        ),
+        (
+            "sync_client",
+            NetworkMessage::SyncClient(SyncMessage {
+                tick_adjustment: -3,
+                server_tick: 90_001,
+            }),
+        ),
+        (
+            "resumed",
+            NetworkMessage::Resumed(Some(ResumeState {
+                id: id(6),
+                pos: [0.5, 0.25],
+                score: 3,
+                secs_alive: 20,
+            })),
+        ),
+        ("pong", NetworkMessage::Pong(654_321)),
+        (
+            "snapshot",
+            NetworkMessage::Snapshot(Snapshot {
+                tick: 90_002,
+                baseline: Some(90_000),
+                removed: vec![4],
+                entities: vec![
+                    EntityDelta {
+                        index: 0,
+                        pos: Some([16, -32]),
+                        ..Default::default()
+                    },
+                    EntityDelta {
+                        index: 2,
+                        id: Some(id(7)),
+                        name: Some(None),
+                        pos: Some([0, 0]),
+                        target: Some([160, 0]),
+                        score: Some(0),
+                        time_alive: Some(0),
+                        alive: Some(true),
+                        rtt_ms: Some(40),
+                    },
+                ],
+            }),
+        ),
+        ("input_ack", NetworkMessage::InputAck(90_003)),
+        (
+            "checksum",
+            NetworkMessage::Checksum(Checksum {
+                tick: 90_000,
+                hash: 0xcbf2_9ce4_8422_2325,
+            }),
+        ),
+        (
+            "objects",
+            NetworkMessage::Objects(ObjectSync {
+                tick: 90_004,
+                objects: ObjectMsg {
+                    objects: vec![(object(KindMsg::Rain, 90_001, 0), [-12.5, 488.0])],
+                },
+            }),
+        ),
+        (
+            "pick_up",
+            NetworkMessage::PickUp(PickUp {
+                id: id(8),
+                power: PowerMsg::Speed,
+                object: Some(object(KindMsg::Speed, 89_910, 0)),
+                until: Some(90_055),
+            }),
+        ),
+        (
+            "power_expired",
+            NetworkMessage::PowerExpired(PowerExpired {
+                id: id(8),
+                power: PowerMsg::Shield,
+                absorbed: Some(object(KindMsg::Rain, 90_002, 0)),
+            }),
+        ),
+        (
+            "welcome",
+            NetworkMessage::Welcome(Handshake {
+                protocol_version: PROTOCOL_VERSION,
+                capabilities: 0b1111,
+            }),
+        ),
+    ]
+}
+
+fn client_messages() -> Vec<(&'static str, ClientMessage)> {
+    let input = |tick| PlayerInput::new([-20.0, 300.5], id(8), tick, true);
+    vec![
+        ("player_input", ClientMessage::PlayerInput(input(90_004))),
+        (
+            "player_name",
+            ClientMessage::PlayerName("rain runner ☔".to_string()),
+        ),
+        (
+            "resume",
+            ClientMessage::Resume(ResumeRequest {
+                id: id(9),
+                resume_token: 0x0807_0605_0403_0201,
+            }),
+        ),
+        ("client_ping", ClientMessage::Ping(777)),
+        ("client_pong", ClientMessage::Pong(888)),
+        ("snapshot_ack", ClientMessage::SnapshotAck(90_002)),
+        ("request_objects", ClientMessage::RequestObjects(90_000)),
+        (
+            "input_batch",
+            ClientMessage::InputBatch(vec![input(90_004), input(90_005)]),
+        ),
+        (
+            "hello",
+            ClientMessage::Hello(Handshake {
+                protocol_version: PROTOCOL_VERSION,
+                capabilities: 0b1111,
+            }),
+        ),
+    ]
+}
+
+#[test]
+fn network_messages_match_golden_bytes() {
+    for (name, message) in network_messages() {
+        check(name, message);
+    }
+}
+
+#[test]
+fn client_messages_match_golden_bytes() {
+    for (name, message) in client_messages() {
+        check(name, message);
+    }
+}
+
+// the handshake must stay readable by every client ever shipped
+#[test]
+fn handshake_layout_is_frozen() {
+    assert_eq!(
+        golden("welcome"),
+        [
+            &[0xff, 0xff, 0, 0][..],
+            &PROTOCOL_VERSION.to_le_bytes(),
+            &[0x0f, 0, 0, 0]
+        ]
+        .concat()
+    );
+    assert_eq!(golden("hello")[..4], [0xff, 0xff, 0, 0]);
+}
+
+#[test]
+fn truncated_frames_are_rejected() {
+    for (name, _) in network_messages() {
+        let frame = golden(name);
+        for len in 0..frame.len() {
+            assert!(
+                NetworkMessage::read_from_buffer(&frame[..len]).is_err(),
+                "{} truncated to {} bytes decoded",
+                name,
+                len
+            );
+        }
+    }
+    for (name, _) in client_messages() {
+        let frame = golden(name);
+        for len in 0..frame.len() {
+            assert!(ClientMessage::read_from_buffer(&frame[..len]).is_err());
+        }
+    }
+}
+
+// decoding garbage may fail but must never panic, and a corrupted snapshot
+// must not take down delta decoding either
+#[test]
+fn corrupted_frames_do_not_panic() {
+    let names = network_messages()
+        .into_iter()
+        .map(|(name, _)| name)
+        .chain(client_messages().into_iter().map(|(name, _)| name));
+
+    for name in names {
+        let frame = golden(name);
+        for index in 0..frame.len() {
+            for flip in [0x01, 0x80, 0xff] {
+                let mut corrupted = frame.clone();
+                corrupted[index] ^= flip;
+                let _ = ClientMessage::read_from_buffer(&corrupted);
+                if let Ok(NetworkMessage::Snapshot(snapshot)) =
+                    NetworkMessage::read_from_buffer(&corrupted)
+                {
+                    let _ = delta::apply(Some(&delta::Frame::new()), &snapshot);
+                }
+            }
+        }
+    }
+}
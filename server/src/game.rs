@@ -0,0 +1,1044 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+
+use log::info;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use satrunner_protocol::{
+    delta::{self, EntityState, Frame},
+    messages::{
+        Checksum, ClientMessage, Damage, Handshake, NetworkMessage, NewGame, NewPos, ObjectMsg,
+        ObjectSync, PickUp, PlayerInput, PlayerState, PowerExpired, ResumeState, Score,
+        SyncMessage, CAP_CHECKSUM, CAP_DELTA, CAP_INPUT_BATCH, CAP_PROBE, CAP_RESUME, CAP_RTT,
+        PROTOCOL_VERSION,
+    },
+};
+use satrunner_sim::{
+    checksum, step_player, ticks_to_secs, Effect, Field, GameMode, Grid, ObjectId, Power, Powers,
+    CHECKSUM_INTERVAL,
+};
+use speedy::Readable;
+use uuid::{Builder, Uuid};
+
+pub const WINNING_SCORE: usize = 21;
+pub const HIGH_SCORES: usize = 5;
+pub const MAX_NAME_LEN: usize = 32;
+
+pub const SERVER_CAPABILITIES: u32 =
+    CAP_RESUME | CAP_RTT | CAP_DELTA | CAP_INPUT_BATCH | CAP_CHECKSUM | CAP_PROBE;
+
+// all in ticks
+pub const RESUME_WINDOW: u64 = 600;
+pub const STATE_INTERVAL: u64 = 10;
+pub const PING_INTERVAL: u64 = 10;
+pub const SYNC_INTERVAL: u64 = 10;
+pub const SNAPSHOT_HISTORY: usize = 32;
+
+// inputs should arrive this many ticks before the server simulates them,
+// clients ease small corrections in so the window can be tight
+pub const TARGET_LEAD: i64 = 2;
+pub const MIN_LEAD: i64 = 1;
+pub const MAX_LEAD: i64 = 3;
+
+pub type ConnId = u64;
+
+#[derive(Clone)]
+struct Runner {
+    id: Uuid,
+    name: Option<String>,
+    pos: [f32; 2],
+    target: [f32; 2],
+    score: usize,
+    alive: bool,
+    spawn_tick: u64,
+    secs_alive: u64,
+    powers: Powers,
+}
+
+impl Runner {
+    fn new(id: Uuid) -> Self {
+        Self {
+            id,
+            name: None,
+            pos: [0.0, 0.0],
+            target: [0.0, 0.0],
+            score: 0,
+            alive: false,
+            spawn_tick: 0,
+            secs_alive: 0,
+            powers: Powers::default(),
+        }
+    }
+
+    fn time_alive(&self, tick: u64) -> u64 {
+        if self.alive {
+            ticks_to_secs(tick - self.spawn_tick)
+        } else {
+            self.secs_alive
+        }
+    }
+
+    fn apply_input(&mut self, tick: u64) {
+        self.pos = step_player(self.pos, self.target, self.powers.boost(tick));
+    }
+
+    // everything a client needs to know about the powers this runner holds
+    fn pick_ups(&self, tick: u64) -> Vec<PickUp> {
+        Power::ALL
+            .into_iter()
+            .filter(|&power| self.powers.active(power, tick))
+            .map(|power| PickUp {
+                id: self.id,
+                power: power.into(),
+                object: None,
+                until: self.powers.remaining(power, tick).map(|left| tick + left),
+            })
+            .collect()
+    }
+}
+
+struct Connection {
+    handshake: Option<Handshake>,
+    runner: Runner,
+    index: u16,
+    resume_token: u64,
+    inputs: BTreeMap<u64, [f32; 2]>,
+    last_applied: Option<u64>,
+    last_acked: Option<u64>,
+    lead: Option<i64>,
+    last_sync: u64,
+    rtt_ms: u16,
+    snapshot_ack: Option<u64>,
+    sent_frames: VecDeque<(u64, Frame)>,
+}
+
+impl Connection {
+    fn supports(&self, capability: u32) -> bool {
+        self.handshake.is_some_and(|hello| {
+            hello.capabilities & SERVER_CAPABILITIES & capability == capability
+        })
+    }
+}
+
+struct Parked {
+    runner: Runner,
+    resume_token: u64,
+    expires: u64,
+}
+
+pub struct Game {
+    pub tick: u64,
+    pub rng_seed: u64,
+    rng: ChaCha8Rng,
+    connections: BTreeMap<ConnId, Connection>,
+    parked: HashMap<Uuid, Parked>,
+    free_indices: Vec<u16>,
+    next_index: u16,
+    field: Field,
+    // where the runners still running are, rebuilt for each tick's collisions
+    runners: Grid<ConnId>,
+    // taken off the field while they would still be falling, what a late
+    // joiner needs on top of the seed to rebuild the field
+    removed: BTreeSet<ObjectId>,
+    high_scores: Vec<(String, u64)>,
+    roster_changed: bool,
+    outbox: Vec<(ConnId, NetworkMessage)>,
+    closing: Vec<ConnId>,
+}
+
+impl Game {
+    // `rng_seed` drives the objects and is shared with every client, ids and
+    // resume tokens come from the separate `secret` so they can't be guessed
+    pub fn new(rng_seed: u64, secret: u64) -> Self {
+        Self {
+            tick: 0,
+            rng_seed,
+            rng: ChaCha8Rng::seed_from_u64(secret),
+            connections: BTreeMap::new(),
+            parked: HashMap::new(),
+            free_indices: Vec::new(),
+            next_index: 0,
+            field: Field::new(),
+            runners: Grid::new(),
+            removed: BTreeSet::new(),
+            high_scores: Vec::new(),
+            roster_changed: false,
+            outbox: Vec::new(),
+            closing: Vec::new(),
+        }
+    }
+
+    // the objects fall by `mode`'s difficulty curve, a fresh game is classic
+    pub fn with_mode(mut self, mode: GameMode) -> Self {
+        self.field = Field::with_mode(mode);
+        self
+    }
+
+    pub fn connect(&mut self, conn: ConnId) {
+        let id = Builder::from_random_bytes(self.rng.gen()).into_uuid();
+        let index = self.free_indices.pop().unwrap_or_else(|| {
+            self.next_index += 1;
+            self.next_index - 1
+        });
+
+        self.connections.insert(
+            conn,
+            Connection {
+                handshake: None,
+                runner: Runner::new(id),
+                index,
+                resume_token: self.rng.gen(),
+                inputs: BTreeMap::new(),
+                last_applied: None,
+                last_acked: None,
+                lead: None,
+                last_sync: 0,
+                rtt_ms: 0,
+                snapshot_ack: None,
+                sent_frames: VecDeque::new(),
+            },
+        );
+    }
+
+    pub fn disconnect(&mut self, conn: ConnId) {
+        let Some(connection) = self.connections.remove(&conn) else {
+            return;
+        };
+        self.free_indices.push(connection.index);
+        self.roster_changed |= connection.runner.name.is_some();
+
+        if connection.runner.alive && connection.supports(CAP_RESUME) {
+            self.parked.insert(
+                connection.runner.id,
+                Parked {
+                    runner: connection.runner,
+                    resume_token: connection.resume_token,
+                    expires: self.tick + RESUME_WINDOW,
+                },
+            );
+        }
+    }
+
+    pub fn receive(&mut self, conn: ConnId, frame: &[u8], now_ms: u64) {
+        match ClientMessage::read_from_buffer(frame) {
+            Ok(message) => self.handle(conn, message, now_ms),
+            Err(e) => info!(
+                "undecodable frame from {} ({} bytes): {}",
+                conn,
+                frame.len(),
+                e
+            ),
+        }
+    }
+
+    pub fn handle(&mut self, conn: ConnId, message: ClientMessage, now_ms: u64) {
+        let tick = self.tick;
+        let Some(connection) = self.connections.get_mut(&conn) else {
+            return;
+        };
+
+        // nothing counts until the client has said which protocol it speaks
+        if connection.handshake.is_none() {
+            if let ClientMessage::Hello(hello) = message {
+                self.hello(conn, hello);
+            }
+            return;
+        }
+
+        match message {
+            ClientMessage::Hello(_) => {}
+            ClientMessage::PlayerName(name) => {
+                let runner = &mut connection.runner;
+                runner.name = Some(name.trim().chars().take(MAX_NAME_LEN).collect());
+                // a resumed runner keeps going, otherwise this is (re)spawning
+                if !runner.alive {
+                    *runner = Runner {
+                        name: runner.name.take(),
+                        alive: true,
+                        spawn_tick: tick,
+                        ..Runner::new(runner.id)
+                    };
+                }
+                self.roster_changed = true;
+            }
+            ClientMessage::PlayerInput(input) => note_input(connection, input, tick),
+            ClientMessage::InputBatch(inputs) => {
+                for input in inputs {
+                    note_input(connection, input, tick);
+                }
+            }
+            ClientMessage::Resume(request) => {
+                let parked = self
+                    .parked
+                    .remove(&request.id)
+                    .filter(|parked| parked.resume_token == request.resume_token);
+
+                let resumed = parked.map(|parked| {
+                    connection.runner = parked.runner;
+                    connection.resume_token = parked.resume_token;
+                    connection.inputs.clear();
+                    ResumeState {
+                        id: connection.runner.id,
+                        pos: connection.runner.pos,
+                        score: connection.runner.score,
+                        secs_alive: connection.runner.time_alive(tick),
+                    }
+                });
+                let pick_ups = match resumed {
+                    Some(_) => connection.runner.pick_ups(tick),
+                    None => Vec::new(),
+                };
+                self.roster_changed = true;
+                self.send(conn, NetworkMessage::Resumed(resumed));
+                for pick_up in pick_ups {
+                    self.send(conn, NetworkMessage::PickUp(pick_up));
+                }
+            }
+            ClientMessage::Ping(timestamp) => self.send(conn, NetworkMessage::Pong(timestamp)),
+            ClientMessage::Pong(timestamp) => {
+                connection.rtt_ms = now_ms.saturating_sub(timestamp).min(u16::MAX as u64) as u16;
+            }
+            ClientMessage::SnapshotAck(tick) => {
+                connection.snapshot_ack = connection.snapshot_ack.max(Some(tick));
+            }
+            ClientMessage::RequestObjects(desync_tick) => {
+                info!("connection {} desynced at tick {}", conn, desync_tick);
+                // objects are as of the last simulated tick
+                let sync = ObjectSync {
+                    tick: tick.saturating_sub(1),
+                    objects: self.object_msg(),
+                };
+                self.send(conn, NetworkMessage::Objects(sync));
+            }
+        }
+    }
+
+    fn hello(&mut self, conn: ConnId, hello: Handshake) {
+        self.send(
+            conn,
+            NetworkMessage::Welcome(Handshake {
+                protocol_version: PROTOCOL_VERSION,
+                capabilities: SERVER_CAPABILITIES,
+            }),
+        );
+
+        // a probe only wanted the Welcome, it gets no game or session
+        if hello.protocol_version != PROTOCOL_VERSION || hello.capabilities & CAP_PROBE != 0 {
+            self.closing.push(conn);
+            return;
+        }
+
+        let removed = self.removed.iter().map(|&id| id.into()).collect();
+        let Some(connection) = self.connections.get_mut(&conn) else {
+            return;
+        };
+        connection.handshake = Some(hello);
+        connection.last_sync = self.tick;
+
+        let new_game = NetworkMessage::NewGame(NewGame {
+            id: connection.runner.id,
+            server_tick: self.tick,
+            rng_seed: self.rng_seed,
+            mode: self.field.mode().into(),
+            high_scores: self.high_scores.clone(),
+            removed,
+            resume_token: connection.resume_token,
+        });
+        self.send(conn, new_game);
+    }
+
+    // Runs one tick: inputs, movement, objects and collisions, then tells every
+    // client about the state at the start of the next tick.
+    pub fn step(&mut self, now_ms: u64) {
+        let tick = self.tick;
+        self.expire_powers(tick);
+
+        for connection in self.connections.values_mut() {
+            let due: Vec<_> = connection.inputs.range(..=tick).map(|(t, _)| *t).collect();
+            for input_tick in due {
+                let target = connection.inputs.remove(&input_tick).unwrap();
+                connection.last_applied = connection.last_applied.max(Some(input_tick));
+                if connection.runner.alive {
+                    connection.runner.target = target;
+                }
+            }
+            if connection.runner.alive {
+                connection.runner.apply_input(tick);
+            }
+        }
+
+        self.spawn_objects(tick);
+        self.collide(tick);
+        if tick.is_multiple_of(CHECKSUM_INTERVAL) {
+            self.send_checksums(tick);
+        }
+
+        self.tick += 1;
+        self.broadcast_state();
+        self.sync_clients(now_ms);
+
+        let tick = self.tick;
+        self.parked.retain(|_, parked| parked.expires > tick);
+    }
+
+    fn expire_powers(&mut self, tick: u64) {
+        let mut events = Vec::new();
+        for connection in self.connections.values_mut() {
+            let runner = &mut connection.runner;
+            for power in runner.powers.expire(tick) {
+                events.push(NetworkMessage::PowerExpired(PowerExpired {
+                    id: runner.id,
+                    power: power.into(),
+                    absorbed: None,
+                }));
+            }
+        }
+
+        for event in events {
+            self.broadcast(event);
+        }
+    }
+
+    fn spawn_objects(&mut self, tick: u64) {
+        self.field.step(self.rng_seed, tick);
+        // anything this old would have fallen off by now anyway
+        self.removed
+            .retain(|id| id.tick + id.kind.def().lifetime() > tick);
+    }
+
+    fn collide(&mut self, tick: u64) {
+        let mut events = Vec::new();
+
+        for connection in self.connections.values_mut() {
+            let runner = &mut connection.runner;
+            if !runner.alive {
+                continue;
+            }
+
+            while let Some((power_up, power)) = self.field.take_power_up(runner.pos) {
+                self.removed.insert(power_up.id);
+                events.push(NetworkMessage::PickUp(PickUp {
+                    id: runner.id,
+                    power: power.into(),
+                    object: Some(power_up.id.into()),
+                    until: runner.powers.pick_up(power, tick),
+                }));
+            }
+
+            if let Some(hit) = self.field.take_damage(runner.pos, runner.score) {
+                self.removed.insert(hit.id);
+                if runner.powers.absorb() {
+                    events.push(NetworkMessage::PowerExpired(PowerExpired {
+                        id: runner.id,
+                        power: Power::Shield.into(),
+                        absorbed: Some(hit.id.into()),
+                    }));
+                } else {
+                    runner.alive = false;
+                    runner.secs_alive = ticks_to_secs(tick - runner.spawn_tick);
+                    events.push(NetworkMessage::DamagePlayer(Damage {
+                        id: runner.id,
+                        object: Some(hit.id.into()),
+                        secs_alive: runner.secs_alive,
+                        high_scores: None,
+                        pos: runner.pos,
+                        score: runner.score,
+                    }));
+                    runner.pos = [0.0, 0.0];
+                    runner.target = [0.0, 0.0];
+                }
+            }
+        }
+
+        // a bolt in reach of several magnets goes to the nearest runner
+        self.runners.rebuild(
+            self.connections
+                .iter()
+                .filter(|(_, connection)| connection.runner.alive)
+                .map(|(&conn, connection)| (conn, connection.runner.pos)),
+        );
+        let connections = &self.connections;
+        let claims = self.field.claims(Effect::Score, &self.runners, |conn| {
+            connections[&conn].runner.powers.reach(tick)
+        });
+
+        for (conn, id) in claims {
+            let runner = &mut self.connections.get_mut(&conn).unwrap().runner;
+            // whoever finished on an earlier bolt leaves the rest where they are
+            if !runner.alive {
+                continue;
+            }
+            self.field.remove(id);
+            self.removed.insert(id);
+            runner.score += 1;
+            events.push(NetworkMessage::ScoreUpdate(Score {
+                id: runner.id,
+                score: runner.score,
+                object: id.into(),
+            }));
+
+            if runner.score >= WINNING_SCORE {
+                // finishing ends the run the same way dying does
+                runner.alive = false;
+                runner.secs_alive = ticks_to_secs(tick - runner.spawn_tick);
+                let name = runner.name.clone().unwrap_or_default();
+                let high_scores = record_high_score(&mut self.high_scores, name, runner.secs_alive)
+                    .then(|| self.high_scores.clone());
+
+                events.push(NetworkMessage::DamagePlayer(Damage {
+                    id: runner.id,
+                    object: Some(id.into()),
+                    secs_alive: runner.secs_alive,
+                    high_scores,
+                    pos: runner.pos,
+                    score: runner.score,
+                }));
+                runner.pos = [0.0, 0.0];
+                runner.target = [0.0, 0.0];
+            }
+        }
+
+        for event in events {
+            self.broadcast(event);
+        }
+    }
+
+    fn object_msg(&self) -> ObjectMsg {
+        ObjectMsg::from_field(&self.field)
+    }
+
+    fn send_checksums(&mut self, tick: u64) {
+        let hash = checksum(tick, &self.field);
+        for (conn, connection) in self.connections.iter() {
+            if connection.supports(CAP_CHECKSUM) {
+                self.outbox
+                    .push((*conn, NetworkMessage::Checksum(Checksum { tick, hash })));
+            }
+        }
+    }
+
+    fn broadcast_state(&mut self) {
+        let tick = self.tick;
+        let frame: Frame = self
+            .connections
+            .values()
+            .filter(|c| c.handshake.is_some() && c.runner.name.is_some())
+            .map(|c| {
+                (
+                    c.index,
+                    EntityState::from_player_state(&player_state(c, tick)),
+                )
+            })
+            .collect();
+        let send_state = self.roster_changed || tick.is_multiple_of(STATE_INTERVAL);
+        self.roster_changed = false;
+
+        for (conn, connection) in self.connections.iter_mut() {
+            if connection.handshake.is_none() {
+                continue;
+            }
+
+            if connection.supports(CAP_DELTA) {
+                let baseline = connection.snapshot_ack.and_then(|ack| {
+                    connection
+                        .sent_frames
+                        .iter()
+                        .find(|(sent, _)| *sent == ack)
+                        .map(|(sent, frame)| (*sent, frame))
+                });
+                let snapshot = delta::encode(tick, baseline, &frame);
+                self.outbox
+                    .push((*conn, NetworkMessage::Snapshot(snapshot)));
+
+                connection.sent_frames.push_back((tick, frame.clone()));
+                while connection.sent_frames.len() > SNAPSHOT_HISTORY {
+                    connection.sent_frames.pop_front();
+                }
+            } else {
+                if send_state {
+                    let players = frame.values().map(|p| p.to_player_state()).collect();
+                    self.outbox
+                        .push((*conn, NetworkMessage::GameState(players)));
+                }
+                let positions = frame
+                    .values()
+                    .filter(|p| p.alive)
+                    .map(|p| NewPos {
+                        input: delta::dequantize(p.target),
+                        tick,
+                        id: p.id,
+                        pos: delta::dequantize(p.pos),
+                    })
+                    .collect();
+                self.outbox
+                    .push((*conn, NetworkMessage::GameUpdate(positions)));
+            }
+        }
+    }
+
+    fn sync_clients(&mut self, now_ms: u64) {
+        let tick = self.tick;
+
+        for (conn, connection) in self.connections.iter_mut() {
+            if connection.handshake.is_none() {
+                continue;
+            }
+
+            if connection.supports(CAP_INPUT_BATCH)
+                && connection.last_applied != connection.last_acked
+            {
+                if let Some(applied) = connection.last_applied {
+                    self.outbox.push((*conn, NetworkMessage::InputAck(applied)));
+                }
+                connection.last_acked = connection.last_applied;
+            }
+
+            if connection.supports(CAP_RTT) && tick.is_multiple_of(PING_INTERVAL) {
+                self.outbox.push((*conn, NetworkMessage::Ping(now_ms)));
+            }
+
+            if tick - connection.last_sync >= SYNC_INTERVAL {
+                if let Some(lead) = connection.lead.take() {
+                    if !(MIN_LEAD..=MAX_LEAD).contains(&lead) {
+                        connection.last_sync = tick;
+                        self.outbox.push((
+                            *conn,
+                            NetworkMessage::SyncClient(SyncMessage {
+                                tick_adjustment: lead - TARGET_LEAD,
+                                server_tick: tick,
+                            }),
+                        ));
+                    }
+                }
+            }
+        }
+    }
+
+    fn send(&mut self, conn: ConnId, message: NetworkMessage) {
+        self.outbox.push((conn, message));
+    }
+
+    fn broadcast(&mut self, message: NetworkMessage) {
+        for (conn, connection) in self.connections.iter() {
+            if connection.handshake.is_some() {
+                self.outbox.push((*conn, message.clone()));
+            }
+        }
+    }
+
+    pub fn drain_outbox(&mut self) -> Vec<(ConnId, NetworkMessage)> {
+        std::mem::take(&mut self.outbox)
+    }
+
+    // connections that must be dropped once their outbox has been flushed
+    pub fn take_closing(&mut self) -> Vec<ConnId> {
+        let closing = std::mem::take(&mut self.closing);
+        for conn in &closing {
+            self.disconnect(*conn);
+        }
+        closing
+    }
+
+    pub fn high_scores(&self) -> &[(String, u64)] {
+        &self.high_scores
+    }
+}
+
+fn note_input(connection: &mut Connection, input: PlayerInput, tick: u64) {
+    if input.id != connection.runner.id {
+        return;
+    }
+
+    connection.lead = Some(input.tick as i64 - tick as i64);
+    // redundant copies of inputs that were already simulated
+    if !input.in_game || connection.last_applied.is_some_and(|t| input.tick <= t) {
+        return;
+    }
+    connection.inputs.insert(input.tick, input.target);
+}
+
+fn player_state(connection: &Connection, tick: u64) -> PlayerState {
+    let runner = &connection.runner;
+    PlayerState {
+        pos: runner.pos,
+        target: runner.target,
+        score: runner.score,
+        name: runner.name.clone(),
+        id: runner.id,
+        time_alive: runner.time_alive(tick),
+        alive: runner.alive,
+        rtt_ms: connection.rtt_ms,
+    }
+}
+
+// fastest first, returns whether the table changed
+fn record_high_score(high_scores: &mut Vec<(String, u64)>, name: String, secs: u64) -> bool {
+    let index = high_scores.partition_point(|(_, best)| *best <= secs);
+    if index >= HIGH_SCORES {
+        return false;
+    }
+    high_scores.insert(index, (name, secs));
+    high_scores.truncate(HIGH_SCORES);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use satrunner_protocol::messages::{
+        ClientMessage, Handshake, ModeMsg, NetworkMessage, PickUp, PlayerInput, PowerExpired,
+        PowerMsg, ResumeRequest,
+    };
+
+    use satrunner_sim::{
+        checksum,
+        player::PLAYER_SPEED,
+        powers::{MAGNET_REACH, MAGNET_TICKS},
+        Field, GameMode, Object, ObjectId, ObjectKind, Power, CHECKSUM_INTERVAL,
+    };
+
+    use super::{record_high_score, Game, HIGH_SCORES, WINNING_SCORE};
+
+    fn joined(game: &mut Game, conn: u64) -> NetworkMessage {
+        game.connect(conn);
+        game.handle(conn, ClientMessage::Hello(Handshake::client()), 0);
+        game.handle(conn, ClientMessage::PlayerName("runner".to_string()), 0);
+        let mut outbox = game.drain_outbox().into_iter().map(|(_, m)| m);
+        assert!(matches!(outbox.next(), Some(NetworkMessage::Welcome(_))));
+        outbox.next().unwrap()
+    }
+
+    fn new_game_id(message: &NetworkMessage) -> uuid::Uuid {
+        match message {
+            NetworkMessage::NewGame(new_game) => new_game.id,
+            other => panic!("unexpected message: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn nothing_is_handled_before_hello() {
+        let mut game = Game::new(1, 2);
+        game.connect(0);
+        game.handle(0, ClientMessage::Ping(5), 0);
+        assert!(game.drain_outbox().is_empty());
+    }
+
+    #[test]
+    fn new_games_carry_the_mode() {
+        let mut game = Game::new(1, 2).with_mode(GameMode::Rush);
+        match joined(&mut game, 0) {
+            NetworkMessage::NewGame(new_game) => assert_eq!(new_game.mode, ModeMsg::Rush),
+            other => panic!("unexpected message: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn old_protocol_is_welcomed_then_closed() {
+        let mut game = Game::new(1, 2);
+        game.connect(0);
+        let hello = Handshake {
+            protocol_version: 0,
+            capabilities: 0,
+        };
+        game.handle(0, ClientMessage::Hello(hello), 0);
+
+        let outbox = game.drain_outbox();
+        assert_eq!(outbox.len(), 1);
+        assert!(matches!(outbox[0].1, NetworkMessage::Welcome(_)));
+        assert_eq!(game.take_closing(), vec![0]);
+    }
+
+    #[test]
+    fn probes_are_welcomed_then_closed_without_a_game() {
+        let mut game = Game::new(1, 2);
+        game.connect(0);
+        game.handle(0, ClientMessage::Hello(Handshake::probe()), 0);
+        game.handle(0, ClientMessage::PlayerName("probe".to_string()), 0);
+
+        let outbox = game.drain_outbox();
+        assert_eq!(outbox.len(), 1);
+        assert!(matches!(outbox[0].1, NetworkMessage::Welcome(_)));
+        assert!(game.connections[&0].runner.name.is_none());
+        assert_eq!(game.take_closing(), vec![0]);
+        assert!(game.parked.is_empty());
+    }
+
+    #[test]
+    fn inputs_move_the_player_and_are_acked() {
+        let mut game = Game::new(1, 2);
+        let id = new_game_id(&joined(&mut game, 0));
+
+        let input = PlayerInput::new([100.0, 0.0], id, 0, true);
+        game.handle(0, ClientMessage::InputBatch(vec![input.clone(), input]), 0);
+        game.field.get_mut(ObjectKind::Rain).clear();
+        game.step(0);
+
+        let runner = &game.connections[&0].runner;
+        assert_eq!(runner.pos, [PLAYER_SPEED, 0.0]);
+
+        let acks: Vec<_> = game
+            .drain_outbox()
+            .into_iter()
+            .filter_map(|(_, m)| match m {
+                NetworkMessage::InputAck(tick) => Some(tick),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(acks, vec![0]);
+    }
+
+    #[test]
+    fn rain_kills_and_bolts_score() {
+        let mut game = Game::new(1, 2);
+        joined(&mut game, 0);
+
+        game.field.insert(Object {
+            id: ObjectId::new(ObjectKind::Bolt, 5, 0),
+            pos: [0.0, 10.0],
+        });
+        game.collide(10);
+        assert_eq!(game.connections[&0].runner.score, 1);
+        assert!(game.field.get(ObjectKind::Bolt).is_empty());
+
+        game.field.insert(Object {
+            id: ObjectId::new(ObjectKind::Rain, 6, 0),
+            pos: [5.0, 5.0],
+        });
+        game.collide(10);
+        let runner = &game.connections[&0].runner;
+        assert!(!runner.alive);
+        assert_eq!(runner.secs_alive, 1);
+
+        let damage = game.drain_outbox().into_iter().find_map(|(_, m)| match m {
+            NetworkMessage::DamagePlayer(damage) => Some(damage),
+            _ => None,
+        });
+        assert_eq!(
+            damage.unwrap().object,
+            Some(ObjectId::new(ObjectKind::Rain, 6, 0).into())
+        );
+    }
+
+    #[test]
+    fn checksums_are_sent_and_desyncs_get_the_objects() {
+        let mut game = Game::new(1, 2);
+        joined(&mut game, 0);
+
+        let mut sent = None;
+        for _ in 0..=CHECKSUM_INTERVAL {
+            game.step(0);
+            sent = sent.or(game.drain_outbox().into_iter().find_map(|(_, m)| match m {
+                NetworkMessage::Checksum(sent) => Some(sent),
+                _ => None,
+            }));
+            if sent.is_some() {
+                break;
+            }
+        }
+        let sent = sent.expect("no checksum sent");
+        assert_eq!(sent.tick, game.tick - 1);
+        assert_eq!(sent.hash, checksum(sent.tick, &game.field));
+
+        game.handle(0, ClientMessage::RequestObjects(sent.tick), 0);
+        match game.drain_outbox().pop() {
+            Some((0, NetworkMessage::Objects(sync))) => {
+                assert_eq!(sync.tick, sent.tick);
+                assert_eq!(sync.objects.to_field(game.field.mode()), game.field);
+            }
+            other => panic!("unexpected message: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn object_syncs_carry_every_kind() {
+        let mut game = Game::new(1, 2);
+        for _ in 0..120 {
+            game.step(0);
+        }
+        for kind in ObjectKind::all() {
+            assert!(!game.field.get(kind).is_empty(), "no {:?}", kind);
+        }
+        assert_eq!(game.object_msg().to_field(game.field.mode()), game.field);
+    }
+
+    #[test]
+    fn late_joiners_rebuild_the_field_from_the_seed() {
+        let mut game = Game::new(1, 2).with_mode(GameMode::Rush);
+        joined(&mut game, 0);
+        while game.connections[&0].runner.alive {
+            game.step(0);
+        }
+        assert!(!game.removed.is_empty());
+        game.drain_outbox();
+
+        let new_game = match joined(&mut game, 1) {
+            NetworkMessage::NewGame(new_game) => new_game,
+            other => panic!("unexpected message: {:?}", other),
+        };
+        let removed = new_game.removed.into_iter().map(ObjectId::from).collect();
+        let field = Field::at(
+            new_game.mode.into(),
+            new_game.rng_seed,
+            new_game.server_tick - 1,
+            &removed,
+        );
+        assert_eq!(field, game.field);
+
+        for _ in 0..1000 {
+            game.step(0);
+        }
+        assert!(game.removed.is_empty());
+    }
+
+    #[test]
+    fn shields_absorb_rain_and_magnets_reach_further() {
+        let mut game = Game::new(1, 2);
+        joined(&mut game, 0);
+        let id = game.connections[&0].runner.id;
+
+        game.field.insert(Object {
+            id: ObjectId::new(ObjectKind::Shield, 70, 0),
+            pos: [0.0, 0.0],
+        });
+        game.field.insert(Object {
+            id: ObjectId::new(ObjectKind::Magnet, 110, 0),
+            pos: [0.0, 0.0],
+        });
+        game.field.insert(Object {
+            id: ObjectId::new(ObjectKind::Rain, 111, 0),
+            pos: [0.0, 0.0],
+        });
+        game.field.insert(Object {
+            id: ObjectId::new(ObjectKind::Bolt, 115, 0),
+            pos: [MAGNET_REACH, 0.0],
+        });
+        game.collide(120);
+
+        let runner = &game.connections[&0].runner;
+        assert!(runner.alive);
+        assert_eq!(runner.score, 1);
+        assert!(game.field.is_empty());
+
+        let events: Vec<_> = game.drain_outbox().into_iter().map(|(_, m)| m).collect();
+        assert!(events.contains(&NetworkMessage::PickUp(PickUp {
+            id,
+            power: PowerMsg::Magnet,
+            object: Some(ObjectId::new(ObjectKind::Magnet, 110, 0).into()),
+            until: Some(120 + MAGNET_TICKS),
+        })));
+        assert!(events.contains(&NetworkMessage::PowerExpired(PowerExpired {
+            id,
+            power: PowerMsg::Shield,
+            absorbed: Some(ObjectId::new(ObjectKind::Rain, 111, 0).into()),
+        })));
+
+        game.tick = 120 + MAGNET_TICKS;
+        game.step(0);
+        let expired = game.drain_outbox().into_iter().any(|(_, m)| {
+            m == NetworkMessage::PowerExpired(PowerExpired {
+                id,
+                power: PowerMsg::Magnet,
+                absorbed: None,
+            })
+        });
+        assert!(expired);
+    }
+
+    #[test]
+    fn bolts_in_reach_of_several_magnets_go_to_the_nearest() {
+        let mut game = Game::new(1, 2);
+        for (conn, x) in [(0, 0.0), (1, 150.0)] {
+            joined(&mut game, conn);
+            let runner = &mut game.connections.get_mut(&conn).unwrap().runner;
+            runner.pos = [x, 0.0];
+            runner.powers.pick_up(Power::Magnet, 120);
+        }
+
+        // both reach either bolt, the first is nearer the second runner
+        for (tick, x) in [(5, 100.0), (10, 40.0)] {
+            game.field.insert(Object {
+                id: ObjectId::new(ObjectKind::Bolt, tick, 0),
+                pos: [x, 0.0],
+            });
+        }
+        game.collide(120);
+
+        assert!(game.field.is_empty());
+        assert_eq!(game.connections[&0].runner.score, 1);
+        assert_eq!(game.connections[&1].runner.score, 1);
+        let scores: Vec<_> = game
+            .drain_outbox()
+            .into_iter()
+            .filter_map(|(_, m)| match m {
+                NetworkMessage::ScoreUpdate(score) => Some((score.id, score.object)),
+                _ => None,
+            })
+            .collect();
+        let id = |conn| game.connections[&conn].runner.id;
+        assert!(scores.contains(&(id(1), ObjectId::new(ObjectKind::Bolt, 5, 0).into())));
+        assert!(scores.contains(&(id(0), ObjectId::new(ObjectKind::Bolt, 10, 0).into())));
+    }
+
+    #[test]
+    fn finishing_records_a_high_score() {
+        let mut game = Game::new(1, 2);
+        joined(&mut game, 0);
+        game.connections.get_mut(&0).unwrap().runner.score = WINNING_SCORE - 1;
+
+        game.field.insert(Object {
+            id: ObjectId::new(ObjectKind::Bolt, 5, 0),
+            pos: [0.0, 0.0],
+        });
+        game.collide(300);
+        assert_eq!(game.high_scores(), &[("runner".to_string(), 30)]);
+        assert!(!game.connections[&0].runner.alive);
+    }
+
+    #[test]
+    fn high_scores_keep_the_fastest() {
+        let mut high_scores = Vec::new();
+        for secs in [50, 10, 40, 30, 20, 60] {
+            record_high_score(&mut high_scores, secs.to_string(), secs);
+        }
+        assert_eq!(high_scores.len(), HIGH_SCORES);
+        assert_eq!(high_scores[0].1, 10);
+        assert!(!record_high_score(
+            &mut high_scores,
+            "slow".to_string(),
+            100
+        ));
+    }
+
+    #[test]
+    fn dropped_runner_can_resume() {
+        let mut game = Game::new(1, 2);
+        let (id, resume_token) = match joined(&mut game, 0) {
+            NetworkMessage::NewGame(new_game) => (new_game.id, new_game.resume_token),
+            other => panic!("unexpected message: {:?}", other),
+        };
+        game.connections.get_mut(&0).unwrap().runner.score = 3;
+        game.disconnect(0);
+
+        joined(&mut game, 1);
+        game.handle(
+            1,
+            ClientMessage::Resume(ResumeRequest { id, resume_token }),
+            0,
+        );
+        match game.drain_outbox().pop() {
+            Some((1, NetworkMessage::Resumed(Some(state)))) => {
+                assert_eq!(state.id, id);
+                assert_eq!(state.score, 3);
+            }
+            other => panic!("unexpected message: {:?}", other),
+        }
+
+        game.handle(
+            1,
+            ClientMessage::Resume(ResumeRequest { id, resume_token }),
+            0,
+        );
+        assert!(matches!(
+            game.drain_outbox().pop(),
+            Some((1, NetworkMessage::Resumed(None)))
+        ));
+    }
+}
@@ -0,0 +1,4 @@
+// Reference server for the satrunner protocol. `game` is the authoritative
+// simulation and knows nothing about sockets, `net` feeds it websocket frames.
+pub mod game;
+pub mod net;
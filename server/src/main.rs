@@ -0,0 +1,63 @@
+use log::{LevelFilter, Log, Metadata, Record};
+use satrunner_server::{game::Game, net::serve};
+use satrunner_sim::GameMode;
+use tokio::net::TcpListener;
+
+// what the game and the sockets report ends up on stderr
+struct Stderr;
+
+impl Log for Stderr {
+    fn enabled(&self, _: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        eprintln!("{}: {}", record.level(), record.args());
+    }
+
+    fn flush(&self) {}
+}
+
+#[tokio::main]
+async fn main() -> std::io::Result<()> {
+    log::set_logger(&Stderr).expect("no other logger is set");
+    log::set_max_level(LevelFilter::Info);
+
+    let mut addr = "0.0.0.0:3030".to_string();
+    let mut rng_seed = rand::random();
+    let mut mode = GameMode::default();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--addr" => addr = args.next().unwrap_or(addr),
+            "--seed" => {
+                rng_seed = args
+                    .next()
+                    .and_then(|seed| seed.parse().ok())
+                    .unwrap_or(rng_seed)
+            }
+            "--mode" => match args.next().as_deref() {
+                Some("classic") => mode = GameMode::Classic,
+                Some("rush") => mode = GameMode::Rush,
+                Some("steady") => mode = GameMode::Steady,
+                other => eprintln!("unknown mode {:?}", other),
+            },
+            _ => eprintln!("unknown argument {}", arg),
+        }
+    }
+
+    let listener = TcpListener::bind(&addr).await?;
+    println!(
+        "listening on {} (seed {}, {:?})",
+        listener.local_addr()?,
+        rng_seed,
+        mode
+    );
+
+    serve(
+        listener,
+        Game::new(rng_seed, rand::random()).with_mode(mode),
+    )
+    .await
+}
@@ -0,0 +1,161 @@
+use std::{collections::HashMap, io, time::Duration};
+
+use futures::{SinkExt, StreamExt};
+use log::{info, warn};
+use satrunner_sim::TICK_RATE;
+use speedy::Writable;
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::mpsc::{channel, error::TrySendError, Sender},
+    time::Instant,
+};
+use tokio_tungstenite::{accept_async, tungstenite::Message};
+
+use crate::game::{ConnId, Game};
+
+// frames waiting to go out to one client, a few seconds' worth; a client that
+// falls further behind than this is dropped
+const FRAME_BACKLOG: usize = 256;
+// events from every socket waiting for the game, readers wait when it is full
+const EVENT_BACKLOG: usize = 1024;
+// a failing accept is retried after this, doubling up to the max while it keeps failing
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(10);
+const MAX_ACCEPT_BACKOFF: Duration = Duration::from_secs(1);
+
+enum Event {
+    Connected(ConnId, Sender<Vec<u8>>),
+    Frame(ConnId, Vec<u8>),
+    Disconnected(ConnId),
+}
+
+// Accepts websocket clients on `listener` and runs the game. Every socket gets
+// its own task, the game lives on this one.
+pub async fn serve(listener: TcpListener, mut game: Game) -> io::Result<()> {
+    let (events_tx, mut events) = channel::<Event>(EVENT_BACKLOG);
+    let mut sockets: HashMap<ConnId, Sender<Vec<u8>>> = HashMap::new();
+    tokio::spawn(accept(listener, events_tx));
+
+    let mut interval = tokio::time::interval(Duration::from_secs_f32(TICK_RATE));
+    let start = Instant::now();
+
+    loop {
+        let now_ms = start.elapsed().as_millis() as u64;
+
+        tokio::select! {
+            _ = interval.tick() => game.step(now_ms),
+            Some(event) = events.recv() => match event {
+                Event::Connected(conn, socket) => {
+                    sockets.insert(conn, socket);
+                    game.connect(conn);
+                }
+                Event::Frame(conn, frame) => game.receive(conn, &frame, now_ms),
+                Event::Disconnected(conn) => {
+                    sockets.remove(&conn);
+                    game.disconnect(conn);
+                }
+            },
+        }
+
+        let mut lagging = Vec::new();
+        for (conn, message) in game.drain_outbox() {
+            if let Some(socket) = sockets.get(&conn) {
+                match message.write_to_vec() {
+                    Ok(frame) => {
+                        if let Err(TrySendError::Full(_)) = socket.try_send(frame) {
+                            lagging.push(conn);
+                        }
+                    }
+                    Err(e) => warn!("Error encoding message: {}", e),
+                }
+            }
+        }
+
+        for conn in lagging {
+            if sockets.remove(&conn).is_some() {
+                info!("connection {} fell too far behind, dropping it", conn);
+                game.disconnect(conn);
+            }
+        }
+
+        // dropping the sender lets the writer close the socket after the last frame
+        for conn in game.take_closing() {
+            sockets.remove(&conn);
+        }
+    }
+}
+
+// Runs apart from the game so that waiting out a failing accept doesn't hold
+// up its ticks.
+async fn accept(listener: TcpListener, events: Sender<Event>) {
+    let mut next_conn: ConnId = 0;
+    let mut backoff = ACCEPT_BACKOFF;
+
+    loop {
+        // running out of file descriptors or a client hanging up mid-accept
+        // only affects that client, but retrying at once would just spin
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                warn!("accept failed, retrying in {:?}: {}", backoff, e);
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_ACCEPT_BACKOFF);
+                continue;
+            }
+        };
+        backoff = ACCEPT_BACKOFF;
+        if events.is_closed() {
+            return;
+        }
+        tokio::spawn(connection(stream, next_conn, events.clone()));
+        next_conn += 1;
+    }
+}
+
+async fn connection(stream: TcpStream, conn: ConnId, events: Sender<Event>) {
+    let socket = match accept_async(stream).await {
+        Ok(socket) => socket,
+        Err(e) => {
+            info!("websocket handshake failed: {}", e);
+            return;
+        }
+    };
+    let (mut write, mut read) = socket.split();
+    let (frames_tx, mut frames) = channel::<Vec<u8>>(FRAME_BACKLOG);
+
+    if events
+        .send(Event::Connected(conn, frames_tx))
+        .await
+        .is_err()
+    {
+        return;
+    }
+
+    let writer = async {
+        while let Some(frame) = frames.recv().await {
+            if write.send(Message::Binary(frame)).await.is_err() {
+                break;
+            }
+        }
+        let _ = write.close().await;
+    };
+
+    let reader = async {
+        while let Some(Ok(message)) = read.next().await {
+            let frame = match message {
+                Message::Binary(frame) => frame,
+                Message::Close(_) => break,
+                _ => continue,
+            };
+            if events.send(Event::Frame(conn, frame)).await.is_err() {
+                break;
+            }
+        }
+    };
+
+    tokio::select! {
+        _ = writer => {}
+        _ = reader => {}
+    }
+
+    let _ = events.send(Event::Disconnected(conn)).await;
+}
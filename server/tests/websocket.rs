@@ -0,0 +1,85 @@
+use std::time::Duration;
+
+use futures::{SinkExt, StreamExt};
+use satrunner_protocol::messages::{ClientMessage, Handshake, NetworkMessage, PROTOCOL_VERSION};
+use satrunner_server::{game::Game, net::serve};
+use speedy::{Readable, Writable};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    time::timeout,
+};
+use tokio_tungstenite::{connect_async, tungstenite::Message, MaybeTlsStream, WebSocketStream};
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+async fn send(socket: &mut Socket, message: ClientMessage) {
+    let frame = message.write_to_vec().unwrap();
+    socket.send(Message::Binary(frame)).await.unwrap();
+}
+
+async fn recv(socket: &mut Socket) -> NetworkMessage {
+    loop {
+        let message = timeout(Duration::from_secs(5), socket.next())
+            .await
+            .expect("timed out")
+            .unwrap()
+            .unwrap();
+        if let Message::Binary(frame) = message {
+            return NetworkMessage::read_from_buffer(&frame).unwrap();
+        }
+    }
+}
+
+#[tokio::test]
+async fn client_joins_and_sees_itself() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(serve(listener, Game::new(1, 2)));
+
+    let (mut socket, _) = connect_async(format!("ws://{}/run", addr)).await.unwrap();
+    send(&mut socket, ClientMessage::Hello(Handshake::client())).await;
+
+    match recv(&mut socket).await {
+        NetworkMessage::Welcome(welcome) => assert_eq!(welcome.protocol_version, PROTOCOL_VERSION),
+        other => panic!("unexpected message: {:?}", other),
+    }
+    let id = match recv(&mut socket).await {
+        NetworkMessage::NewGame(new_game) => new_game.id,
+        other => panic!("unexpected message: {:?}", other),
+    };
+
+    send(&mut socket, ClientMessage::PlayerName("runner".to_string())).await;
+    loop {
+        if let NetworkMessage::Snapshot(snapshot) = recv(&mut socket).await {
+            if snapshot.entities.iter().any(|entity| entity.id == Some(id)) {
+                break;
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn old_clients_are_turned_away() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(serve(listener, Game::new(1, 2)));
+
+    let (mut socket, _) = connect_async(format!("ws://{}/run", addr)).await.unwrap();
+    let hello = Handshake {
+        protocol_version: PROTOCOL_VERSION + 1,
+        capabilities: 0,
+    };
+    send(&mut socket, ClientMessage::Hello(hello)).await;
+
+    assert!(matches!(
+        recv(&mut socket).await,
+        NetworkMessage::Welcome(_)
+    ));
+    let closed = timeout(Duration::from_secs(5), socket.next())
+        .await
+        .expect("timed out");
+    assert!(matches!(
+        closed,
+        None | Some(Ok(Message::Close(_))) | Some(Err(_))
+    ));
+}
@@ -0,0 +1,174 @@
+// How the grid holds up against scanning every object, at far more objects
+// than a real field ever has.
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use satrunner_sim::{
+    objects::PLAYER_SIZE, powers::MAGNET_REACH, Effect, Field, Grid, Object, ObjectId, ObjectKind,
+    X_BOUNDS, Y_BOUNDS,
+};
+
+const SIZES: [usize; 3] = [1_000, 10_000, 50_000];
+const PLAYERS: usize = 100;
+const RUNNERS: [usize; 3] = [100, 1_000, 10_000];
+const BOLTS: usize = 200;
+// about what one screen shows
+const VIEW: [f32; 2] = [640.0, 360.0];
+
+fn positions(count: usize, seed: u64) -> Vec<[f32; 2]> {
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    (0..count)
+        .map(|_| {
+            [
+                rng.gen_range(-X_BOUNDS..X_BOUNDS),
+                rng.gen_range(-Y_BOUNDS..Y_BOUNDS),
+            ]
+        })
+        .collect()
+}
+
+fn rebuild(c: &mut Criterion) {
+    let mut group = c.benchmark_group("rebuild");
+    for size in SIZES {
+        let objects = positions(size, 1);
+        let mut grid = Grid::new();
+        group.bench_with_input(BenchmarkId::from_parameter(size), &objects, |b, objects| {
+            b.iter(|| grid.rebuild(objects.iter().enumerate().map(|(i, &pos)| (i, pos))))
+        });
+    }
+    group.finish();
+}
+
+// every player looking for what touches it, the collision check of one tick
+fn touching(c: &mut Criterion) {
+    let reach = PLAYER_SIZE;
+    let players = positions(PLAYERS, 2);
+    let mut group = c.benchmark_group("touching");
+    for size in SIZES {
+        let objects = positions(size, 1);
+        let mut grid = Grid::new();
+        grid.rebuild(objects.iter().enumerate().map(|(i, &pos)| (i, pos)));
+
+        group.bench_with_input(BenchmarkId::new("scan", size), &objects, |b, objects| {
+            b.iter(|| {
+                players
+                    .iter()
+                    .map(|p| {
+                        objects
+                            .iter()
+                            .filter(|o| {
+                                (p[0] - o[0]).abs() <= reach && (p[1] - o[1]).abs() <= reach
+                            })
+                            .count()
+                    })
+                    .sum::<usize>()
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("grid", size), &grid, |b, grid| {
+            b.iter(|| {
+                players
+                    .iter()
+                    .map(|&p| grid.near(p, reach).count())
+                    .sum::<usize>()
+            })
+        });
+    }
+    group.finish();
+}
+
+// what a camera around one player can see
+fn culling(c: &mut Criterion) {
+    let mut group = c.benchmark_group("culling");
+    for size in SIZES {
+        let mut grid = Grid::new();
+        grid.rebuild(positions(size, 1).into_iter().enumerate());
+        let min = [-VIEW[0] / 2.0, -VIEW[1] / 2.0];
+        let max = [VIEW[0] / 2.0, VIEW[1] / 2.0];
+        group.bench_with_input(BenchmarkId::from_parameter(size), &grid, |b, grid| {
+            b.iter(|| grid.within(black_box(min), black_box(max)).count())
+        });
+    }
+    group.finish();
+}
+
+// a whole field, indexing included, with every player taking a hit
+fn take_hit(c: &mut Criterion) {
+    let players = positions(PLAYERS, 2);
+    let mut group = c.benchmark_group("take_hit");
+    for size in SIZES {
+        let mut field = Field::new();
+        field
+            .get_mut(ObjectKind::Rain)
+            .extend(
+                positions(size, 1)
+                    .into_iter()
+                    .enumerate()
+                    .map(|(tick, pos)| Object {
+                        id: ObjectId::new(ObjectKind::Rain, tick as u64, 0),
+                        pos,
+                    }),
+            );
+        group.bench_with_input(BenchmarkId::from_parameter(size), &field, |b, field| {
+            b.iter_batched(
+                || field.clone(),
+                |mut field| {
+                    players
+                        .iter()
+                        .filter_map(|&p| field.take_hit(p, Effect::Damage))
+                        .count()
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+// everyone with a magnet on a field of bolts: indexing the runners and handing
+// each bolt to the nearest one, against every runner taking what it reaches
+fn players(c: &mut Criterion) {
+    let mut field = Field::new();
+    field
+        .get_mut(ObjectKind::Bolt)
+        .extend(
+            positions(BOLTS, 1)
+                .into_iter()
+                .enumerate()
+                .map(|(tick, pos)| Object {
+                    id: ObjectId::new(ObjectKind::Bolt, tick as u64, 0),
+                    pos,
+                }),
+        );
+    field.index();
+
+    let mut group = c.benchmark_group("players");
+    for count in RUNNERS {
+        let runners = positions(count, 2);
+        group.bench_with_input(BenchmarkId::new("scan", count), &runners, |b, runners| {
+            b.iter_batched(
+                || field.clone(),
+                |mut field| {
+                    runners
+                        .iter()
+                        .map(|&p| {
+                            std::iter::from_fn(|| field.take_within(p, Effect::Score, MAGNET_REACH))
+                                .count()
+                        })
+                        .sum::<usize>()
+                },
+                BatchSize::LargeInput,
+            )
+        });
+        let mut grid = Grid::new();
+        group.bench_with_input(BenchmarkId::new("grid", count), &runners, |b, runners| {
+            b.iter(|| {
+                grid.rebuild(runners.iter().enumerate().map(|(i, &pos)| (i, pos)));
+                field.claims(Effect::Score, &grid, |_| MAGNET_REACH).len()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, rebuild, touching, culling, take_hit, players);
+criterion_main!(benches);
@@ -0,0 +1,132 @@
+// How hard the rain is for one runner. The field is shared by everyone on the
+// server, so it always holds the rain of the hardest level; every drop has a
+// threat of its own that says from which level on it counts, and a runner's
+// level follows their score. The drops that count for a runner fall as dense,
+// as fast and as clustered as their level says, the rest pass through them.
+use crate::{
+    objects::{ObjectId, ObjectKind},
+    weather::{mix, unit},
+    X_BOUNDS,
+};
+
+// drops in a cluster land this far either side of its centre
+pub const CLUSTER_SPREAD: f32 = 120.0;
+// a cluster stays in one place for this many ticks
+pub const CLUSTER_TICKS: u64 = 30;
+
+const CLUSTER_SALT: u64 = 0x0063_6c75_7374_6572;
+const THREAT_SALT: u64 = 0x0074_6872_6561_7400;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum GameMode {
+    #[default]
+    Classic,
+    // ramps up fast and starts out hard
+    Rush,
+    // the rain as it always was, never gets any harder
+    Steady,
+}
+
+impl GameMode {
+    pub const ALL: [GameMode; 3] = [GameMode::Classic, GameMode::Rush, GameMode::Steady];
+
+    pub fn curve(self) -> &'static Curve {
+        &CURVES[self as usize]
+    }
+}
+
+// Where each knob starts and where it ends up, the curve moves linearly from
+// one to the other.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Curve {
+    // points from the easiest to the hardest it gets, 0 stays at the easiest
+    pub ramp: usize,
+    // chance that a rain tick drops rain that counts
+    pub density: (f32, f32),
+    // multiplies how fast everything falls
+    pub speed: (f32, f32),
+    // chance that a drop falls in the current cluster instead of anywhere
+    pub cluster: (f32, f32),
+}
+
+// indexed by GameMode
+pub const CURVES: &[Curve] = &[
+    Curve {
+        ramp: 15,
+        density: (0.6, 1.0),
+        speed: (1.0, 1.5),
+        cluster: (0.0, 0.5),
+    },
+    Curve {
+        ramp: 6,
+        density: (0.8, 1.0),
+        speed: (1.2, 1.8),
+        cluster: (0.2, 0.7),
+    },
+    Curve {
+        ramp: 0,
+        density: (1.0, 1.0),
+        speed: (1.0, 1.0),
+        cluster: (0.0, 0.0),
+    },
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Difficulty {
+    pub density: f32,
+    pub speed: f32,
+    pub cluster: f32,
+}
+
+impl Curve {
+    // 0 for a runner with no points, 1 once they have climbed the whole ramp
+    pub fn level(&self, score: usize) -> f32 {
+        if self.ramp == 0 {
+            return 0.0;
+        }
+        (score as f32 / self.ramp as f32).min(1.0)
+    }
+
+    pub fn at(&self, level: f32) -> Difficulty {
+        let lerp = |(from, to): (f32, f32)| from + (to - from) * level;
+        Difficulty {
+            density: lerp(self.density),
+            speed: lerp(self.speed),
+            cluster: lerp(self.cluster),
+        }
+    }
+
+    // The level from which on the object `id` counts, and whose speed and
+    // clustering it falls with. The field drops rain as densely as the hardest
+    // level does, so at any level the share of it that counts is that level's
+    // density over the hardest one's. Everything else counts from the start.
+    pub fn onset(&self, id: ObjectId) -> f32 {
+        let (easiest, hardest) = self.density;
+        if id.kind != ObjectKind::Rain || hardest <= easiest {
+            return 0.0;
+        }
+        ((threat(id) * hardest - easiest) / (hardest - easiest)).clamp(0.0, 1.0)
+    }
+
+    // whether the object `id` can hit a runner with `score` points
+    pub fn counts(&self, id: ObjectId, score: usize) -> bool {
+        self.onset(id) <= self.level(score)
+    }
+}
+
+pub fn difficulty(mode: GameMode, score: usize) -> Difficulty {
+    let curve = mode.curve();
+    curve.at(curve.level(score))
+}
+
+// in [0, 1), the same for the object `id` on every side
+pub fn threat(id: ObjectId) -> f32 {
+    let hash = mix(THREAT_SALT ^ mix(id.tick) ^ ((id.index as u64) << 8 | id.kind as u64));
+    (unit(hash) + 1.0) / 2.0
+}
+
+// where the cluster is on `tick`, kept clear of the edges so it never spills off
+pub fn cluster_centre(rng_seed: u64, tick: u64) -> f32 {
+    let index = tick / CLUSTER_TICKS;
+    unit(mix(rng_seed ^ CLUSTER_SALT ^ mix(index))) * (X_BOUNDS - CLUSTER_SPREAD)
+}
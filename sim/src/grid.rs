@@ -0,0 +1,102 @@
+// A uniform spatial hash over the arena. Everything is bucketed by the cell
+// it sits in, so a query only looks at the cells it overlaps instead of the
+// whole field.
+use crate::{X_BOUNDS, Y_BOUNDS};
+
+// wider than anything touches without a magnet, small enough that a cell
+// rarely holds more than a handful of drops
+pub const CELL_SIZE: f32 = 50.0;
+pub const COLUMNS: usize = (2.0 * X_BOUNDS / CELL_SIZE) as usize;
+pub const ROWS: usize = (2.0 * Y_BOUNDS / CELL_SIZE) as usize;
+
+// Rebuilt from scratch rather than updated in place: everything moves every
+// tick anyway, and a counting sort into one flat list keeps each row of cells
+// next to each other in memory.
+#[derive(Debug, Clone)]
+pub struct Grid<T> {
+    // cell i holds entries[starts[i]..starts[i + 1]]
+    starts: Vec<u32>,
+    entries: Vec<(T, [f32; 2])>,
+    scratch: Vec<(T, [f32; 2])>,
+}
+
+impl<T: Copy> Grid<T> {
+    pub fn new() -> Self {
+        Self {
+            starts: vec![0; COLUMNS * ROWS + 1],
+            entries: Vec::new(),
+            scratch: Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // anything outside the arena goes in the nearest edge cell
+    fn column(x: f32) -> usize {
+        (((x + X_BOUNDS) / CELL_SIZE) as usize).min(COLUMNS - 1)
+    }
+
+    fn row(y: f32) -> usize {
+        (((y + Y_BOUNDS) / CELL_SIZE) as usize).min(ROWS - 1)
+    }
+
+    fn cell(pos: [f32; 2]) -> usize {
+        Self::row(pos[1]) * COLUMNS + Self::column(pos[0])
+    }
+
+    pub fn rebuild(&mut self, items: impl IntoIterator<Item = (T, [f32; 2])>) {
+        self.scratch.clear();
+        self.scratch.extend(items);
+
+        self.starts.fill(0);
+        for &(_, pos) in &self.scratch {
+            self.starts[Self::cell(pos) + 1] += 1;
+        }
+        for cell in 1..self.starts.len() {
+            self.starts[cell] += self.starts[cell - 1];
+        }
+
+        self.entries.clear();
+        self.entries.extend_from_slice(&self.scratch);
+        let mut next = self.starts.clone();
+        for &entry in &self.scratch {
+            let cell = Self::cell(entry.1);
+            self.entries[next[cell] as usize] = entry;
+            next[cell] += 1;
+        }
+    }
+
+    // everything inside the rectangle from `min` to `max`, edges included
+    pub fn within(&self, min: [f32; 2], max: [f32; 2]) -> impl Iterator<Item = (T, [f32; 2])> + '_ {
+        let (first, last) = (Self::column(min[0]), Self::column(max[0]));
+        (Self::row(min[1])..=Self::row(max[1]))
+            .flat_map(move |row| {
+                let start = self.starts[row * COLUMNS + first] as usize;
+                let end = self.starts[row * COLUMNS + last + 1] as usize;
+                self.entries[start..end].iter().copied()
+            })
+            .filter(move |&(_, pos)| {
+                (min[0]..=max[0]).contains(&pos[0]) && (min[1]..=max[1]).contains(&pos[1])
+            })
+    }
+
+    // everything no further than `reach` from `pos` along either axis
+    pub fn near(&self, pos: [f32; 2], reach: f32) -> impl Iterator<Item = (T, [f32; 2])> + '_ {
+        self.within(
+            [pos[0] - reach, pos[1] - reach],
+            [pos[0] + reach, pos[1] + reach],
+        )
+    }
+}
+
+impl<T: Copy> Default for Grid<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
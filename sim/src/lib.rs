@@ -0,0 +1,35 @@
+// Deterministic game rules shared by the client, the server and tests. Every
+// function here must give bit-identical results on every platform, since both
+// sides simulate the same ticks independently.
+pub mod difficulty;
+pub mod grid;
+pub mod objects;
+pub mod player;
+pub mod powers;
+pub mod rollback;
+pub mod weather;
+
+pub use difficulty::{difficulty, Curve, Difficulty, GameMode, CURVES};
+pub use grid::Grid;
+pub use objects::{
+    checksum, spawn, spawn_kinds, step_objects, Effect, Field, Object, ObjectDef, ObjectId,
+    ObjectKind, OBJECTS,
+};
+pub use player::{movement, step_player};
+pub use powers::{Power, Powers};
+pub use rollback::{History, Runner, State};
+
+pub const TICK_RATE: f32 = 1. / 10.;
+// the server sends a checksum of its objects every this many ticks
+pub const CHECKSUM_INTERVAL: u64 = 30;
+
+pub const X_BOUNDS: f32 = 1000.0;
+pub const Y_BOUNDS: f32 = 500.0;
+
+pub fn within_bounds(pos: [f32; 2]) -> bool {
+    pos[0].abs() <= X_BOUNDS && pos[1].abs() <= Y_BOUNDS
+}
+
+pub fn ticks_to_secs(ticks: u64) -> u64 {
+    (ticks as f32 * TICK_RATE) as u64
+}
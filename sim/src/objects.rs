@@ -0,0 +1,615 @@
+use std::{collections::BTreeSet, ops::Range};
+
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+use crate::{
+    difficulty::{cluster_centre, GameMode, CLUSTER_SPREAD},
+    grid::Grid,
+    powers::{Power, MAGNET_REACH},
+    weather::{variation, wind},
+    within_bounds, X_BOUNDS, Y_BOUNDS,
+};
+
+pub const FALL_SPEED: f32 = 3.0;
+pub const STORM_FALL_SPEED: f32 = 5.0;
+// every BOLT_INTERVAL-th tick drops a bolt instead of rain
+pub const BOLT_INTERVAL: u64 = 5;
+pub const PLAYER_SIZE: f32 = 20.0;
+// the rest of a burst lands this far either side of the first
+pub const BURST_SPREAD: f32 = 40.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ObjectKind {
+    Rain,
+    Bolt,
+    Shield,
+    Speed,
+    Magnet,
+    Storm,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Effect {
+    // ends the run
+    Damage,
+    // worth a point
+    Score,
+    PowerUp(Power),
+}
+
+// Everything that makes one kind of falling object different from another.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ObjectDef {
+    pub kind: ObjectKind,
+    pub sprite: &'static str,
+    // multiplied into the sprite's colour
+    pub tint: [f32; 3],
+    pub size: f32,
+    pub fall_speed: f32,
+    // each object falls up to this fraction faster or slower than fall_speed
+    pub speed_variance: f32,
+    // the most an object speeds up by per tick, each gets a share of it
+    pub acceleration: f32,
+    // how much of the wind moves it sideways
+    pub drift: f32,
+    // spawns on every tick that is a multiple of this, alongside anything else
+    // due then; rain only falls on the ticks nothing else is due
+    pub every: u64,
+    // how many spawn together
+    pub burst: u8,
+    pub effect: Effect,
+}
+
+// indexed by ObjectKind
+pub const OBJECTS: &[ObjectDef] = &[
+    ObjectDef {
+        kind: ObjectKind::Rain,
+        sprite: "droplet.png",
+        tint: [1.0, 1.0, 1.0],
+        size: 10.0,
+        fall_speed: FALL_SPEED,
+        speed_variance: 0.3,
+        acceleration: 0.02,
+        drift: 1.0,
+        every: 1,
+        burst: 1,
+        effect: Effect::Damage,
+    },
+    ObjectDef {
+        kind: ObjectKind::Bolt,
+        sprite: "high-voltage.png",
+        tint: [1.0, 1.0, 1.0],
+        size: 10.0,
+        fall_speed: FALL_SPEED,
+        speed_variance: 0.2,
+        acceleration: 0.0,
+        drift: 0.6,
+        every: BOLT_INTERVAL,
+        burst: 1,
+        effect: Effect::Score,
+    },
+    ObjectDef {
+        kind: ObjectKind::Shield,
+        sprite: "umbrella.png",
+        tint: [0.4, 0.8, 1.0],
+        size: 16.0,
+        fall_speed: FALL_SPEED,
+        speed_variance: 0.0,
+        acceleration: 0.0,
+        drift: 0.3,
+        every: 70,
+        burst: 1,
+        effect: Effect::PowerUp(Power::Shield),
+    },
+    ObjectDef {
+        kind: ObjectKind::Speed,
+        sprite: "fast-forward.png",
+        tint: [0.3, 1.0, 0.3],
+        size: 16.0,
+        fall_speed: FALL_SPEED,
+        speed_variance: 0.0,
+        acceleration: 0.0,
+        drift: 0.3,
+        every: 90,
+        burst: 1,
+        effect: Effect::PowerUp(Power::Speed),
+    },
+    ObjectDef {
+        kind: ObjectKind::Magnet,
+        sprite: "magnet.png",
+        tint: [1.0, 0.3, 1.0],
+        size: 16.0,
+        fall_speed: FALL_SPEED,
+        speed_variance: 0.0,
+        acceleration: 0.0,
+        drift: 0.3,
+        every: 110,
+        burst: 1,
+        effect: Effect::PowerUp(Power::Magnet),
+    },
+    // heavy drops that barely care about the wind
+    ObjectDef {
+        kind: ObjectKind::Storm,
+        sprite: "droplet.png",
+        tint: [0.4, 0.5, 1.0],
+        size: 14.0,
+        fall_speed: STORM_FALL_SPEED,
+        speed_variance: 0.2,
+        acceleration: 0.04,
+        drift: 0.4,
+        every: 7,
+        burst: 3,
+        effect: Effect::Damage,
+    },
+];
+
+impl ObjectKind {
+    pub fn all() -> impl Iterator<Item = ObjectKind> {
+        OBJECTS.iter().map(|def| def.kind)
+    }
+
+    pub fn def(self) -> &'static ObjectDef {
+        &OBJECTS[self as usize]
+    }
+}
+
+impl ObjectDef {
+    // player and object overlap, both are squares
+    pub fn hits(&self, player: [f32; 2], object: [f32; 2]) -> bool {
+        self.reaches(player, object, 0.0)
+    }
+
+    // like hits, for a player that reaches `reach` further than its edge
+    pub fn reaches(&self, player: [f32; 2], object: [f32; 2], reach: f32) -> bool {
+        let distance = (PLAYER_SIZE + self.size) / 2.0 + reach;
+        (player[0] - object[0]).abs() < distance && (player[1] - object[1]).abs() < distance
+    }
+
+    // the most ticks one of this kind moves on before it has left the field,
+    // no mode ever slows things down
+    pub fn lifetime(&self) -> u64 {
+        let slowest = self.fall_speed * (1.0 - self.speed_variance);
+        (2.0 * Y_BOUNDS / slowest).ceil() as u64 + 1
+    }
+
+    // the most of this kind that can be on the field at once, no mode ever
+    // spawns more often than every tick
+    pub fn max_alive(&self) -> usize {
+        (self.lifetime() as usize / self.every as usize + 1) * self.burst as usize
+    }
+
+    // how far the object `id` moves on `tick`, as fast as the difficulty is
+    // at the level it starts counting at
+    pub fn velocity(&self, mode: GameMode, rng_seed: u64, id: ObjectId, tick: u64) -> [f32; 2] {
+        let (offset, share) = variation(rng_seed, id.tick, id.index);
+        let age = tick.saturating_sub(id.tick) as f32;
+        let fall = self.fall_speed * (1.0 + self.speed_variance * offset)
+            + self.acceleration * share * age;
+        let curve = mode.curve();
+        [
+            wind(rng_seed, tick) * self.drift,
+            -fall * curve.at(curve.onset(id)).speed,
+        ]
+    }
+
+    pub fn advance(&self, object: &mut Object, mode: GameMode, rng_seed: u64, tick: u64) {
+        let velocity = self.velocity(mode, rng_seed, object.id, tick);
+        object.pos[0] += velocity[0];
+        object.pos[1] += velocity[1];
+    }
+}
+
+// Names one object the same way on every side. A burst spawns several objects
+// on the same tick, `index` tells them apart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ObjectId {
+    pub tick: u64,
+    pub index: u8,
+    pub kind: ObjectKind,
+}
+
+impl ObjectId {
+    pub fn new(kind: ObjectKind, tick: u64, index: u8) -> Self {
+        Self { tick, index, kind }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Object {
+    pub id: ObjectId,
+    pub pos: [f32; 2],
+}
+
+// the kinds that spawn on `tick`, so a power-up or a storm never costs a bolt
+pub fn spawn_kinds(tick: u64) -> Vec<ObjectKind> {
+    let due: Vec<_> = OBJECTS
+        .iter()
+        .filter(|def| def.kind != ObjectKind::Rain && tick.is_multiple_of(def.every))
+        .map(|def| def.kind)
+        .collect();
+    if due.is_empty() {
+        vec![ObjectKind::Rain]
+    } else {
+        due
+    }
+}
+
+// What spawns on `tick`: rain ticks stay dry as often as the hardest density
+// says, a drop may land in the current cluster as often as the level it counts
+// from says and the rest of a burst lands around the first.
+pub fn spawn(mode: GameMode, rng_seed: u64, tick: u64) -> Vec<Object> {
+    let mut rng = ChaCha8Rng::seed_from_u64(rng_seed ^ tick);
+    let curve = mode.curve();
+    let mut spawned = Vec::new();
+
+    for kind in spawn_kinds(tick) {
+        let def = kind.def();
+        let mut x_position: f32 = rng.gen_range(-X_BOUNDS..X_BOUNDS);
+        if kind == ObjectKind::Rain && rng.gen::<f32>() >= curve.density.1 {
+            continue;
+        }
+        let onset = curve.onset(ObjectId::new(kind, tick, 0));
+        if rng.gen::<f32>() < curve.at(onset).cluster {
+            x_position =
+                cluster_centre(rng_seed, tick) + rng.gen_range(-CLUSTER_SPREAD..CLUSTER_SPREAD);
+        }
+
+        for index in 0..def.burst {
+            let x = if index == 0 {
+                x_position
+            } else {
+                let offset = rng.gen_range(-BURST_SPREAD..BURST_SPREAD);
+                (x_position + offset).clamp(-X_BOUNDS, X_BOUNDS)
+            };
+            spawned.push(Object {
+                id: ObjectId::new(kind, tick, index),
+                pos: [x, Y_BOUNDS],
+            });
+        }
+    }
+    spawned
+}
+
+// Advances the objects of one kind by a tick: whatever spawns this tick is
+// added first, then everything moves and what left the field is dropped.
+pub fn step_objects(
+    objects: &mut Vec<Object>,
+    kind: ObjectKind,
+    mode: GameMode,
+    rng_seed: u64,
+    tick: u64,
+) {
+    objects.extend(
+        spawn(mode, rng_seed, tick)
+            .into_iter()
+            .filter(|object| object.id.kind == kind),
+    );
+
+    let def = kind.def();
+    for object in objects.iter_mut() {
+        def.advance(object, mode, rng_seed, tick);
+    }
+
+    objects.retain(|object| within_bounds(object.pos));
+}
+
+// Where `object` ends up after moving on every tick in `ticks`, none if it
+// leaves the field on the way. Moves it exactly like stepping the field would.
+fn trajectory(
+    mut object: Object,
+    mode: GameMode,
+    rng_seed: u64,
+    ticks: Range<u64>,
+) -> Option<Object> {
+    let def = object.id.kind.def();
+    for tick in ticks {
+        def.advance(&mut object, mode, rng_seed, tick);
+        if !within_bounds(object.pos) {
+            return None;
+        }
+    }
+    Some(object)
+}
+
+// Every object on the field, one list per kind kept in id order, and the mode
+// they fall by.
+pub struct Field {
+    objects: Vec<Vec<Object>>,
+    mode: GameMode,
+    // where every object was when last indexed, stale once anything moves
+    grid: Grid<ObjectId>,
+    indexed: bool,
+}
+
+impl Field {
+    pub fn new() -> Self {
+        Self::with_mode(GameMode::default())
+    }
+
+    pub fn with_mode(mode: GameMode) -> Self {
+        Self {
+            objects: vec![Vec::new(); OBJECTS.len()],
+            mode,
+            grid: Grid::new(),
+            indexed: false,
+        }
+    }
+
+    // The field once every tick up to and including `tick` has been stepped,
+    // without the objects in `removed`. Not closed form: every object that
+    // could still be falling is replayed a tick at a time, since only that
+    // matches stepping to the bit. The cost is bounded by the longest
+    // lifetime however late `tick` is.
+    pub fn at(mode: GameMode, rng_seed: u64, tick: u64, removed: &BTreeSet<ObjectId>) -> Self {
+        let mut field = Self::with_mode(mode);
+        field.seek_without(rng_seed, 0..tick + 1, removed);
+        field
+    }
+
+    pub fn mode(&self) -> GameMode {
+        self.mode
+    }
+
+    pub fn get(&self, kind: ObjectKind) -> &Vec<Object> {
+        &self.objects[kind as usize]
+    }
+
+    // anything may change through here, so the grid is rebuilt before the
+    // next query
+    pub fn get_mut(&mut self, kind: ObjectKind) -> &mut Vec<Object> {
+        self.indexed = false;
+        &mut self.objects[kind as usize]
+    }
+
+    fn find(&self, id: ObjectId) -> Result<usize, usize> {
+        self.get(id.kind)
+            .binary_search_by_key(&id, |object| object.id)
+    }
+
+    pub fn contains(&self, id: ObjectId) -> bool {
+        self.find(id).is_ok()
+    }
+
+    // puts an object back where it belongs in id order
+    pub fn insert(&mut self, object: Object) {
+        let index = self.find(object.id).unwrap_or_else(|index| index);
+        self.get_mut(object.id.kind).insert(index, object);
+    }
+
+    // Sorts and reindexes whatever was changed by hand. Removing objects
+    // doesn't need it, the grid skips anything it can no longer find.
+    pub fn index(&mut self) {
+        if self.indexed {
+            return;
+        }
+        for objects in &mut self.objects {
+            objects.sort_by_key(|object| object.id);
+        }
+        self.grid.rebuild(
+            self.objects
+                .iter()
+                .flatten()
+                .map(|object| (object.id, object.pos)),
+        );
+        self.indexed = true;
+    }
+
+    // the objects inside the rectangle from `min` to `max`
+    pub fn within(&mut self, min: [f32; 2], max: [f32; 2]) -> Vec<Object> {
+        self.index();
+        self.grid
+            .within(min, max)
+            .filter_map(|(id, _)| {
+                let index = self.find(id).ok()?;
+                Some(self.get(id.kind)[index])
+            })
+            .collect()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Object> {
+        self.objects.iter().flatten()
+    }
+
+    pub fn len(&self) -> usize {
+        self.objects.iter().map(Vec::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn step(&mut self, rng_seed: u64, tick: u64) {
+        for kind in ObjectKind::all() {
+            let mode = self.mode;
+            step_objects(self.get_mut(kind), kind, mode, rng_seed, tick);
+        }
+        self.index();
+    }
+
+    // Steps every tick in `ticks` at once, for catching up, by replaying each
+    // object on its own rather than the whole field tick by tick. Whatever was
+    // taken off the field stays off, and a gap longer than anything lives for
+    // costs no more than one that long. `ticks` must all be after the last one
+    // stepped, or their objects spawn twice.
+    pub fn seek(&mut self, rng_seed: u64, ticks: Range<u64>) {
+        self.seek_without(rng_seed, ticks, &BTreeSet::new());
+    }
+
+    fn seek_without(&mut self, rng_seed: u64, ticks: Range<u64>, removed: &BTreeSet<ObjectId>) {
+        if ticks.is_empty() {
+            return;
+        }
+        let mode = self.mode;
+        for objects in &mut self.objects {
+            *objects = objects
+                .iter()
+                .filter_map(|&object| trajectory(object, mode, rng_seed, ticks.clone()))
+                .collect();
+        }
+
+        // anything spawned before this is gone again by the end
+        let longest = OBJECTS.iter().map(ObjectDef::lifetime).max().unwrap_or(0);
+        let first = ticks.start.max(ticks.end.saturating_sub(longest));
+        for tick in first..ticks.end {
+            for object in spawn(mode, rng_seed, tick) {
+                if removed.contains(&object.id) {
+                    continue;
+                }
+                if let Some(object) = trajectory(object, mode, rng_seed, tick..ticks.end) {
+                    self.objects[object.id.kind as usize].push(object);
+                }
+            }
+        }
+
+        self.indexed = false;
+        self.index();
+    }
+
+    // the first power-up a player at `pos` touches
+    pub fn take_power_up(&mut self, pos: [f32; 2]) -> Option<(Object, Power)> {
+        Power::ALL.into_iter().find_map(|power| {
+            self.take_hit(pos, Effect::PowerUp(power))
+                .map(|object| (object, power))
+        })
+    }
+
+    pub fn remove(&mut self, id: ObjectId) -> Option<Object> {
+        self.index();
+        let index = self.find(id).ok()?;
+        Some(self.objects[id.kind as usize].remove(index))
+    }
+
+    // takes the first object with `effect` that a player at `pos` touches
+    pub fn take_hit(&mut self, pos: [f32; 2], effect: Effect) -> Option<Object> {
+        self.take_within(pos, effect, 0.0)
+    }
+
+    // takes the first damaging object that a runner with `score` points at
+    // `pos` touches and that counts at their level
+    pub fn take_damage(&mut self, pos: [f32; 2], score: usize) -> Option<Object> {
+        let curve = self.mode.curve();
+        self.take_first(pos, Effect::Damage, 0.0, |id| curve.counts(id, score))
+    }
+
+    pub fn take_within(&mut self, pos: [f32; 2], effect: Effect, reach: f32) -> Option<Object> {
+        self.take_first(pos, effect, reach, |_| true)
+    }
+
+    // Who each object with `effect` that a runner reaches goes to: the nearest
+    // runner that reaches it, the lowest key of those on a tie, lowest object
+    // id first. `runners` is where everyone still running is and `reach` how
+    // far past their edge each of them reaches.
+    pub fn claims<K: Copy + Ord>(
+        &self,
+        effect: Effect,
+        runners: &Grid<K>,
+        reach: impl Fn(K) -> f32,
+    ) -> Vec<(K, ObjectId)> {
+        let mut claims = Vec::new();
+        for def in OBJECTS.iter().filter(|def| def.effect == effect) {
+            // nobody reaches further than a magnet
+            let furthest = (PLAYER_SIZE + def.size) / 2.0 + MAGNET_REACH;
+            for object in self.get(def.kind) {
+                let nearest = runners
+                    .near(object.pos, furthest)
+                    .filter(|&(key, pos)| def.reaches(pos, object.pos, reach(key)))
+                    .map(|(key, pos)| {
+                        let (dx, dy) = (pos[0] - object.pos[0], pos[1] - object.pos[1]);
+                        (dx * dx + dy * dy, key)
+                    })
+                    .min_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+                if let Some((_, key)) = nearest {
+                    claims.push((key, object.id));
+                }
+            }
+        }
+        claims.sort_by_key(|&(_, id)| id);
+        claims
+    }
+
+    fn take_first(
+        &mut self,
+        pos: [f32; 2],
+        effect: Effect,
+        reach: f32,
+        counts: impl Fn(ObjectId) -> bool,
+    ) -> Option<Object> {
+        self.index();
+        for def in OBJECTS.iter().filter(|def| def.effect == effect) {
+            let distance = (PLAYER_SIZE + def.size) / 2.0 + reach;
+            // the lowest id, like a scan through the list would find
+            let first = self
+                .grid
+                .near(pos, distance)
+                .filter(|&(id, object)| id.kind == def.kind && def.reaches(pos, object, reach))
+                .map(|(id, _)| id)
+                .filter(|&id| self.contains(id) && counts(id))
+                .min();
+            if let Some(id) = first {
+                return self.remove(id);
+            }
+        }
+        None
+    }
+}
+
+impl Default for Field {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// the grid is only a cache of the objects, so it is neither copied nor compared
+impl Clone for Field {
+    fn clone(&self) -> Self {
+        Self {
+            objects: self.objects.clone(),
+            mode: self.mode,
+            grid: Grid::new(),
+            indexed: false,
+        }
+    }
+}
+
+impl PartialEq for Field {
+    fn eq(&self, other: &Self) -> bool {
+        self.objects == other.objects && self.mode == other.mode
+    }
+}
+
+impl std::fmt::Debug for Field {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Field")
+            .field("objects", &self.objects)
+            .field("mode", &self.mode)
+            .finish()
+    }
+}
+
+// FNV-1a over every object's id and exact position bits, in id order so it
+// doesn't matter how either side happens to store them
+pub fn checksum(tick: u64, field: &Field) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    let mut write = |value: u64| {
+        for byte in value.to_le_bytes() {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    };
+
+    write(tick);
+    for kind in ObjectKind::all() {
+        let mut sorted: Vec<_> = field.get(kind).iter().collect();
+        sorted.sort_by_key(|object| object.id);
+
+        write(sorted.len() as u64);
+        for object in sorted {
+            write(object.id.tick);
+            write(object.id.index as u64);
+            write(object.pos[0].to_bits() as u64);
+            write(object.pos[1].to_bits() as u64);
+        }
+    }
+    hash
+}
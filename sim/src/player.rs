@@ -0,0 +1,37 @@
+use crate::within_bounds;
+
+pub const PLAYER_SPEED: f32 = 2.5;
+// players stop once they are this close to their target
+pub const MOVE_TOLERANCE: f32 = 6.0;
+
+// one tick of movement towards `target`, falling is twice as fast and `boost`
+// scales both
+pub fn movement(pos: [f32; 2], target: [f32; 2], boost: f32) -> [f32; 2] {
+    let direction = [target[0] - pos[0], target[1] - pos[1]];
+    let length = (direction[0] * direction[0] + direction[1] * direction[1]).sqrt();
+
+    if length > MOVE_TOLERANCE {
+        let mut speed = PLAYER_SPEED * boost;
+
+        if direction[1] < 0.0 {
+            speed *= 2.0;
+        }
+
+        let recip = 1.0 / length;
+        [direction[0] * recip * speed, direction[1] * recip * speed]
+    } else {
+        [0.0, 0.0]
+    }
+}
+
+// a move that would leave the field is not taken at all
+pub fn step_player(pos: [f32; 2], target: [f32; 2], boost: f32) -> [f32; 2] {
+    let movement = movement(pos, target, boost);
+    let next = [pos[0] + movement[0], pos[1] + movement[1]];
+
+    if within_bounds(next) {
+        next
+    } else {
+        pos
+    }
+}
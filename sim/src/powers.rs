@@ -0,0 +1,119 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Power {
+    // absorbs the next hit
+    Shield,
+    // moves faster for a while
+    Speed,
+    // pulls in score objects from further away for a while
+    Magnet,
+}
+
+impl Power {
+    pub const ALL: [Power; 3] = [Power::Shield, Power::Speed, Power::Magnet];
+}
+
+pub const SPEED_BOOST: f32 = 1.5;
+// how much further than touching a magnet reaches
+pub const MAGNET_REACH: f32 = 100.0;
+// all in ticks
+pub const SPEED_TICKS: u64 = 50;
+pub const MAGNET_TICKS: u64 = 80;
+
+// The powers a runner holds. Timed ones keep the tick they run out on so
+// every side agrees on exactly when that is.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Powers {
+    pub shield: bool,
+    pub speed: Option<u64>,
+    pub magnet: Option<u64>,
+}
+
+impl Powers {
+    // returns the tick a timed power runs out on
+    pub fn pick_up(&mut self, power: Power, tick: u64) -> Option<u64> {
+        let until = match power {
+            Power::Shield => None,
+            Power::Speed => Some(tick + SPEED_TICKS),
+            Power::Magnet => Some(tick + MAGNET_TICKS),
+        };
+        self.set(power, until);
+        until
+    }
+
+    pub fn set(&mut self, power: Power, until: Option<u64>) {
+        match power {
+            Power::Shield => self.shield = true,
+            Power::Speed => self.speed = until,
+            Power::Magnet => self.magnet = until,
+        }
+    }
+
+    pub fn clear(&mut self, power: Power) {
+        match power {
+            Power::Shield => self.shield = false,
+            Power::Speed => self.speed = None,
+            Power::Magnet => self.magnet = None,
+        }
+    }
+
+    pub fn active(&self, power: Power, tick: u64) -> bool {
+        match power {
+            Power::Shield => self.shield,
+            Power::Speed => self.speed.is_some_and(|until| tick < until),
+            Power::Magnet => self.magnet.is_some_and(|until| tick < until),
+        }
+    }
+
+    // ticks left on a timed power
+    pub fn remaining(&self, power: Power, tick: u64) -> Option<u64> {
+        let until = match power {
+            Power::Shield => None,
+            Power::Speed => self.speed,
+            Power::Magnet => self.magnet,
+        };
+        until
+            .filter(|&until| tick < until)
+            .map(|until| until - tick)
+    }
+
+    pub fn boost(&self, tick: u64) -> f32 {
+        if self.active(Power::Speed, tick) {
+            SPEED_BOOST
+        } else {
+            1.0
+        }
+    }
+
+    pub fn reach(&self, tick: u64) -> f32 {
+        if self.active(Power::Magnet, tick) {
+            MAGNET_REACH
+        } else {
+            0.0
+        }
+    }
+
+    // uses up the shield, false if there was none
+    pub fn absorb(&mut self) -> bool {
+        std::mem::take(&mut self.shield)
+    }
+
+    // drops the timed powers that have run out by `tick`
+    pub fn expire(&mut self, tick: u64) -> Vec<Power> {
+        let expired: Vec<_> = [Power::Speed, Power::Magnet]
+            .into_iter()
+            .filter(|&power| self.remaining(power, tick).is_none() && self.held(power))
+            .collect();
+        for &power in &expired {
+            self.clear(power);
+        }
+        expired
+    }
+
+    fn held(&self, power: Power) -> bool {
+        match power {
+            Power::Shield => self.shield,
+            Power::Speed => self.speed.is_some(),
+            Power::Magnet => self.magnet.is_some(),
+        }
+    }
+}
@@ -0,0 +1,158 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::{step_player, Effect, Field, Grid, Powers};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Runner {
+    pub pos: [f32; 2],
+    pub target: [f32; 2],
+    pub score: usize,
+    pub alive: bool,
+    pub powers: Powers,
+}
+
+impl Runner {
+    pub fn new(pos: [f32; 2], target: [f32; 2], score: usize) -> Self {
+        Self {
+            pos,
+            target,
+            score,
+            alive: true,
+            powers: Powers::default(),
+        }
+    }
+}
+
+// Everything the simulation needs to carry on from `tick`. Runners are keyed by
+// whatever identifies a player on the caller's side.
+#[derive(Debug, Clone, PartialEq)]
+pub struct State<K> {
+    pub tick: u64,
+    pub runners: BTreeMap<K, Runner>,
+    pub field: Field,
+}
+
+impl<K: Ord + Copy> State<K> {
+    pub fn new(tick: u64) -> Self {
+        Self {
+            tick,
+            runners: BTreeMap::new(),
+            field: Field::new(),
+        }
+    }
+
+    // Same order as the server: powers running out, new targets, movement,
+    // objects, collisions.
+    // `input` returns the target a runner has on this tick, if it changed.
+    pub fn step(&mut self, rng_seed: u64, mut input: impl FnMut(u64, K) -> Option<[f32; 2]>) {
+        let tick = self.tick;
+
+        for (&key, runner) in self.runners.iter_mut() {
+            if !runner.alive {
+                continue;
+            }
+            runner.powers.expire(tick);
+            if let Some(target) = input(tick, key) {
+                runner.target = target;
+            }
+            runner.pos = step_player(runner.pos, runner.target, runner.powers.boost(tick));
+        }
+
+        self.field.step(rng_seed, tick);
+
+        for runner in self.runners.values_mut() {
+            if !runner.alive {
+                continue;
+            }
+            while let Some((_, power)) = self.field.take_power_up(runner.pos) {
+                runner.powers.pick_up(power, tick);
+            }
+            if self.field.take_damage(runner.pos, runner.score).is_some() && !runner.powers.absorb()
+            {
+                runner.alive = false;
+            }
+        }
+
+        let mut alive = Grid::new();
+        alive.rebuild(
+            self.runners
+                .iter()
+                .filter(|(_, runner)| runner.alive)
+                .map(|(&key, runner)| (key, runner.pos)),
+        );
+        let runners = &self.runners;
+        let claims = self.field.claims(Effect::Score, &alive, |key| {
+            runners[&key].powers.reach(tick)
+        });
+        for (key, id) in claims {
+            self.field.remove(id);
+            if let Some(runner) = self.runners.get_mut(&key) {
+                runner.score += 1;
+            }
+        }
+
+        self.tick += 1;
+    }
+}
+
+// The last `capacity` states, oldest first, one per tick.
+pub struct History<K> {
+    states: VecDeque<State<K>>,
+    capacity: usize,
+}
+
+impl<K: Ord + Copy> History<K> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            states: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn push(&mut self, state: State<K>) {
+        // a state replaces whatever was predicted for its tick and after
+        self.states.retain(|s| s.tick < state.tick);
+        self.states.push_back(state);
+        while self.states.len() > self.capacity {
+            self.states.pop_front();
+        }
+    }
+
+    pub fn at(&self, tick: u64) -> Option<&State<K>> {
+        self.states.iter().find(|state| state.tick == tick)
+    }
+
+    pub fn latest(&self) -> Option<&State<K>> {
+        self.states.back()
+    }
+
+    pub fn states_mut(&mut self) -> impl Iterator<Item = &mut State<K>> {
+        self.states.iter_mut()
+    }
+
+    pub fn clear(&mut self) {
+        self.states.clear();
+    }
+
+    // Restores the state at `tick`, lets `correct` patch in what the server
+    // confirmed and simulates forward to `to_tick` again, recording every
+    // state on the way. Returns None when `tick` has fallen out of history.
+    pub fn rollback(
+        &mut self,
+        tick: u64,
+        to_tick: u64,
+        rng_seed: u64,
+        correct: impl FnOnce(&mut State<K>),
+        mut input: impl FnMut(u64, K) -> Option<[f32; 2]>,
+    ) -> Option<&State<K>> {
+        let mut state = self.at(tick)?.clone();
+        correct(&mut state);
+        self.push(state.clone());
+
+        while state.tick < to_tick {
+            state.step(rng_seed, &mut input);
+            self.push(state.clone());
+        }
+        self.latest()
+    }
+}
@@ -0,0 +1,43 @@
+// Wind and per-object motion. Both are pure functions of the seed and a tick so
+// every side derives the same trajectories without sending them anywhere.
+
+// strongest sideways push in px per tick
+pub const MAX_WIND: f32 = 1.5;
+// wind blends from one gust to the next over this many ticks
+pub const GUST_TICKS: u64 = 40;
+
+const WIND_SALT: u64 = 0x7769_6e64;
+const MOTION_SALT: u64 = 0x6d6f_7469_6f6e;
+
+// SplitMix64, cheap enough to run for every object on every tick
+pub(crate) fn mix(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+// uniform in [-1, 1), built from 24 bits so it is exact in an f32
+pub(crate) fn unit(x: u64) -> f32 {
+    (x >> 40) as f32 / (1u64 << 23) as f32 - 1.0
+}
+
+fn gust(rng_seed: u64, index: u64) -> f32 {
+    unit(mix(rng_seed ^ WIND_SALT ^ mix(index))) * MAX_WIND
+}
+
+// sideways wind on `tick`, positive blows right
+pub fn wind(rng_seed: u64, tick: u64) -> f32 {
+    let index = tick / GUST_TICKS;
+    let from = gust(rng_seed, index);
+    let to = gust(rng_seed, index + 1);
+    let blend = (tick % GUST_TICKS) as f32 / GUST_TICKS as f32;
+    from + (to - from) * blend
+}
+
+// How the object spawned `index`th on `object_tick` differs from the rest of
+// its kind: a speed offset in [-1, 1) and an acceleration factor in [0, 1).
+pub fn variation(rng_seed: u64, object_tick: u64, index: u8) -> (f32, f32) {
+    let hash = mix(rng_seed ^ mix(object_tick ^ MOTION_SALT ^ ((index as u64) << 56)));
+    (unit(hash), unit(mix(hash)) * 0.5 + 0.5)
+}
@@ -0,0 +1,92 @@
+use satrunner_sim::{History, Runner, State};
+
+const SEED: u64 = 0x5eed;
+
+fn start() -> State<u8> {
+    let mut state = State::new(100);
+    state
+        .runners
+        .insert(0, Runner::new([0.0, -400.0], [0.0, -400.0], 0));
+    state
+        .runners
+        .insert(1, Runner::new([300.0, -400.0], [300.0, -400.0], 0));
+    state
+}
+
+// runner 0 heads left on tick 105, runner 1 right on tick 108
+fn inputs(tick: u64, key: u8) -> Option<[f32; 2]> {
+    match (tick, key) {
+        (105, 0) => Some([-500.0, -300.0]),
+        (108, 1) => Some([800.0, -450.0]),
+        _ => None,
+    }
+}
+
+#[test]
+fn late_inputs_resimulate_to_the_same_state() {
+    let mut straight = start();
+    for _ in 0..20 {
+        straight.step(SEED, inputs);
+    }
+
+    // runner 1's input only shows up after tick 115 was already predicted
+    let mut history = History::new(32);
+    let mut predicted = start();
+    history.push(predicted.clone());
+    for _ in 0..20 {
+        predicted.step(
+            SEED,
+            |tick, key| if key == 1 { None } else { inputs(tick, key) },
+        );
+        history.push(predicted.clone());
+    }
+    assert_ne!(predicted, straight);
+
+    let resimulated = history.rollback(108, 120, SEED, |_| {}, inputs).unwrap();
+    assert_eq!(resimulated, &straight);
+    assert_eq!(history.latest(), Some(&straight));
+}
+
+#[test]
+fn corrections_are_applied_before_resimulating() {
+    let mut history = History::new(32);
+    let mut state = start();
+    history.push(state.clone());
+    for _ in 0..5 {
+        state.step(SEED, |_, _| None);
+        history.push(state.clone());
+    }
+
+    let latest = history
+        .rollback(
+            102,
+            105,
+            SEED,
+            |state| {
+                *state.runners.get_mut(&0).unwrap() = Runner::new([10.0, -400.0], [10.0, -400.0], 2)
+            },
+            |_, _| None,
+        )
+        .unwrap();
+    assert_eq!(latest.tick, 105);
+    assert_eq!(
+        latest.runners[&0],
+        Runner::new([10.0, -400.0], [10.0, -400.0], 2)
+    );
+}
+
+#[test]
+fn history_only_reaches_back_so_far() {
+    let mut history = History::new(4);
+    let mut state = start();
+    for _ in 0..10 {
+        history.push(state.clone());
+        state.step(SEED, |_, _| None);
+    }
+
+    assert!(history.at(105).is_none());
+    assert!(history.at(106).is_some());
+    assert!(history
+        .rollback(105, 110, SEED, |_| {}, |_, _| None)
+        .is_none());
+}
@@ -0,0 +1,602 @@
+use std::collections::BTreeSet;
+
+use satrunner_sim::{
+    checksum, difficulty,
+    difficulty::{cluster_centre, threat, CLUSTER_SPREAD},
+    objects::{BOLT_INTERVAL, BURST_SPREAD, FALL_SPEED, PLAYER_SIZE},
+    player::{MOVE_TOLERANCE, PLAYER_SPEED},
+    powers::{MAGNET_REACH, SPEED_BOOST, SPEED_TICKS},
+    spawn, spawn_kinds, step_objects, step_player, ticks_to_secs,
+    weather::{wind, GUST_TICKS, MAX_WIND},
+    within_bounds, Effect, Field, GameMode, Grid, Object, ObjectId, ObjectKind, Power, Powers,
+    OBJECTS, X_BOUNDS, Y_BOUNDS,
+};
+
+const STEADY: GameMode = GameMode::Steady;
+
+fn length(v: [f32; 2]) -> f32 {
+    (v[0] * v[0] + v[1] * v[1]).sqrt()
+}
+
+#[test]
+fn players_climb_slower_than_they_fall() {
+    let up = step_player([0.0, 0.0], [0.0, 100.0], 1.0);
+    let down = step_player([0.0, 0.0], [0.0, -100.0], 1.0);
+    assert_eq!(up, [0.0, PLAYER_SPEED]);
+    assert_eq!(down, [0.0, -PLAYER_SPEED * 2.0]);
+
+    let diagonal = step_player([0.0, 0.0], [100.0, 100.0], 1.0);
+    assert!((length(diagonal) - PLAYER_SPEED).abs() < 1e-5);
+}
+
+#[test]
+fn players_stop_near_their_target() {
+    let target = [MOVE_TOLERANCE - 0.5, 0.0];
+    assert_eq!(step_player([0.0, 0.0], target, 1.0), [0.0, 0.0]);
+}
+
+#[test]
+fn players_never_leave_the_field() {
+    let edge = [X_BOUNDS - 1.0, 0.0];
+    assert_eq!(step_player(edge, [X_BOUNDS + 100.0, 0.0], 1.0), edge);
+    assert!(within_bounds([-X_BOUNDS, Y_BOUNDS]));
+    assert!(!within_bounds([0.0, -Y_BOUNDS - 0.1]));
+}
+
+#[test]
+fn spawns_are_deterministic_per_seed_and_tick() {
+    assert_eq!(spawn(STEADY, 7, 11), spawn(STEADY, 7, 11));
+    assert_ne!(spawn(STEADY, 7, 11), spawn(STEADY, 8, 11));
+
+    for tick in 1..20 {
+        let spawned = spawn(STEADY, 7, tick);
+        let expected = if tick % BOLT_INTERVAL == 0 {
+            ObjectKind::Bolt
+        } else if tick % ObjectKind::Storm.def().every == 0 {
+            ObjectKind::Storm
+        } else {
+            ObjectKind::Rain
+        };
+        assert_eq!(spawned.len(), expected.def().burst as usize);
+        for (index, object) in spawned.iter().enumerate() {
+            assert_eq!(object.id, ObjectId::new(expected, tick, index as u8));
+            assert_eq!(object.pos[1], Y_BOUNDS);
+            assert!(object.pos[0].abs() <= X_BOUNDS);
+        }
+    }
+}
+
+#[test]
+fn bursts_spawn_several_objects_a_tick_apart_by_index() {
+    let storm = ObjectKind::Storm.def();
+    let tick = storm.every * 3;
+    let burst = spawn(STEADY, 7, tick);
+    assert!(burst.len() > 1);
+    for object in &burst[1..] {
+        assert!((object.pos[0] - burst[0].pos[0]).abs() <= BURST_SPREAD);
+    }
+
+    let mut storms = Vec::new();
+    step_objects(&mut storms, ObjectKind::Storm, STEADY, 7, tick);
+    assert_eq!(storms.len(), burst.len());
+    // each falls its own way even though they spawned together
+    assert_ne!(
+        storm.velocity(STEADY, 7, burst[0].id, tick),
+        storm.velocity(STEADY, 7, burst[1].id, tick)
+    );
+
+    let mut field = Field::new();
+    for object in burst.iter().rev() {
+        field.insert(*object);
+    }
+    assert_eq!(field.get(ObjectKind::Storm), &burst);
+    assert_eq!(field.remove(burst[1].id), Some(burst[1]));
+    assert_eq!(field.remove(burst[1].id), None);
+    assert!(field.contains(burst[0].id) && field.contains(burst[2].id));
+}
+
+#[test]
+fn objects_fall_until_they_leave_the_field() {
+    let rain_def = ObjectKind::Rain.def();
+    let mut rain = Vec::new();
+    step_objects(&mut rain, ObjectKind::Rain, STEADY, 7, 1);
+    assert_eq!(rain.len(), 1);
+    let fallen = Y_BOUNDS - rain[0].pos[1];
+    assert!(fallen >= FALL_SPEED * (1.0 - rain_def.speed_variance));
+    assert!(fallen <= FALL_SPEED * (1.0 + rain_def.speed_variance));
+
+    // a bolt tick adds nothing to the rain but still moves it
+    let before = rain[0];
+    step_objects(&mut rain, ObjectKind::Rain, STEADY, 7, BOLT_INTERVAL);
+    assert_eq!(rain.len(), 1);
+    let velocity = rain_def.velocity(STEADY, 7, before.id, BOLT_INTERVAL);
+    assert_eq!(rain[0].pos[0], before.pos[0] + velocity[0]);
+    assert_eq!(rain[0].pos[1], before.pos[1] + velocity[1]);
+
+    let mut bolts = vec![Object {
+        id: ObjectId::new(ObjectKind::Bolt, 0, 0),
+        pos: [0.0, -Y_BOUNDS + 1.0],
+    }];
+    step_objects(&mut bolts, ObjectKind::Bolt, STEADY, 7, 1);
+    assert!(bolts.is_empty());
+}
+
+#[test]
+fn wind_blows_in_gusts_from_the_seed() {
+    assert_eq!(wind(7, 123), wind(7, 123));
+    assert_ne!(wind(7, 123), wind(8, 123));
+
+    let mut gusts = Vec::new();
+    for tick in 0..20 * GUST_TICKS {
+        let now = wind(7, tick);
+        assert!(now.abs() <= MAX_WIND);
+        // gusts blend into each other instead of jumping
+        assert!((wind(7, tick + 1) - now).abs() <= 2.0 * MAX_WIND / GUST_TICKS as f32);
+        if tick % GUST_TICKS == 0 {
+            gusts.push(now);
+        }
+    }
+    assert!(gusts.iter().any(|&gust| gust > 0.0));
+    assert!(gusts.iter().any(|&gust| gust < 0.0));
+}
+
+#[test]
+fn objects_fall_at_their_own_speed_and_speed_up() {
+    let rain = ObjectKind::Rain.def();
+    let id = |tick| ObjectId::new(ObjectKind::Rain, tick, 0);
+    let speeds: Vec<f32> = (0..20)
+        .map(|tick| -rain.velocity(STEADY, 7, id(tick), tick)[1])
+        .collect();
+    assert!(speeds.iter().any(|&speed| speed != speeds[0]));
+    for &speed in &speeds {
+        assert!(speed >= FALL_SPEED * (1.0 - rain.speed_variance));
+        assert!(speed < FALL_SPEED * (1.0 + rain.speed_variance));
+    }
+
+    assert!(rain.velocity(STEADY, 7, id(3), 100)[1] <= rain.velocity(STEADY, 7, id(3), 4)[1]);
+    assert_eq!(
+        rain.velocity(STEADY, 7, id(3), 100),
+        rain.velocity(STEADY, 7, id(3), 100)
+    );
+}
+
+#[test]
+fn storm_drops_are_heavier_than_rain() {
+    let (rain, storm) = (ObjectKind::Rain.def(), ObjectKind::Storm.def());
+    assert_eq!(storm.effect, Effect::Damage);
+    assert!(storm.size > rain.size);
+    assert!(storm.drift < rain.drift);
+    assert!(
+        storm.fall_speed * (1.0 - storm.speed_variance)
+            > rain.fall_speed * (1.0 + rain.speed_variance)
+    );
+}
+
+#[test]
+fn checksums_ignore_order_but_not_positions() {
+    let mut field = Field::new();
+    for tick in 0..40 {
+        field.step(7, tick);
+    }
+    let hash = checksum(39, &field);
+
+    let rain = field.get_mut(ObjectKind::Rain);
+    rain.reverse();
+    assert_eq!(checksum(39, &field), hash);
+    assert_ne!(checksum(40, &field), hash);
+
+    field.get_mut(ObjectKind::Rain)[3].pos[1] -= FALL_SPEED;
+    assert_ne!(checksum(39, &field), hash);
+    field.get_mut(ObjectKind::Rain)[3].pos[1] += FALL_SPEED;
+
+    // a missing rain drop can't be made up for by an extra bolt
+    let drop = field.get_mut(ObjectKind::Rain).pop().unwrap();
+    field.get_mut(ObjectKind::Bolt).push(drop);
+    assert_ne!(checksum(39, &field), hash);
+}
+
+#[test]
+fn the_registry_is_indexed_by_kind() {
+    for (index, def) in OBJECTS.iter().enumerate() {
+        assert_eq!(def.kind as usize, index);
+        assert_eq!(def.kind.def(), def);
+    }
+    for power in Power::ALL {
+        let kinds = OBJECTS
+            .iter()
+            .filter(|def| def.effect == Effect::PowerUp(power))
+            .count();
+        assert_eq!(kinds, 1, "{:?}", power);
+    }
+}
+
+#[test]
+fn power_ups_are_rarer_than_bolts() {
+    assert!(spawn_kinds(70).contains(&ObjectKind::Shield));
+    assert!(spawn_kinds(90).contains(&ObjectKind::Speed));
+    assert!(spawn_kinds(110).contains(&ObjectKind::Magnet));
+    assert_eq!(spawn_kinds(75), vec![ObjectKind::Bolt]);
+    assert_eq!(spawn_kinds(76), vec![ObjectKind::Rain]);
+
+    let count = |kind: fn(ObjectKind) -> bool| {
+        (1..1000)
+            .flat_map(spawn_kinds)
+            .filter(|&spawned| kind(spawned))
+            .count()
+    };
+    let power_ups = count(|kind| matches!(kind.def().effect, Effect::PowerUp(_)));
+    let bolts = count(|kind| kind == ObjectKind::Bolt);
+    assert!(power_ups * 4 < bolts);
+}
+
+#[test]
+fn bolts_keep_their_cadence_whatever_else_spawns() {
+    for tick in 0..10_000 {
+        let kinds = spawn_kinds(tick);
+        assert_eq!(
+            kinds.contains(&ObjectKind::Bolt),
+            tick % BOLT_INTERVAL == 0,
+            "tick {}",
+            tick
+        );
+        // rain only fills the ticks nothing else wants
+        assert_eq!(
+            kinds.contains(&ObjectKind::Rain),
+            kinds.len() == 1 && kinds[0] == ObjectKind::Rain
+        );
+    }
+
+    for mode in GameMode::ALL {
+        let bolts = (0..10_000)
+            .flat_map(|tick| spawn(mode, 11, tick))
+            .filter(|object| object.id.kind == ObjectKind::Bolt)
+            .count() as u64;
+        assert_eq!(bolts, 10_000 / BOLT_INTERVAL, "{:?}", mode);
+    }
+    assert_eq!(
+        spawn(GameMode::Steady, 11, 70)
+            .iter()
+            .map(|object| object.id.kind)
+            .collect::<Vec<_>>(),
+        vec![
+            ObjectKind::Bolt,
+            ObjectKind::Shield,
+            ObjectKind::Storm,
+            ObjectKind::Storm,
+            ObjectKind::Storm,
+        ]
+    );
+}
+
+#[test]
+fn timed_powers_run_out_and_shields_absorb_once() {
+    let mut powers = Powers::default();
+    assert_eq!(powers.pick_up(Power::Speed, 10), Some(10 + SPEED_TICKS));
+    assert_eq!(powers.pick_up(Power::Shield, 10), None);
+    assert_eq!(powers.boost(10), SPEED_BOOST);
+    assert_eq!(powers.reach(10), 0.0);
+    assert_eq!(powers.remaining(Power::Speed, 12), Some(SPEED_TICKS - 2));
+
+    assert!(powers.expire(10 + SPEED_TICKS - 1).is_empty());
+    assert_eq!(powers.expire(10 + SPEED_TICKS), vec![Power::Speed]);
+    assert_eq!(powers.boost(10), 1.0);
+
+    assert!(powers.absorb());
+    assert!(!powers.absorb());
+}
+
+#[test]
+fn boosts_and_magnets_scale_movement_and_reach() {
+    let boosted = step_player([0.0, 0.0], [0.0, 100.0], SPEED_BOOST);
+    assert_eq!(boosted, [0.0, PLAYER_SPEED * SPEED_BOOST]);
+
+    let mut field = Field::new();
+    field.insert(Object {
+        id: ObjectId::new(ObjectKind::Bolt, 5, 0),
+        pos: [MAGNET_REACH, 0.0],
+    });
+    assert_eq!(field.take_hit([0.0, 0.0], Effect::Score), None);
+    assert!(field
+        .take_within([0.0, 0.0], Effect::Score, MAGNET_REACH)
+        .is_some());
+}
+
+#[test]
+fn the_field_never_holds_more_than_max_alive() {
+    for mode in GameMode::ALL {
+        let mut field = Field::with_mode(mode);
+        for tick in 0..4000 {
+            field.step(3, tick);
+            for kind in ObjectKind::all() {
+                assert!(field.get(kind).len() <= kind.def().max_alive());
+            }
+        }
+    }
+}
+
+#[test]
+fn difficulty_climbs_with_the_score() {
+    for mode in GameMode::ALL {
+        let curve = mode.curve();
+        let start = difficulty(mode, 0);
+        assert_eq!(start.density, curve.density.0);
+        assert_eq!(start.speed, curve.speed.0);
+
+        let mut last = start;
+        for score in 0..40 {
+            let now = difficulty(mode, score);
+            assert!(now.density <= 1.0 && now.speed >= 1.0, "{:?}", mode);
+            assert!(now.density >= last.density);
+            assert!(now.speed >= last.speed);
+            assert!(now.cluster >= last.cluster);
+            last = now;
+        }
+        assert_eq!(difficulty(mode, curve.ramp), curve.at(1.0));
+    }
+    assert_eq!(
+        difficulty(GameMode::Steady, 1000),
+        difficulty(GameMode::Steady, 0)
+    );
+}
+
+#[test]
+fn higher_scores_face_more_faster_and_clustered_rain() {
+    let mode = GameMode::Classic;
+    let curve = mode.curve();
+    let rain = ObjectKind::Rain.def();
+
+    // late in the game, so only the score can tell a runner's level
+    let spawned: Vec<_> = (5000..5600)
+        .flat_map(|tick| spawn(mode, 7, tick))
+        .filter(|object| object.id.kind == ObjectKind::Rain)
+        .collect();
+
+    // (drops that count, of those near the cluster, fastest of those)
+    let sample = |score: usize| {
+        let counting: Vec<_> = spawned
+            .iter()
+            .filter(|o| curve.counts(o.id, score))
+            .collect();
+        let clustered = counting
+            .iter()
+            .filter(|o| (o.pos[0] - cluster_centre(7, o.id.tick)).abs() < CLUSTER_SPREAD)
+            .count();
+        let fastest = counting
+            .iter()
+            .map(|o| -rain.velocity(mode, 7, o.id, o.id.tick)[1])
+            .fold(0.0, f32::max);
+        (counting.len(), clustered, fastest)
+    };
+
+    let (calm, hard) = (sample(0), sample(curve.ramp));
+    assert_eq!(hard.0, spawned.len());
+    let share = calm.0 as f32 / hard.0 as f32;
+    assert!(
+        (share - curve.density.0 / curve.density.1).abs() < 0.1,
+        "{}",
+        share
+    );
+    assert!(hard.1 * calm.0 > calm.1 * hard.0);
+    assert!(hard.2 > calm.2);
+    for object in &spawned {
+        assert!(object.pos[0].abs() <= X_BOUNDS);
+    }
+    assert_eq!(spawn(mode, 7, 5011), spawn(mode, 7, 5011));
+}
+
+#[test]
+fn rain_above_a_runners_level_passes_through_them() {
+    let mode = GameMode::Classic;
+    let curve = mode.curve();
+    let rain = (0..)
+        .map(|tick| ObjectId::new(ObjectKind::Rain, tick, 0))
+        .find(|&id| curve.onset(id) > 0.5)
+        .unwrap();
+
+    let mut field = Field::with_mode(mode);
+    for id in [rain, ObjectId::new(ObjectKind::Storm, rain.tick, 0)] {
+        field.insert(Object {
+            id,
+            pos: [0.0, 0.0],
+        });
+    }
+    // storms hit everyone
+    assert_eq!(
+        field.take_damage([0.0, 0.0], 0).map(|o| o.id.kind),
+        Some(ObjectKind::Storm)
+    );
+    assert_eq!(field.take_damage([0.0, 0.0], 0), None);
+    assert_eq!(
+        field.take_damage([0.0, 0.0], curve.ramp).map(|o| o.id),
+        Some(rain)
+    );
+
+    let threats: Vec<_> = (0..1000)
+        .map(|tick| threat(ObjectId::new(ObjectKind::Rain, tick, 0)))
+        .collect();
+    assert!(threats.iter().all(|threat| (0.0..1.0).contains(threat)));
+}
+
+#[test]
+fn hits_take_the_first_touching_object_with_that_effect() {
+    let mut field = Field::new();
+    field.insert(Object {
+        id: ObjectId::new(ObjectKind::Bolt, 5, 0),
+        pos: [0.0, 0.0],
+    });
+    field.insert(Object {
+        id: ObjectId::new(ObjectKind::Rain, 6, 0),
+        pos: [50.0, 0.0],
+    });
+
+    assert_eq!(field.take_hit([0.0, 0.0], Effect::Damage), None);
+    assert_eq!(
+        field.take_hit([0.0, 0.0], Effect::Score),
+        Some(Object {
+            id: ObjectId::new(ObjectKind::Bolt, 5, 0),
+            pos: [0.0, 0.0]
+        })
+    );
+    assert_eq!(field.len(), 1);
+}
+
+#[test]
+fn hits_use_the_sprite_overlap() {
+    let rain = ObjectKind::Rain.def();
+    let distance = (PLAYER_SIZE + rain.size) / 2.0;
+    assert!(rain.hits([0.0, 0.0], [distance - 0.1, -distance + 0.1]));
+    assert!(!rain.hits([0.0, 0.0], [distance, 0.0]));
+}
+
+#[test]
+fn power_ups_look_apart_from_each_other_and_the_bolt() {
+    let power_ups: Vec<_> = OBJECTS
+        .iter()
+        .filter(|def| def.kind == ObjectKind::Bolt || matches!(def.effect, Effect::PowerUp(_)))
+        .collect();
+    let sprites: BTreeSet<_> = power_ups.iter().map(|def| def.sprite).collect();
+    assert_eq!(sprites.len(), power_ups.len());
+
+    let assets = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("../assets");
+    for def in OBJECTS {
+        assert!(assets.join(def.sprite).exists(), "missing {}", def.sprite);
+    }
+}
+
+#[test]
+fn claims_go_to_the_nearest_runner_that_reaches() {
+    let mut field = Field::new();
+    for (tick, x) in [(5, 0.0), (10, 300.0), (15, 600.0)] {
+        field.insert(Object {
+            id: ObjectId::new(ObjectKind::Bolt, tick, 0),
+            pos: [x, 0.0],
+        });
+    }
+    let mut runners = Grid::new();
+    runners.rebuild([(3, [-50.0, 0.0]), (1, [50.0, 0.0]), (2, [250.0, 0.0])]);
+
+    // 1 and 3 tie for the first bolt, nobody reaches the last one
+    let claims = field.claims(Effect::Score, &runners, |_| MAGNET_REACH);
+    assert_eq!(
+        claims,
+        vec![
+            (1, ObjectId::new(ObjectKind::Bolt, 5, 0)),
+            (2, ObjectId::new(ObjectKind::Bolt, 10, 0)),
+        ]
+    );
+    assert!(field.claims(Effect::Score, &runners, |_| 0.0).is_empty());
+}
+
+#[test]
+fn ticks_convert_to_whole_seconds() {
+    assert_eq!(ticks_to_secs(9), 0);
+    assert_eq!(ticks_to_secs(10), 1);
+    assert_eq!(ticks_to_secs(305), 30);
+}
+
+#[test]
+fn the_grid_finds_what_a_scan_would() {
+    // players and drops alike, some right on the edge of the arena
+    let mut positions: Vec<[f32; 2]> = (0..2000)
+        .map(|i| {
+            let i = i as f32;
+            [
+                (i * 37.3) % (2.0 * X_BOUNDS) - X_BOUNDS,
+                (i * 11.9) % (2.0 * Y_BOUNDS) - Y_BOUNDS,
+            ]
+        })
+        .collect();
+    positions.extend([
+        [X_BOUNDS, Y_BOUNDS],
+        [-X_BOUNDS, -Y_BOUNDS],
+        [X_BOUNDS, -Y_BOUNDS],
+    ]);
+
+    let mut grid = Grid::new();
+    grid.rebuild(positions.iter().copied().enumerate());
+    assert_eq!(grid.len(), positions.len());
+
+    for (centre, reach) in [
+        ([0.0, 0.0], 20.0),
+        ([X_BOUNDS, Y_BOUNDS], 75.0),
+        ([-990.0, 10.0], 300.0),
+    ] {
+        let mut found: Vec<usize> = grid.near(centre, reach).map(|(i, _)| i).collect();
+        found.sort();
+        let expected: Vec<usize> = positions
+            .iter()
+            .enumerate()
+            .filter(|(_, p)| (p[0] - centre[0]).abs() <= reach && (p[1] - centre[1]).abs() <= reach)
+            .map(|(i, _)| i)
+            .collect();
+        assert!(!expected.is_empty());
+        assert_eq!(found, expected);
+    }
+}
+
+#[test]
+fn fields_take_the_earliest_object_and_find_it_after_changes() {
+    let mut field = Field::new();
+    for tick in 1000..1200 {
+        field.step(5, tick);
+    }
+    let latest = *field.get(ObjectKind::Rain).last().unwrap();
+    let earlier = Object {
+        id: ObjectId::new(ObjectKind::Rain, latest.id.tick - 1000, 0),
+        pos: latest.pos,
+    };
+    // added out of order behind the grid's back
+    field.get_mut(ObjectKind::Rain).push(earlier);
+    assert_eq!(field.take_hit(latest.pos, Effect::Damage), Some(earlier));
+    assert_eq!(field.take_hit(latest.pos, Effect::Damage), Some(latest));
+
+    field.insert(earlier);
+    let rain = field.get(ObjectKind::Rain);
+    assert!(rain.windows(2).all(|pair| pair[0].id < pair[1].id));
+    assert_eq!(field.remove(earlier.id), Some(earlier));
+    assert_eq!(field.remove(earlier.id), None);
+
+    let view = field.within([-X_BOUNDS, 0.0], [X_BOUNDS, Y_BOUNDS]);
+    assert!(!view.is_empty());
+    assert!(view.iter().all(|object| object.pos[1] >= 0.0));
+    assert_eq!(
+        view.len(),
+        field.iter().filter(|object| object.pos[1] >= 0.0).count()
+    );
+}
+
+#[test]
+fn seeking_lands_on_the_same_field_as_stepping() {
+    for mode in GameMode::ALL {
+        let mut stepped = Field::with_mode(mode);
+        let mut removed = BTreeSet::new();
+        for tick in 0..1500 {
+            stepped.step(9, tick);
+            if tick % 50 == 0 {
+                let taken = stepped.iter().next().map(|object| object.id).unwrap();
+                stepped.remove(taken);
+                removed.insert(taken);
+            }
+        }
+        let at = Field::at(mode, 9, 1499, &removed);
+        assert_eq!(at, stepped, "{:?}", mode);
+        assert_eq!(checksum(1499, &at), checksum(1499, &stepped));
+
+        // seeking keeps whatever was taken off the field off it
+        let mut seeked = stepped.clone();
+        for tick in 1500..1520 {
+            stepped.step(9, tick);
+        }
+        seeked.seek(9, 1500..1520);
+        assert_eq!(seeked, stepped, "{:?}", mode);
+
+        // further than anything falls
+        let later = 1520 + 10_000;
+        seeked.seek(9, 1520..later);
+        assert_eq!(seeked, Field::at(mode, 9, later - 1, &BTreeSet::new()));
+    }
+    assert_eq!(
+        Field::at(GameMode::Classic, 9, 0, &BTreeSet::new()).len(),
+        spawn(GameMode::Classic, 9, 0).len()
+    );
+}
@@ -0,0 +1,181 @@
+use std::collections::BTreeMap;
+
+use bevy::prelude::*;
+use satrunner_sim::{checksum, Object, ObjectId, ObjectKind, CHECKSUM_INTERVAL};
+
+use crate::{
+    game_util::resources::{Checksums, ClientTick, Objects, Rollback},
+    network::messages::{Checksum, ObjectSync},
+};
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Divergence {
+    pub only_here: Vec<ObjectId>,
+    pub only_on_server: Vec<ObjectId>,
+    pub moved: Vec<ObjectId>,
+}
+
+// the last tick the objects in `Objects` have been simulated for, the rollback
+// simulation shows what tick N-1 left behind on tick N
+pub fn objects_tick(client_tick: &ClientTick, rollback: &Rollback) -> Option<u64> {
+    let tick = client_tick.tick?;
+    Some(if rollback.enabled() {
+        tick.saturating_sub(1)
+    } else {
+        tick
+    })
+}
+
+pub fn record_checksum(
+    objects: Res<Objects>,
+    client_tick: Res<ClientTick>,
+    rollback: Res<Rollback>,
+    mut checksums: ResMut<Checksums>,
+) {
+    if let Some(tick) = objects_tick(&client_tick, &rollback) {
+        if tick.is_multiple_of(CHECKSUM_INTERVAL) {
+            checksums.record(tick, &objects.field);
+        }
+    }
+}
+
+// our own checksum for the server's tick when the two differ
+pub fn verify(checksums: &Checksums, sent: Checksum) -> Option<u64> {
+    let field = checksums.recorded.get(&sent.tick)?;
+    let local = checksum(sent.tick, field);
+    (local != sent.hash).then_some(local)
+}
+
+pub fn diverging(here: &[Object], server: &[Object]) -> Divergence {
+    let mut divergence = Divergence::default();
+    for object in here {
+        match server.iter().find(|o| o.id == object.id) {
+            Some(theirs) if theirs.pos != object.pos => divergence.moved.push(object.id),
+            Some(_) => {}
+            None => divergence.only_here.push(object.id),
+        }
+    }
+    for object in server {
+        if !here.iter().any(|o| o.id == object.id) {
+            divergence.only_on_server.push(object.id);
+        }
+    }
+    divergence
+}
+
+// Takes the server's objects, reports how ours had drifted from them and
+// simulates them forward to `now`, the tick our own objects are at.
+pub fn resync(checksums: &mut Checksums, objects: &mut Objects, sync: ObjectSync, now: u64) {
+    let Some(rng_seed) = objects.rng_seed else {
+        return;
+    };
+    let mut field = sync.objects.to_field(objects.field.mode());
+
+    if let Some(desync_tick) = checksums.pending.take() {
+        if let Some(mut here) = checksums.recorded.get(&desync_tick).cloned() {
+            here.seek(rng_seed, desync_tick + 1..sync.tick + 1);
+            let divergence: BTreeMap<ObjectKind, Divergence> = ObjectKind::all()
+                .map(|kind| (kind, diverging(here.get(kind), field.get(kind))))
+                .filter(|(_, divergence)| *divergence != Divergence::default())
+                .collect();
+            warn!(
+                "desync at tick {}, as of tick {} {:?}",
+                desync_tick, sync.tick, divergence
+            );
+        }
+    }
+
+    field.seek(rng_seed, sync.tick + 1..now + 1);
+    objects.field = field;
+    checksums.recorded.clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use satrunner_sim::{checksum, step_objects, Field, GameMode, Object, ObjectKind};
+
+    use super::{diverging, resync, verify, Divergence};
+    use crate::{
+        game_util::resources::{Checksums, Objects},
+        network::messages::{Checksum, ObjectMsg, ObjectSync},
+    };
+
+    const SEED: u64 = 99;
+
+    fn simulate(to: u64) -> Vec<Object> {
+        let mut rain = Vec::new();
+        for tick in 0..=to {
+            step_objects(&mut rain, ObjectKind::Rain, GameMode::Classic, SEED, tick);
+        }
+        rain
+    }
+
+    fn rain_only(rain: &[Object]) -> Field {
+        let mut field = Field::new();
+        field.get_mut(ObjectKind::Rain).extend_from_slice(rain);
+        field
+    }
+
+    #[test]
+    fn matching_checksums_are_quiet() {
+        let rain = simulate(30);
+        let mut checksums = Checksums::new();
+        checksums.record(30, &rain_only(&rain));
+
+        let hash = checksum(30, &rain_only(&rain));
+        assert_eq!(verify(&checksums, Checksum { tick: 30, hash }), None);
+        assert!(verify(
+            &checksums,
+            Checksum {
+                tick: 30,
+                hash: !hash
+            }
+        )
+        .is_some());
+        assert_eq!(verify(&checksums, Checksum { tick: 60, hash }), None);
+    }
+
+    #[test]
+    fn server_removals_are_not_a_desync() {
+        let rain = simulate(30);
+        let server: Vec<_> = rain[1..].to_vec();
+        let mut checksums = Checksums::new();
+        checksums.record(30, &rain_only(&rain));
+
+        let hash = checksum(30, &rain_only(&server));
+        assert!(verify(&checksums, Checksum { tick: 30, hash }).is_some());
+        checksums.remove_object(rain[0].id);
+        assert_eq!(verify(&checksums, Checksum { tick: 30, hash }), None);
+    }
+
+    #[test]
+    fn resync_reports_and_replaces_our_objects() {
+        let server = simulate(30);
+        let mut here = server.clone();
+        let missing = here.remove(2).id;
+        here[0].pos[0] += 1.0;
+        assert_eq!(
+            diverging(&here, &server),
+            Divergence {
+                only_here: vec![],
+                only_on_server: vec![missing],
+                moved: vec![here[0].id],
+            }
+        );
+
+        let mut checksums = Checksums::new();
+        checksums.record(30, &rain_only(&here));
+        checksums.pending = Some(30);
+        let mut objects = Objects::new();
+        objects.rng_seed = Some(SEED);
+        objects.field = rain_only(&here);
+
+        let sync = ObjectSync {
+            tick: 30,
+            objects: ObjectMsg::from_field(&rain_only(&server)),
+        };
+        resync(&mut checksums, &mut objects, sync, 34);
+        assert_eq!(objects.field.get(ObjectKind::Rain), &simulate(34));
+        assert_eq!(checksums.pending, None);
+    }
+}
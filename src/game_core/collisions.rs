@@ -0,0 +1,147 @@
+use bevy::prelude::*;
+use satrunner_sim::{within_bounds, Effect};
+
+use crate::game_util::resources::{ClientTick, Objects, Prediction, Predictions, Rollback};
+
+use super::player::Player;
+
+// Collides the local player with the objects it can see so hits and pickups
+// show up on the tick they happen instead of a round trip later. The server
+// stays authoritative, handle_server confirms or rolls these back.
+pub fn predict_collisions(
+    mut query_player: Query<(&mut Player, &Transform)>,
+    mut objects: ResMut<Objects>,
+    mut predictions: ResMut<Predictions>,
+    client_tick: Res<ClientTick>,
+    rollback: Res<Rollback>,
+) {
+    predictions.flash = predictions.flash.saturating_sub(1);
+
+    // the rollback simulation collides everything itself
+    if rollback.enabled() {
+        return;
+    }
+
+    let Some(tick) = client_tick.tick else {
+        return;
+    };
+    if client_tick.pause > 0 {
+        return;
+    }
+
+    for (mut player, t) in query_player.iter_mut() {
+        let pos = [t.translation.x, t.translation.y];
+
+        // only the object is predicted, powers wait for the server's PickUp
+        while let Some((power_up, _)) = objects.field.take_power_up(pos) {
+            predictions.predict(tick, power_up);
+        }
+
+        if let Some(hit) = objects.field.take_damage(pos, player.score) {
+            predictions.predict(tick, hit);
+        }
+
+        let reach = player.powers.reach(tick);
+        while let Some(pickup) = objects.field.take_within(pos, Effect::Score, reach) {
+            predictions.predict(tick, pickup);
+            player.score += 1;
+        }
+    }
+}
+
+// Undoes predictions the server never confirmed: the objects are put back
+// where they would be by now and predicted pickups are taken back.
+pub fn roll_back(
+    expired: Vec<Prediction>,
+    player: &mut Player,
+    objects: &mut Objects,
+    predictions: &mut Predictions,
+    now: u64,
+) {
+    for prediction in expired {
+        let def = prediction.object.id.kind.def();
+        let mut object = prediction.object;
+        if let Some(rng_seed) = objects.rng_seed {
+            for tick in prediction.tick + 1..=now {
+                def.advance(&mut object, objects.field.mode(), rng_seed, tick);
+            }
+        }
+
+        match def.effect {
+            Effect::Damage => predictions.flash = 0,
+            Effect::Score => player.score = player.score.saturating_sub(1),
+            Effect::PowerUp(_) => {}
+        }
+
+        if within_bounds(object.pos) {
+            objects.field.insert(object);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use satrunner_sim::{GameMode, Object, ObjectId, ObjectKind};
+
+    use super::roll_back;
+    use crate::game_util::resources::{Objects, Predictions, PREDICTION_SLACK};
+
+    fn player() -> super::Player {
+        super::Player {
+            target: Default::default(),
+            last_direction: None,
+            id: Default::default(),
+            score: 3,
+            pending_inputs: Vec::new(),
+            name: String::new(),
+            spawn_time: None,
+            death_time: None,
+            powers: Default::default(),
+        }
+    }
+
+    #[test]
+    fn resolved_predictions_are_not_rolled_back() {
+        let mut predictions = Predictions::new();
+        let bolt = Object {
+            id: ObjectId::new(ObjectKind::Bolt, 40, 0),
+            pos: [0.0, 100.0],
+        };
+        predictions.predict(50, bolt);
+
+        assert!(!predictions.resolve(ObjectId::new(ObjectKind::Rain, 40, 0)));
+        assert!(!predictions.resolve(ObjectId::new(ObjectKind::Bolt, 40, 1)));
+        assert!(predictions.resolve(bolt.id));
+        assert!(predictions.expire(100).is_empty());
+    }
+
+    #[test]
+    fn unconfirmed_predictions_roll_back() {
+        let mut predictions = Predictions::new();
+        let mut objects = Objects::new();
+        objects.rng_seed = Some(7);
+        let mut player = player();
+        let bolt = Object {
+            id: ObjectId::new(ObjectKind::Bolt, 40, 0),
+            pos: [0.0, 100.0],
+        };
+        predictions.predict(50, bolt);
+        player.score += 1;
+
+        assert!(predictions.expire(50 + PREDICTION_SLACK).is_empty());
+        let expired = predictions.expire(51 + PREDICTION_SLACK);
+        assert_eq!(expired.len(), 1);
+
+        roll_back(expired, &mut player, &mut objects, &mut predictions, 54);
+        assert_eq!(player.score, 3);
+        let bolts = objects.field.get(ObjectKind::Bolt);
+        assert_eq!(bolts.len(), 1);
+        let mut expected = bolt;
+        for tick in 51..=54 {
+            ObjectKind::Bolt
+                .def()
+                .advance(&mut expected, GameMode::Classic, 7, tick);
+        }
+        assert_eq!(bolts[0], expected);
+    }
+}
@@ -2,7 +2,7 @@ use std::time::Duration;
 
 use crate::game_util::{
     components::{NamePlates, NamePlatesLocal},
-    resources::ClientTick,
+    resources::{ClientTick, Predictions, RemoteSmoothing, Rollback, SmoothingMode},
 };
 use bevy::{prelude::*, utils::Instant};
 
@@ -12,10 +12,16 @@ pub fn player_loop(
     mut query_player: Query<(&mut Transform, &mut Player, &mut Sprite)>,
     mut query_text: Query<&mut Text, With<NamePlatesLocal>>,
     client_tick: Res<ClientTick>,
+    predictions: Res<Predictions>,
+    rollback: Res<Rollback>,
 ) {
     for (mut t, mut player, mut sprite) in query_player.iter_mut() {
         t.translation.z = 1.0;
-        sprite.color = default();
+        sprite.color = if predictions.flash > 0 {
+            Color::RED
+        } else {
+            default()
+        };
 
         let duration = Instant::now() - player.spawn_time.unwrap();
         let seconds = duration.as_secs();
@@ -30,7 +36,9 @@ pub fn player_loop(
             );
         }
 
-        player.apply_input(&mut t, &client_tick);
+        if !rollback.enabled() {
+            player.apply_input(&mut t, &client_tick);
+        }
     }
 }
 
@@ -38,6 +46,8 @@ pub fn enemy_loop(
     mut query_enemy: Query<(&mut Transform, &mut Enemy)>,
     mut query_text: Query<(&mut Text, &NamePlates)>,
     client_tick: Res<ClientTick>,
+    smoothing: Res<RemoteSmoothing>,
+    rollback: Res<Rollback>,
 ) {
     for (mut t, mut enemy) in query_enemy.iter_mut() {
         enemy.spawn_time.tick(Duration::from_millis(100));
@@ -58,14 +68,48 @@ pub fn enemy_loop(
             }
         }
 
-        enemy.apply_input(&mut t, &client_tick);
+        if smoothing.mode == SmoothingMode::Extrapolate && !rollback.enabled() {
+            enemy.apply_input(&mut t, &client_tick);
+        }
+    }
+}
+
+// runs every frame so remote players move between fixed ticks too
+pub fn interpolate_enemies(
+    mut query_enemy: Query<(&mut Transform, &Enemy)>,
+    client_tick: Res<ClientTick>,
+    smoothing: Res<RemoteSmoothing>,
+    fixed_time: Res<FixedTime>,
+    rollback: Res<Rollback>,
+) {
+    if smoothing.mode != SmoothingMode::Interpolate || rollback.enabled() {
+        return;
+    }
+    let Some(tick) = client_tick.tick else {
+        return;
+    };
+
+    let progress = fixed_time.accumulated().as_secs_f32() / fixed_time.period.as_secs_f32();
+    let Some(render_tick) = smoothing.render_tick(tick as f32 + progress.min(1.0)) else {
+        return;
+    };
+
+    for (mut t, enemy) in query_enemy.iter_mut() {
+        if let Some(pos) = enemy.sample(render_tick, smoothing.max_extrapolation) {
+            t.translation.x = pos.x;
+            t.translation.y = pos.y;
+        }
     }
 }
 
-pub fn tick(mut client_tick: ResMut<ClientTick>) {
+pub fn tick(mut client_tick: ResMut<ClientTick>, mut fixed_time: ResMut<FixedTime>) {
     if client_tick.pause > 0 {
         client_tick.pause -= 1;
     } else if let Some(tick) = &mut client_tick.tick {
         *tick += 1;
     }
+
+    let dilation = client_tick.dilation();
+    client_tick.drift -= dilation;
+    fixed_time.period = client_tick.period();
 }
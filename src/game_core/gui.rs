@@ -1,6 +1,7 @@
 use bevy::{prelude::*, utils::Instant};
 
 use names::Generator;
+use satrunner_sim::{Power, TICK_RATE};
 
 use bevy_egui::{
     egui::{self, Color32, RichText, TextEdit},
@@ -10,7 +11,10 @@ use bevy_egui::{
 use crate::{
     game_util::{
         components::NamePlatesLocal,
-        resources::{ClientTick, NetworkStuff, Objects, PingTimer, PlayerName},
+        resources::{
+            ClientTick, Latency, NetworkStuff, Objects, PingTimer, PlayerName, Reconnect, Region,
+            RegionProbes, ServerEndpoint,
+        },
     },
     network::messages::{ClientMessage, PlayerInput},
     GameStage, KeyboardState,
@@ -23,6 +27,7 @@ pub fn score_board(
     query_player: Query<&Player>,
     query_enemy: Query<&Enemy>,
     player_name: Res<PlayerName>,
+    latency: Res<Latency>,
 ) {
     let ctx = contexts.ctx_mut();
 
@@ -32,7 +37,7 @@ pub fn score_board(
 
     ctx.set_style(style);
 
-    let mut score_list: Vec<(String, i32, egui::Color32, u64, u64)> = Vec::new();
+    let mut score_list: Vec<(String, i32, egui::Color32, u64, u64, u16)> = Vec::new();
 
     if player_name.submitted {
         for player in query_player.iter() {
@@ -51,6 +56,7 @@ pub fn score_board(
                 egui::Color32::GREEN,
                 seconds,
                 minutes,
+                latency.srtt_ms.unwrap_or(0.0).round() as u16,
             ));
         }
     }
@@ -66,6 +72,7 @@ pub fn score_board(
                 egui::Color32::WHITE,
                 seconds,
                 minutes,
+                enemy.rtt_ms,
             ));
         }
     }
@@ -75,14 +82,15 @@ pub fn score_board(
     egui::Area::new("score_board")
         .fixed_pos(egui::pos2(10.0, 10.0))
         .show(ctx, |ui| {
-            for (id, score, color, secs, mins) in score_list {
+            for (id, score, color, secs, mins, rtt) in score_list {
                 ui.label(
                     RichText::new(format!(
-                        "{}: {:02}/21⚡ ({:02}:{:02})",
+                        "{}: {:02}/21⚡ ({:02}:{:02}) {}ms",
                         id,
                         score,
                         mins % 60,
                         secs % 60,
+                        rtt,
                     ))
                     .color(color),
                 );
@@ -91,6 +99,45 @@ pub fn score_board(
         });
 }
 
+// the local player's powers, with the time left on the timed ones
+pub fn power_hud(
+    mut contexts: EguiContexts,
+    query_player: Query<&Player>,
+    client_tick: Res<ClientTick>,
+) {
+    let Some(tick) = client_tick.tick else {
+        return;
+    };
+    let Ok(player) = query_player.get_single() else {
+        return;
+    };
+
+    let held: Vec<_> = Power::ALL
+        .into_iter()
+        .filter(|&power| player.powers.active(power, tick))
+        .collect();
+    if held.is_empty() {
+        return;
+    }
+
+    egui::Area::new("power_hud")
+        .anchor(egui::Align2::RIGHT_TOP, egui::vec2(-10.0, 10.0))
+        .show(contexts.ctx_mut(), |ui| {
+            for power in held {
+                let (label, color) = match power {
+                    Power::Shield => ("🛡 Shield", Color32::LIGHT_BLUE),
+                    Power::Speed => ("⚡ Speed", Color32::LIGHT_GREEN),
+                    Power::Magnet => ("🧲 Magnet", Color32::from_rgb(255, 80, 255)),
+                };
+                let text = match player.powers.remaining(power, tick) {
+                    Some(ticks) => format!("{} {:.1}s", label, ticks as f32 * TICK_RATE),
+                    None => label.to_string(),
+                };
+                ui.label(RichText::new(text).color(color));
+            }
+        });
+}
+
 #[allow(clippy::too_many_arguments, clippy::type_complexity)]
 pub fn setup_menu(
     mut contexts: EguiContexts,
@@ -101,20 +148,14 @@ pub fn setup_menu(
     client_tick: Res<ClientTick>,
     objects: Res<Objects>,
     mut keyboard_state: ResMut<NextState<KeyboardState>>,
+    mut endpoint: ResMut<ServerEndpoint>,
+    mut probes: ResMut<RegionProbes>,
 ) {
     if client_tick.tick.unwrap_or(0) % 10 == 0 {
         for (player, _, _) in query_player.iter_mut() {
             let input = PlayerInput::new([0.0, 0.0], player.id, client_tick.tick.unwrap(), false);
 
-            match network_stuff
-                .write
-                .as_mut()
-                .unwrap()
-                .try_send(ClientMessage::PlayerInput(input))
-            {
-                Ok(()) => {}
-                Err(e) => error!("Error sending message: {} CHANNEL FULL???", e),
-            };
+            network_stuff.send(ClientMessage::PlayerInput(input));
         }
     }
 
@@ -200,15 +241,7 @@ pub fn setup_menu(
                 if ui.button("Play").clicked() && !player_name.name.is_empty() {
                     keyboard_state.set(KeyboardState::Off);
                     player_name.submitted = true;
-                    match network_stuff
-                        .write
-                        .as_mut()
-                        .unwrap()
-                        .try_send(ClientMessage::PlayerName(player_name.name.clone()))
-                    {
-                        Ok(()) => {}
-                        Err(e) => error!("Error sending message: {} CHANNEL FULL???", e),
-                    };
+                    network_stuff.send(ClientMessage::PlayerName(player_name.name.clone()));
 
                     for (mut player, _, _) in query_player.iter_mut() {
                         player.spawn_time = Some(Instant::now());
@@ -218,10 +251,45 @@ pub fn setup_menu(
                     next_state.set(GameStage::InGame);
                 }
             });
+
+            if endpoint.regions.len() > 1 {
+                let mut pending = None;
+                ui.horizontal(|ui| {
+                    egui::ComboBox::from_id_source("region")
+                        .selected_text(region_label(&endpoint.regions[endpoint.selected]))
+                        .show_ui(ui, |ui| {
+                            for (index, region) in endpoint.regions.iter().enumerate() {
+                                if ui
+                                    .selectable_label(
+                                        index == endpoint.selected,
+                                        region_label(region),
+                                    )
+                                    .clicked()
+                                {
+                                    pending = Some(index);
+                                }
+                            }
+                        });
+                    if ui.button("Ping").clicked() {
+                        probes.refresh = true;
+                    }
+                });
+                if pending.is_some() {
+                    endpoint.pending = pending;
+                }
+            }
         });
 }
 
-pub fn disconnected(mut contexts: EguiContexts) {
+fn region_label(region: &Region) -> String {
+    match region.latency {
+        Some(latency) => format!("{} ({}ms)", region.name, latency.as_millis()),
+        None if region.probing => format!("{} (...)", region.name),
+        None => format!("{} (-)", region.name),
+    }
+}
+
+pub fn disconnected(mut contexts: EguiContexts, mut reconnect: ResMut<Reconnect>) {
     let ctx = contexts.ctx_mut();
 
     egui::Window::new("☔ rain.run              ")
@@ -230,15 +298,61 @@ pub fn disconnected(mut contexts: EguiContexts) {
         .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
         .show(ctx, |ui| {
             ui.label("disconnected");
+            match reconnect.retry_at {
+                Some(retry_at) => {
+                    let wait = retry_at.saturating_duration_since(Instant::now());
+                    ui.label(format!(
+                        "reconnecting in {}s (attempt {})",
+                        wait.as_secs() + 1,
+                        reconnect.attempt + 1
+                    ));
+                    if ui.button("Retry Now").clicked() {
+                        reconnect.retry_at = Some(Instant::now());
+                    }
+                }
+                None => {
+                    ui.label("reconnecting...");
+                }
+            }
+        });
+}
+
+pub fn out_of_date(mut contexts: EguiContexts) {
+    let ctx = contexts.ctx_mut();
+
+    egui::Window::new("☔ rain.run              ")
+        .resizable(false)
+        .collapsible(false)
+        .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+        .show(ctx, |ui| {
+            ui.label("client out of date, please reload");
+            #[cfg(target_arch = "wasm32")]
+            if ui.button("Reload").clicked() {
+                if let Some(window) = web_sys::window() {
+                    let _ = window.location().reload();
+                }
+            }
         });
 }
 
 pub fn check_disconnected(
     mut ping: ResMut<PingTimer>,
+    state: Res<State<GameStage>>,
     mut next_state: ResMut<NextState<GameStage>>,
+    mut reconnect: ResMut<Reconnect>,
+    time: Res<Time>,
 ) {
+    // an outdated client can't do anything useful with a new connection
+    if *state.get() == GameStage::OutOfDate {
+        return;
+    }
+
     if let Some(ref mut disconnected) = ping.disconnected_rx {
         while let Ok(Some(_)) = disconnected.try_next() {
+            if reconnect.resume_stage.is_none() && *state.get() != GameStage::Disconnected {
+                reconnect.resume_stage = Some(state.get().clone());
+            }
+            reconnect.schedule(time.raw_elapsed().as_nanos() as u64);
             next_state.set(GameStage::Disconnected);
         }
     }
@@ -259,15 +373,7 @@ pub fn game_over(
         for (_, player, _) in query_player.iter_mut() {
             let input = PlayerInput::new([0.0, 0.0], player.id, client_tick.tick.unwrap(), false);
 
-            match network_stuff
-                .write
-                .as_mut()
-                .unwrap()
-                .try_send(ClientMessage::PlayerInput(input))
-            {
-                Ok(()) => {}
-                Err(e) => error!("Error sending message: {} CHANNEL FULL???", e),
-            };
+            network_stuff.send(ClientMessage::PlayerInput(input));
         }
     }
 
@@ -305,17 +411,8 @@ pub fn game_over(
                                 );
                             }
                             if ui.button("Play Again").clicked() {
-                                match network_stuff
-                                    .write
-                                    .as_mut()
-                                    .unwrap()
-                                    .try_send(ClientMessage::PlayerName(player_name.name.clone()))
-                                {
-                                    Ok(()) => {}
-                                    Err(e) => {
-                                        error!("Error sending message: {} CHANNEL FULL???", e)
-                                    }
-                                };
+                                network_stuff
+                                    .send(ClientMessage::PlayerName(player_name.name.clone()));
                                 player.score = 0;
                                 player.spawn_time = Some(Instant::now());
                                 next_state.set(GameStage::InGame);
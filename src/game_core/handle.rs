@@ -1,20 +1,29 @@
-use bevy::{prelude::*, utils::HashSet};
+use std::time::Duration;
 
-use speedy::Readable;
+use bevy::{
+    prelude::*,
+    utils::{HashSet, Instant},
+};
+
+use satrunner_sim::{Effect, ObjectId, Power, Powers};
+use uuid::Uuid;
 
 use crate::{
     game_core::sprites::{spawn_enemies, spawn_player},
-    game_util::{
-        components::{Bolt, Rain},
-        resources::{BoltPool, ClientTick, NetworkStuff, Objects, RainPool},
+    game_util::resources::{
+        Checksums, ClientTick, Inbox, Latency, NetworkStuff, Objects, PlayerName, Predictions,
+        Protocol, Reconnect, RemoteSmoothing, Rollback, Session, SmoothingMode, SNAP_THRESHOLD,
     },
-    network::messages::NetworkMessage,
+    network::messages::{ClientMessage, NetworkMessage, ResumeRequest, CAP_RESUME},
     GameStage, KeyboardState,
 };
 
 use super::{
-    objects::{handle_bolt_behind, handle_rain_behind, ObjectPos},
+    checksums::{objects_tick, resync, verify},
+    collisions::roll_back,
+    objects::{handle_objects_behind, join_objects, ObjectBatches},
     player::{Enemy, Player},
+    rollback::confirm,
 };
 
 #[allow(clippy::too_many_arguments, clippy::type_complexity)]
@@ -24,204 +33,377 @@ pub fn handle_server(
     mut query_enemy: Query<(Entity, &mut Enemy, &mut Transform, &mut Visibility), Without<Player>>,
     mut commands: Commands,
     mut client_tick: ResMut<ClientTick>,
-    mut objects: ResMut<Objects>,
+    (mut objects, mut predictions, mut rollback, mut checksums): (
+        ResMut<Objects>,
+        ResMut<Predictions>,
+        ResMut<Rollback>,
+        ResMut<Checksums>,
+    ),
     asset_server: Res<AssetServer>,
     mut next_state: ResMut<NextState<GameStage>>,
-    mut rain_pool: ResMut<RainPool>,
-    mut rain: Query<(&Rain, &mut Visibility, &mut Transform), (Without<Player>, Without<Enemy>)>,
-    mut bolt_pool: ResMut<BoltPool>,
-    mut bolt: Query<
-        (&Bolt, &mut Visibility, &mut Transform),
-        (Without<Player>, Without<Enemy>, Without<Rain>),
-    >,
+    mut batches: ObjectBatches,
     mut keyboard_state: ResMut<NextState<KeyboardState>>,
     windows: Query<&Window>,
+    mut reconnect: ResMut<Reconnect>,
+    player_name: Res<PlayerName>,
+    (latency, protocol): (Res<Latency>, Res<Protocol>),
+    mut smoothing: ResMut<RemoteSmoothing>,
+    mut inbox: ResMut<Inbox>,
 ) {
-    if let Some(ref mut receive_rx) = incoming.read {
-        while let Ok(Some(message)) = receive_rx.try_next() {
-            match NetworkMessage::read_from_buffer(&message) {
-                Ok(NetworkMessage::GameUpdate(game_update)) => {
-                    for game_update in &game_update {
-                        for (mut player, mut t) in query_player.iter_mut() {
-                            if game_update.id == player.id {
-                                player.server_reconciliation(
-                                    &mut t,
-                                    &client_tick,
-                                    game_update.pos,
-                                    game_update.tick,
-                                );
-                            }
+    let incoming = &mut *incoming;
+    for message in inbox.0.drain(..) {
+        match message {
+            NetworkMessage::GameUpdate(game_update) => {
+                if let (Some(update), Some(tick)) = (game_update.first(), client_tick.tick) {
+                    smoothing.observe(tick, update.tick);
+                }
+                let rolled_back = match (client_tick.tick, objects.rng_seed) {
+                    (Some(now), Some(rng_seed)) if rollback.enabled() => confirm(
+                        &mut rollback,
+                        &game_update,
+                        query_player.iter().next().map(|(player, _)| player),
+                        now,
+                        rng_seed,
+                    ),
+                    _ => false,
+                };
+
+                for game_update in &game_update {
+                    for (mut player, mut t) in query_player.iter_mut() {
+                        if game_update.id == player.id && !rolled_back {
+                            player.server_reconciliation(
+                                &mut t,
+                                &client_tick,
+                                game_update.pos,
+                                game_update.tick,
+                            );
+                            roll_back(
+                                predictions.expire(game_update.tick),
+                                &mut player,
+                                &mut objects,
+                                &mut predictions,
+                                client_tick.tick.unwrap(),
+                            );
                         }
-                        for (_, mut enemy, mut t, _) in query_enemy.iter_mut() {
-                            if game_update.id == enemy.id {
-                                enemy.target.x = game_update.input[0];
-                                enemy.target.y = game_update.input[1];
+                    }
+                    for (_, mut enemy, mut t, _) in query_enemy.iter_mut() {
+                        if game_update.id == enemy.id {
+                            enemy.target.x = game_update.input[0];
+                            enemy.target.y = game_update.input[1];
+                            enemy.record(game_update.tick, game_update.pos);
+                            if smoothing.mode == SmoothingMode::Extrapolate && !rolled_back {
                                 enemy.enemy_reconciliation(
                                     &mut t,
                                     &client_tick,
                                     game_update.pos,
                                     game_update.tick,
+                                    latency.max_extrapolation_ticks(),
                                 );
                             }
                         }
                     }
                 }
-                Ok(NetworkMessage::GameState(player_state)) => {
-                    let current_player_ids: HashSet<_> =
-                        player_state.iter().map(|p| p.id).collect();
-                    let mut existing_entities = Vec::new();
+            }
+            NetworkMessage::GameState(player_state) => {
+                let current_player_ids: HashSet<_> = player_state.iter().map(|p| p.id).collect();
+                let mut existing_entities = Vec::new();
 
-                    for (player, _) in query_player.iter_mut() {
-                        existing_entities.push(player.id);
+                for (player, _) in query_player.iter_mut() {
+                    existing_entities.push(player.id);
+                }
+                for (entity, enemy, _, _) in query_enemy.iter_mut() {
+                    existing_entities.push(enemy.id);
+                    for entity_id in &existing_entities {
+                        if !current_player_ids.contains(entity_id) && entity_id == &enemy.id {
+                            commands.entity(entity).despawn_recursive();
+                        }
                     }
-                    for (entity, enemy, _, _) in query_enemy.iter_mut() {
-                        existing_entities.push(enemy.id);
-                        for entity_id in &existing_entities {
-                            if !current_player_ids.contains(entity_id) && entity_id == &enemy.id {
-                                commands.entity(entity).despawn_recursive();
-                            }
+                }
+
+                for player in player_state.clone() {
+                    for (mut local_player, _) in query_player.iter_mut() {
+                        if local_player.id == player.id {
+                            local_player.score = player.score;
+                        }
+                    }
+                    for (_, mut enemy, _, _) in query_enemy.iter_mut() {
+                        if enemy.id == player.id {
+                            enemy.score = player.score;
+                            enemy.rtt_ms = player.rtt_ms;
                         }
                     }
+                    if !existing_entities.contains(&player.id) {
+                        spawn_enemies(
+                            &mut commands,
+                            &player.id,
+                            Some(player.pos),
+                            Some(player.target),
+                            player.score,
+                            player.name,
+                            &asset_server,
+                            player.time_alive,
+                        );
+                    }
+                }
+            }
+            NetworkMessage::NewGame(new_game) => {
+                let tick = new_game.server_tick + latency.one_way_ticks();
+                client_tick.tick = Some(tick);
+                predictions.clear();
+                rollback.history.clear();
+                checksums.clear();
+                join_objects(&mut objects, &new_game, tick);
+                objects.high_scores = new_game.high_scores;
 
-                    for player in player_state.clone() {
-                        for (mut local_player, _) in query_player.iter_mut() {
-                            if local_player.id == player.id {
-                                local_player.score = player.score;
-                            }
+                reconnect.attempt = 0;
+                let session = Session {
+                    id: new_game.id,
+                    resume_token: new_game.resume_token,
+                };
+
+                match reconnect.resume_stage.take() {
+                    Some(stage) if !query_player.is_empty() => {
+                        for (mut player, _) in query_player.iter_mut() {
+                            player.id = new_game.id;
                         }
-                        for (_, mut enemy, _, _) in query_enemy.iter_mut() {
-                            if enemy.id == player.id {
-                                enemy.score = player.score;
+
+                        let previous = reconnect.session.replace(session);
+                        if stage == GameStage::InGame {
+                            if let Some(previous) =
+                                previous.filter(|_| protocol.supports(CAP_RESUME))
+                            {
+                                incoming.send(ClientMessage::Resume(ResumeRequest {
+                                    id: previous.id,
+                                    resume_token: previous.resume_token,
+                                }));
+                                reconnect.resuming = Some(previous);
                             }
+                            incoming.send(ClientMessage::PlayerName(player_name.name.clone()));
                         }
-                        if !existing_entities.contains(&player.id) {
-                            spawn_enemies(
-                                &mut commands,
-                                &player.id,
-                                Some(player.pos),
-                                Some(player.target),
-                                player.score,
-                                player.name,
-                                &asset_server,
-                                player.time_alive,
-                            );
+                        next_state.set(stage);
+                    }
+                    _ => {
+                        reconnect.session = Some(session);
+                        spawn_player(
+                            &mut commands,
+                            &new_game.id,
+                            &asset_server,
+                            &mut next_state,
+                            &mut keyboard_state,
+                            &windows,
+                        );
+                    }
+                }
+            }
+            NetworkMessage::Resumed(resumed) => {
+                let previous = reconnect.resuming.take();
+
+                for (mut player, mut t) in query_player.iter_mut() {
+                    match &resumed {
+                        Some(state) => {
+                            player.id = state.id;
+                            player.score = state.score;
+                            player.spawn_time = Instant::now()
+                                .checked_sub(Duration::from_secs(state.secs_alive))
+                                .or(player.spawn_time);
+                            t.translation.x = state.pos[0];
+                            t.translation.y = state.pos[1];
+                            player.target = t.translation.truncate();
+                            player.pending_inputs.clear();
                         }
+                        None => {
+                            player.score = 0;
+                            player.powers = Powers::default();
+                            player.spawn_time = Some(Instant::now());
+                            player.death_time = None;
+                        }
+                    }
+                }
+
+                if resumed.is_some() {
+                    if let Some(previous) = previous {
+                        reconnect.session = Some(previous);
                     }
                 }
-                Ok(NetworkMessage::NewGame(new_game)) => {
-                    client_tick.tick = Some(new_game.server_tick);
-                    objects.rng_seed = Some(new_game.rng_seed);
-                    objects.high_scores = new_game.high_scores;
-
-                    objects.rain_pos = new_game
-                        .objects
-                        .rain_pos
-                        .iter()
-                        .map(|&(tick, [x, y])| ObjectPos {
-                            tick,
-                            pos: Vec3 { x, y, z: 0.0 },
-                        })
-                        .collect();
-
-                    objects.bolt_pos = new_game
-                        .objects
-                        .bolt_pos
-                        .iter()
-                        .map(|&(tick, [x, y])| ObjectPos {
-                            tick,
-                            pos: Vec3 { x, y, z: 0.0 },
-                        })
-                        .collect();
-
-                    spawn_player(
-                        &mut commands,
-                        &new_game.id,
-                        &asset_server,
-                        &mut next_state,
-                        &mut keyboard_state,
-                        &windows,
+            }
+            NetworkMessage::DamagePlayer(damage) => {
+                if let Some(object) = damage.object {
+                    object_taken(
+                        object.into(),
+                        None,
+                        &mut objects,
+                        &mut predictions,
+                        &mut rollback,
+                        &mut checksums,
                     );
                 }
-                Ok(NetworkMessage::DamagePlayer(damage)) => {
-                    if let Some(index) = objects
-                        .rain_pos
-                        .iter()
-                        .position(|object| object.tick == damage.tick.unwrap())
-                    {
-                        objects.rain_pos.remove(index);
-                    }
 
-                    if let Some(high_scores) = damage.high_scores {
-                        objects.high_scores = high_scores;
+                if let Some(high_scores) = damage.high_scores {
+                    objects.high_scores = high_scores;
+                }
+
+                for (mut player, mut t) in query_player.iter_mut() {
+                    if damage.id == player.id {
+                        // the run is over, whatever else we predicted doesn't matter
+                        predictions.clear();
+                        rollback.history.clear();
+                        t.translation = Vec3::ZERO;
+                        player.death_time = Some(damage.secs_alive);
+                        player.score = damage.score;
+                        player.powers = Powers::default();
+                        player.target = t.translation.truncate();
+                        next_state.set(GameStage::GameOver);
+                    }
+                }
+            }
+            NetworkMessage::ScoreUpdate(score) => {
+                let predicted = object_taken(
+                    score.object.into(),
+                    Some((score.id, score.score)),
+                    &mut objects,
+                    &mut predictions,
+                    &mut rollback,
+                    &mut checksums,
+                );
+                for (mut player, _t) in query_player.iter_mut() {
+                    if score.id == player.id {
+                        player.score = score.score + predictions.pending(Effect::Score);
+                    } else if predicted {
+                        // someone else got there first
+                        player.score = player.score.saturating_sub(1);
                     }
+                }
+                for (_entity, mut enemy, _t, _) in query_enemy.iter_mut() {
+                    if score.id == enemy.id {
+                        enemy.score = score.score;
+                    }
+                }
+            }
+            NetworkMessage::PickUp(pick_up) => {
+                if let Some(object) = pick_up.object {
+                    object_taken(
+                        object.into(),
+                        None,
+                        &mut objects,
+                        &mut predictions,
+                        &mut rollback,
+                        &mut checksums,
+                    );
+                }
 
-                    for (mut player, mut t) in query_player.iter_mut() {
-                        if damage.id == player.id {
-                            t.translation = Vec3::ZERO;
-                            player.death_time = Some(damage.secs_alive);
-                            player.score = damage.score;
-                            player.target = t.translation.truncate();
-                            next_state.set(GameStage::GameOver);
-                        }
+                let power = Power::from(pick_up.power);
+                for (mut player, _t) in query_player.iter_mut() {
+                    if pick_up.id == player.id {
+                        player.powers.set(power, pick_up.until);
                     }
                 }
-                Ok(NetworkMessage::ScoreUpdate(score)) => {
-                    if let Some(index) = objects
-                        .bolt_pos
-                        .iter()
-                        .position(|object| object.tick == score.tick)
-                    {
-                        objects.bolt_pos.remove(index);
+                for (_entity, mut enemy, _t, _) in query_enemy.iter_mut() {
+                    if pick_up.id == enemy.id {
+                        enemy.powers.set(power, pick_up.until);
                     }
+                }
+            }
+            NetworkMessage::PowerExpired(expired) => {
+                if let Some(object) = expired.absorbed {
+                    object_taken(
+                        object.into(),
+                        None,
+                        &mut objects,
+                        &mut predictions,
+                        &mut rollback,
+                        &mut checksums,
+                    );
+                }
 
-                    for (mut player, _t) in query_player.iter_mut() {
-                        if score.id == player.id {
-                            player.score = score.score;
-                        }
+                let power = Power::from(expired.power);
+                for (mut player, _t) in query_player.iter_mut() {
+                    if expired.id == player.id {
+                        player.powers.clear(power);
                     }
-                    for (_entity, mut enemy, _t, _) in query_enemy.iter_mut() {
-                        if score.id == enemy.id {
-                            enemy.score = score.score;
-                        }
+                }
+                for (_entity, mut enemy, _t, _) in query_enemy.iter_mut() {
+                    if expired.id == enemy.id {
+                        enemy.powers.clear(power);
                     }
                 }
-                Ok(NetworkMessage::SyncClient(sync_client)) => {
-                    for (mut player, mut t) in query_player.iter_mut() {
-                        if sync_client.tick_adjustment > 0
-                            && client_tick.tick.unwrap() > sync_client.server_tick
-                        {
-                            client_tick.pause = sync_client.tick_adjustment;
-                        } else if sync_client.tick_adjustment < 0
-                            && client_tick.tick.unwrap() < sync_client.server_tick
-                        {
-                            let mut ticks_behind = sync_client.tick_adjustment;
-
-                            while ticks_behind < 0 {
-                                handle_rain_behind(
-                                    &mut objects,
-                                    &mut rain_pool,
-                                    &mut rain,
-                                    &client_tick,
-                                );
-                                handle_bolt_behind(
-                                    &mut objects,
-                                    &mut bolt_pool,
-                                    &mut bolt,
-                                    &client_tick,
-                                );
-                                player.apply_input(&mut t, &client_tick);
-                                ticks_behind += 1;
+            }
+            NetworkMessage::Checksum(sent) => {
+                if let Some(local) = verify(&checksums, sent) {
+                    checksums.desyncs += 1;
+                    warn!(
+                        "checksum mismatch at tick {}: server {:016x}, here {:016x}",
+                        sent.tick, sent.hash, local
+                    );
+                    if checksums.pending.is_none() {
+                        checksums.pending = Some(sent.tick);
+                        incoming.send(ClientMessage::RequestObjects(sent.tick));
+                    }
+                }
+            }
+            NetworkMessage::Objects(sync) => {
+                if let Some(now) = objects_tick(&client_tick, &rollback) {
+                    resync(&mut checksums, &mut objects, sync, now);
+                    // the rollback simulation picks the new objects up from here
+                    rollback.history.clear();
+                }
+            }
+            NetworkMessage::SyncClient(sync_client) => {
+                // small corrections are eased in by the tick system, see ClientTick::dilation
+                if sync_client.tick_adjustment.abs() <= SNAP_THRESHOLD {
+                    client_tick.drift = sync_client.tick_adjustment as f32;
+                    continue;
+                }
+
+                client_tick.drift = 0.0;
+                for (mut player, mut t) in query_player.iter_mut() {
+                    if sync_client.tick_adjustment > 0
+                        && client_tick.tick.unwrap() > sync_client.server_tick
+                    {
+                        client_tick.pause = sync_client.tick_adjustment;
+                    } else if sync_client.tick_adjustment < 0
+                        && client_tick.tick.unwrap() < sync_client.server_tick
+                    {
+                        let mut ticks_behind = sync_client.tick_adjustment;
+                        handle_objects_behind(
+                            &mut objects,
+                            &mut batches,
+                            &client_tick,
+                            ticks_behind.unsigned_abs(),
+                            player.score,
+                        );
+
+                        while ticks_behind < 0 {
+                            player.apply_input(&mut t, &client_tick);
+                            ticks_behind += 1;
 
-                                if let Some(tick) = &mut client_tick.tick {
-                                    *tick += 1;
-                                }
+                            if let Some(tick) = &mut client_tick.tick {
+                                *tick += 1;
                             }
                         }
                     }
                 }
-                Ok(NetworkMessage::Ping) => {}
-                Err(_) => {}
             }
+            NetworkMessage::Welcome(_)
+            | NetworkMessage::Ping(_)
+            | NetworkMessage::Pong(_)
+            | NetworkMessage::Snapshot(_)
+            | NetworkMessage::InputAck(_) => {}
         }
     }
 }
+
+// The server took the object `id` off the field, true if we had predicted
+// that ourselves.
+fn object_taken(
+    id: ObjectId,
+    score: Option<(Uuid, usize)>,
+    objects: &mut Objects,
+    predictions: &mut Predictions,
+    rollback: &mut Rollback,
+    checksums: &mut Checksums,
+) -> bool {
+    objects.field.remove(id);
+    rollback.server_event(id, score);
+    checksums.remove_object(id);
+    predictions.resolve(id)
+}
@@ -4,8 +4,8 @@ use virtual_joystick::{
 };
 
 use crate::{
-    game_util::resources::{ClientTick, NetworkStuff},
-    network::messages::{ClientMessage, PlayerInput},
+    game_util::resources::{ClientTick, InputSync, NetworkStuff, Protocol, INPUT_REDUNDANCY},
+    network::messages::{ClientMessage, PlayerInput, CAP_INPUT_BATCH},
 };
 
 use super::player::Player;
@@ -16,7 +16,7 @@ pub fn input(
     mouse: Res<Input<MouseButton>>,
     camera_query: Query<(&Camera, &GlobalTransform)>,
     windows: Query<&Window>,
-    mut outgoing: ResMut<NetworkStuff>,
+    mut input_sync: ResMut<InputSync>,
     client_tick: Res<ClientTick>,
     touches: Res<Touches>,
 ) {
@@ -50,17 +50,8 @@ pub fn input(
                     true,
                 );
 
-                player.pending_inputs.push(input.clone());
-
-                match outgoing
-                    .write
-                    .as_mut()
-                    .unwrap()
-                    .try_send(ClientMessage::PlayerInput(input))
-                {
-                    Ok(()) => {}
-                    Err(e) => error!("Error sending message: {} CHANNEL FULL???", e),
-                };
+                player.push_input(input);
+                input_sync.dirty = true;
             };
 
             if mouse.just_pressed(MouseButton::Left) || mouse.just_pressed(MouseButton::Right) {
@@ -86,7 +77,7 @@ pub fn update_joystick(
     mut joystick: EventReader<VirtualJoystickEvent<String>>,
     mut joystick_color: Query<(&mut TintColor, &VirtualJoystickNode<String>)>,
     mut query: Query<(&Transform, &mut Player)>,
-    mut outgoing: ResMut<NetworkStuff>,
+    mut input_sync: ResMut<InputSync>,
     client_tick: Res<ClientTick>,
 ) {
     if client_tick.pause == 0 {
@@ -161,19 +152,160 @@ pub fn update_joystick(
                         true,
                     );
 
-                    player.pending_inputs.push(input.clone());
-
-                    match outgoing
-                        .write
-                        .as_mut()
-                        .unwrap()
-                        .try_send(ClientMessage::PlayerInput(input))
-                    {
-                        Ok(()) => {}
-                        Err(e) => error!("Error sending message: {} CHANNEL FULL???", e),
-                    };
+                    player.push_input(input);
+                    input_sync.dirty = true;
                 }
             }
         }
     }
 }
+
+// Goes out at most once a tick: whatever input is new, along with anything
+// still unacked until the server confirms it. Input from later frames of a
+// tick that was already sent waits for the next one.
+pub fn send_inputs(
+    query: Query<&Player>,
+    mut input_sync: ResMut<InputSync>,
+    mut outgoing: ResMut<NetworkStuff>,
+    client_tick: Res<ClientTick>,
+    protocol: Res<Protocol>,
+) {
+    if client_tick.tick.is_none() || input_sync.last_sent_tick == client_tick.tick {
+        return;
+    }
+
+    for player in query.iter() {
+        let unacked: Vec<_> = player
+            .pending_inputs
+            .iter()
+            .filter(|input| !input_sync.is_acked(input))
+            .collect();
+        if unacked.is_empty() || (!input_sync.dirty && !protocol.supports(CAP_INPUT_BATCH)) {
+            continue;
+        }
+
+        if protocol.supports(CAP_INPUT_BATCH) {
+            let batch = unacked[unacked.len().saturating_sub(INPUT_REDUNDANCY)..]
+                .iter()
+                .map(|input| (*input).clone())
+                .collect();
+            outgoing.send(ClientMessage::InputBatch(batch));
+        } else if let Some(input) = unacked.last() {
+            outgoing.send(ClientMessage::PlayerInput((*input).clone()));
+        }
+        input_sync.last_sent_tick = client_tick.tick;
+    }
+
+    input_sync.dirty = false;
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::*;
+    use futures::channel::mpsc::{channel, Receiver};
+    use uuid::Uuid;
+
+    use super::send_inputs;
+    use crate::{
+        game_core::player::Player,
+        game_util::resources::{ClientTick, InputSync, NetworkStuff, Protocol, MAX_PENDING_INPUTS},
+        network::messages::{ClientMessage, Handshake, PlayerInput, CLIENT_CAPABILITIES},
+    };
+
+    fn app() -> (App, Receiver<ClientMessage>) {
+        let (write, read) = channel(100);
+        let mut network_stuff = NetworkStuff::new();
+        network_stuff.write = Some(write);
+        let mut protocol = Protocol::new();
+        protocol.server = Some(Handshake {
+            capabilities: CLIENT_CAPABILITIES,
+            ..Handshake::client()
+        });
+
+        let mut app = App::new();
+        app.insert_resource(network_stuff)
+            .insert_resource(protocol)
+            .insert_resource(InputSync::new())
+            .insert_resource(ClientTick::new())
+            .add_systems(Update, send_inputs);
+        app.world.spawn(Player {
+            target: Vec2::ZERO,
+            last_direction: None,
+            id: Uuid::nil(),
+            score: 0,
+            pending_inputs: Vec::new(),
+            name: String::new(),
+            spawn_time: None,
+            death_time: None,
+            powers: Default::default(),
+        });
+        (app, read)
+    }
+
+    // one frame of input on `tick`
+    fn frame(app: &mut App, tick: u64, target: f32) {
+        app.world.resource_mut::<ClientTick>().tick = Some(tick);
+        let mut players = app.world.query::<&mut Player>();
+        let mut player = players.single_mut(&mut app.world);
+        let input = PlayerInput::new([target, 0.0], Uuid::nil(), tick, true);
+        player.push_input(input);
+        app.world.resource_mut::<InputSync>().dirty = true;
+        app.update();
+    }
+
+    fn sent(read: &mut Receiver<ClientMessage>) -> Vec<Vec<u64>> {
+        std::iter::from_fn(|| read.try_next().ok().flatten())
+            .map(|message| match message {
+                ClientMessage::InputBatch(batch) => batch.iter().map(|input| input.tick).collect(),
+                other => panic!("unexpected message: {:?}", other),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn inputs_go_out_once_a_tick() {
+        let (mut app, mut read) = app();
+        for target in 0..6 {
+            frame(&mut app, 10, target as f32);
+        }
+        assert_eq!(sent(&mut read).len(), 1);
+
+        // the later frames of tick 10 go out with tick 11
+        frame(&mut app, 11, 7.0);
+        frame(&mut app, 11, 8.0);
+        let batches = sent(&mut read);
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].last(), Some(&11));
+
+        // nothing acked, so tick 12 repeats the tail without any new input
+        app.world.resource_mut::<ClientTick>().tick = Some(12);
+        app.update();
+        app.update();
+        assert_eq!(sent(&mut read).len(), 1);
+
+        app.world.resource_mut::<InputSync>().acked = Some(11);
+        app.world.resource_mut::<ClientTick>().tick = Some(13);
+        app.update();
+        assert!(sent(&mut read).is_empty());
+    }
+
+    #[test]
+    fn unacked_inputs_stay_bounded_without_batching() {
+        let (mut app, mut read) = app();
+        app.world.resource_mut::<Protocol>().server = Some(Handshake {
+            capabilities: 0,
+            ..Handshake::client()
+        });
+        for tick in 0..200 {
+            frame(&mut app, tick, tick as f32);
+            while let Ok(Some(message)) = read.try_next() {
+                assert!(matches!(message, ClientMessage::PlayerInput(_)));
+            }
+        }
+
+        let mut players = app.world.query::<&Player>();
+        let pending = &players.single(&app.world).pending_inputs;
+        assert_eq!(pending.len(), MAX_PENDING_INPUTS);
+        assert_eq!(pending.last().map(|input| input.tick), Some(199));
+    }
+}
@@ -0,0 +1,429 @@
+use std::mem::size_of;
+
+use bevy::{
+    core_pipeline::{
+        core_2d::Transparent2d,
+        tonemapping::{DebandDither, Tonemapping},
+    },
+    ecs::{
+        query::ROQueryItem,
+        system::{
+            lifetimeless::{Read, SRes},
+            SystemParamItem,
+        },
+    },
+    prelude::*,
+    reflect::TypeUuid,
+    render::{
+        mesh::{GpuBufferInfo, MeshVertexBufferLayout},
+        render_asset::RenderAssets,
+        render_phase::{
+            AddRenderCommand, DrawFunctions, PhaseItem, RenderCommand, RenderCommandResult,
+            RenderPhase, SetItemPipeline, TrackedRenderPass,
+        },
+        render_resource::{
+            BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout,
+            BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingResource, BindingType,
+            BufferUsages, BufferVec, PipelineCache, RenderPipelineDescriptor, SamplerBindingType,
+            ShaderStages, SpecializedMeshPipeline, SpecializedMeshPipelineError,
+            SpecializedMeshPipelines, TextureSampleType, TextureViewDimension, VertexAttribute,
+            VertexBufferLayout, VertexFormat, VertexStepMode,
+        },
+        renderer::{RenderDevice, RenderQueue},
+        view::{ExtractedView, VisibleEntities},
+        Extract, Render, RenderApp, RenderSet,
+    },
+    sprite::{
+        Mesh2dHandle, Mesh2dPipeline, Mesh2dPipelineKey, Mesh2dUniform, SetMesh2dBindGroup,
+        SetMesh2dViewBindGroup,
+    },
+    utils::FloatOrd,
+};
+use satrunner_sim::{ObjectKind, OBJECTS};
+
+use crate::game_util::components::{ObjectBatch, ObjectInstance, ObjectInstances};
+
+const OBJECT_SHADER_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 4_381_209_715_662_405_117);
+
+// Draws every object batch as instances of its kind's quad: the quad is
+// uploaded once, and a tick only writes one position per object.
+pub struct ObjectInstancingPlugin;
+
+impl Plugin for ObjectInstancingPlugin {
+    fn build(&self, app: &mut App) {
+        let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+        render_app
+            .add_render_command::<Transparent2d, DrawObjects>()
+            .init_resource::<SpecializedMeshPipelines<ObjectPipeline>>()
+            .init_resource::<GpuObjectBatches>()
+            .add_systems(ExtractSchedule, extract_object_batches)
+            .add_systems(
+                Render,
+                (
+                    prepare_object_batches.in_set(RenderSet::Prepare),
+                    queue_object_batches.in_set(RenderSet::Queue),
+                ),
+            );
+
+        app.world.resource_mut::<Assets<Shader>>().set_untracked(
+            OBJECT_SHADER_HANDLE,
+            Shader::from_wgsl(include_str!("objects.wgsl"), file!()),
+        );
+    }
+
+    fn finish(&self, app: &mut App) {
+        if let Ok(render_app) = app.get_sub_app_mut(RenderApp) {
+            render_app.init_resource::<ObjectPipeline>();
+        }
+    }
+}
+
+// a batch in the render world, the quad and uniform come from extract_mesh2d
+#[derive(Component)]
+struct ExtractedObjectBatch {
+    kind: ObjectKind,
+    sprite: Handle<Image>,
+}
+
+// what the GPU holds for one kind, kept across frames so the instance buffer
+// is only reallocated when a kind has more objects in view than ever before
+struct GpuObjectBatch {
+    instances: BufferVec<ObjectInstance>,
+    changed: bool,
+    sprite: Option<BindGroup>,
+}
+
+#[derive(Resource)]
+struct GpuObjectBatches(Vec<GpuObjectBatch>);
+
+impl Default for GpuObjectBatches {
+    fn default() -> Self {
+        Self(
+            OBJECTS
+                .iter()
+                .map(|_| GpuObjectBatch {
+                    instances: BufferVec::new(BufferUsages::VERTEX),
+                    changed: false,
+                    sprite: None,
+                })
+                .collect(),
+        )
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn extract_object_batches(
+    mut commands: Commands,
+    mut gpu_batches: ResMut<GpuObjectBatches>,
+    mut previous_len: Local<usize>,
+    batches: Extract<
+        Query<(
+            Entity,
+            &ObjectBatch,
+            &Handle<Image>,
+            Ref<ObjectInstances>,
+            &ComputedVisibility,
+        )>,
+    >,
+) {
+    let mut values = Vec::with_capacity(*previous_len);
+    for (entity, batch, sprite, instances, computed_visibility) in &batches {
+        // the instances only go to the GPU on the frames a tick rewrote them
+        if instances.is_changed() {
+            let gpu_batch = &mut gpu_batches.0[batch.0 as usize];
+            gpu_batch.instances.clear();
+            gpu_batch.instances.extend(instances.0.iter().copied());
+            gpu_batch.changed = true;
+        }
+        if computed_visibility.is_visible() {
+            values.push((
+                entity,
+                ExtractedObjectBatch {
+                    kind: batch.0,
+                    sprite: sprite.clone_weak(),
+                },
+            ));
+        }
+    }
+    *previous_len = values.len();
+    commands.insert_or_spawn_batch(values);
+}
+
+fn prepare_object_batches(
+    mut gpu_batches: ResMut<GpuObjectBatches>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+) {
+    for gpu_batch in gpu_batches.0.iter_mut().filter(|batch| batch.changed) {
+        gpu_batch
+            .instances
+            .write_buffer(&render_device, &render_queue);
+        gpu_batch.changed = false;
+    }
+}
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn queue_object_batches(
+    transparent_draw_functions: Res<DrawFunctions<Transparent2d>>,
+    object_pipeline: Res<ObjectPipeline>,
+    mut pipelines: ResMut<SpecializedMeshPipelines<ObjectPipeline>>,
+    pipeline_cache: Res<PipelineCache>,
+    msaa: Res<Msaa>,
+    render_device: Res<RenderDevice>,
+    render_meshes: Res<RenderAssets<Mesh>>,
+    images: Res<RenderAssets<Image>>,
+    mut gpu_batches: ResMut<GpuObjectBatches>,
+    batches: Query<(&ExtractedObjectBatch, &Mesh2dHandle, &Mesh2dUniform)>,
+    mut views: Query<(
+        &ExtractedView,
+        &VisibleEntities,
+        Option<&Tonemapping>,
+        Option<&DebandDither>,
+        &mut RenderPhase<Transparent2d>,
+    )>,
+) {
+    for (batch, _, _) in &batches {
+        let Some(image) = images.get(&batch.sprite) else {
+            continue;
+        };
+        gpu_batches.0[batch.kind as usize].sprite =
+            Some(render_device.create_bind_group(&BindGroupDescriptor {
+                label: Some("object_sprite_bind_group"),
+                layout: &object_pipeline.sprite_layout,
+                entries: &[
+                    BindGroupEntry {
+                        binding: 0,
+                        resource: BindingResource::TextureView(&image.texture_view),
+                    },
+                    BindGroupEntry {
+                        binding: 1,
+                        resource: BindingResource::Sampler(&image.sampler),
+                    },
+                ],
+            }));
+    }
+
+    let draw_objects = transparent_draw_functions.read().id::<DrawObjects>();
+    for (view, visible_entities, tonemapping, dither, mut transparent_phase) in &mut views {
+        let view_key = view_key(view, &msaa, tonemapping, dither);
+        for &entity in &visible_entities.entities {
+            let Ok((batch, mesh_handle, mesh_uniform)) = batches.get(entity) else {
+                continue;
+            };
+            let gpu_batch = &gpu_batches.0[batch.kind as usize];
+            if gpu_batch.sprite.is_none() || gpu_batch.instances.is_empty() {
+                continue;
+            }
+            let Some(mesh) = render_meshes.get(&mesh_handle.0) else {
+                continue;
+            };
+
+            let key =
+                view_key | Mesh2dPipelineKey::from_primitive_topology(mesh.primitive_topology);
+            let pipeline =
+                match pipelines.specialize(&pipeline_cache, &object_pipeline, key, &mesh.layout) {
+                    Ok(pipeline) => pipeline,
+                    Err(err) => {
+                        error!("{}", err);
+                        continue;
+                    }
+                };
+            transparent_phase.add(Transparent2d {
+                entity,
+                draw_function: draw_objects,
+                pipeline,
+                sort_key: FloatOrd(mesh_uniform.transform.w_axis.z),
+                // each kind is a single instanced draw already
+                batch_range: None,
+            });
+        }
+    }
+}
+
+// the parts of the pipeline key that depend on the camera, as bevy's own
+// mesh2d materials work them out
+fn view_key(
+    view: &ExtractedView,
+    msaa: &Msaa,
+    tonemapping: Option<&Tonemapping>,
+    dither: Option<&DebandDither>,
+) -> Mesh2dPipelineKey {
+    let mut key = Mesh2dPipelineKey::from_msaa_samples(msaa.samples())
+        | Mesh2dPipelineKey::from_hdr(view.hdr);
+    if view.hdr {
+        return key;
+    }
+    if let Some(tonemapping) = tonemapping {
+        key |= Mesh2dPipelineKey::TONEMAP_IN_SHADER;
+        key |= match tonemapping {
+            Tonemapping::None => Mesh2dPipelineKey::TONEMAP_METHOD_NONE,
+            Tonemapping::Reinhard => Mesh2dPipelineKey::TONEMAP_METHOD_REINHARD,
+            Tonemapping::ReinhardLuminance => Mesh2dPipelineKey::TONEMAP_METHOD_REINHARD_LUMINANCE,
+            Tonemapping::AcesFitted => Mesh2dPipelineKey::TONEMAP_METHOD_ACES_FITTED,
+            Tonemapping::AgX => Mesh2dPipelineKey::TONEMAP_METHOD_AGX,
+            Tonemapping::SomewhatBoringDisplayTransform => {
+                Mesh2dPipelineKey::TONEMAP_METHOD_SOMEWHAT_BORING_DISPLAY_TRANSFORM
+            }
+            Tonemapping::TonyMcMapface => Mesh2dPipelineKey::TONEMAP_METHOD_TONY_MC_MAPFACE,
+            Tonemapping::BlenderFilmic => Mesh2dPipelineKey::TONEMAP_METHOD_BLENDER_FILMIC,
+        };
+    }
+    if let Some(DebandDither::Enabled) = dither {
+        key |= Mesh2dPipelineKey::DEBAND_DITHER;
+    }
+    key
+}
+
+// bevy's mesh2d pipeline with the sprite bound in group 1, the instances in a
+// second vertex buffer and our shader in place of its own
+#[derive(Resource)]
+struct ObjectPipeline {
+    mesh2d: Mesh2dPipeline,
+    sprite_layout: BindGroupLayout,
+}
+
+impl FromWorld for ObjectPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let sprite_layout =
+            world
+                .resource::<RenderDevice>()
+                .create_bind_group_layout(&BindGroupLayoutDescriptor {
+                    label: Some("object_sprite_layout"),
+                    entries: &[
+                        BindGroupLayoutEntry {
+                            binding: 0,
+                            visibility: ShaderStages::FRAGMENT,
+                            ty: BindingType::Texture {
+                                multisampled: false,
+                                sample_type: TextureSampleType::Float { filterable: true },
+                                view_dimension: TextureViewDimension::D2,
+                            },
+                            count: None,
+                        },
+                        BindGroupLayoutEntry {
+                            binding: 1,
+                            visibility: ShaderStages::FRAGMENT,
+                            ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                            count: None,
+                        },
+                    ],
+                });
+        Self {
+            mesh2d: world.resource::<Mesh2dPipeline>().clone(),
+            sprite_layout,
+        }
+    }
+}
+
+impl SpecializedMeshPipeline for ObjectPipeline {
+    type Key = Mesh2dPipelineKey;
+
+    fn specialize(
+        &self,
+        key: Self::Key,
+        layout: &MeshVertexBufferLayout,
+    ) -> Result<RenderPipelineDescriptor, SpecializedMeshPipelineError> {
+        let mut descriptor = self.mesh2d.specialize(key, layout)?;
+        descriptor.label = Some("object_instancing_pipeline".into());
+        descriptor.vertex.shader = OBJECT_SHADER_HANDLE.typed();
+        descriptor.vertex.buffers.push(VertexBufferLayout {
+            array_stride: size_of::<ObjectInstance>() as u64,
+            step_mode: VertexStepMode::Instance,
+            attributes: vec![
+                VertexAttribute {
+                    format: VertexFormat::Float32x2,
+                    offset: 0,
+                    shader_location: 5,
+                },
+                VertexAttribute {
+                    format: VertexFormat::Float32,
+                    offset: VertexFormat::Float32x2.size(),
+                    shader_location: 6,
+                },
+            ],
+        });
+        if let Some(fragment) = descriptor.fragment.as_mut() {
+            fragment.shader = OBJECT_SHADER_HANDLE.typed();
+        }
+        // the mesh bindings bevy's shader imports sit in group 2
+        descriptor.layout.insert(1, self.sprite_layout.clone());
+        Ok(descriptor)
+    }
+}
+
+type DrawObjects = (
+    SetItemPipeline,
+    SetMesh2dViewBindGroup<0>,
+    SetObjectSprite<1>,
+    SetMesh2dBindGroup<2>,
+    DrawObjectInstances,
+);
+
+struct SetObjectSprite<const I: usize>;
+
+impl<P: PhaseItem, const I: usize> RenderCommand<P> for SetObjectSprite<I> {
+    type Param = SRes<GpuObjectBatches>;
+    type ViewWorldQuery = ();
+    type ItemWorldQuery = Read<ExtractedObjectBatch>;
+
+    fn render<'w>(
+        _item: &P,
+        _view: (),
+        batch: ROQueryItem<'w, Self::ItemWorldQuery>,
+        gpu_batches: SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        match &gpu_batches.into_inner().0[batch.kind as usize].sprite {
+            Some(sprite) => {
+                pass.set_bind_group(I, sprite, &[]);
+                RenderCommandResult::Success
+            }
+            None => RenderCommandResult::Failure,
+        }
+    }
+}
+
+struct DrawObjectInstances;
+
+impl<P: PhaseItem> RenderCommand<P> for DrawObjectInstances {
+    type Param = (SRes<RenderAssets<Mesh>>, SRes<GpuObjectBatches>);
+    type ViewWorldQuery = ();
+    type ItemWorldQuery = (Read<Mesh2dHandle>, Read<ExtractedObjectBatch>);
+
+    fn render<'w>(
+        _item: &P,
+        _view: (),
+        (mesh_handle, batch): ROQueryItem<'w, Self::ItemWorldQuery>,
+        (meshes, gpu_batches): SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let gpu_batch = &gpu_batches.into_inner().0[batch.kind as usize];
+        let (Some(gpu_mesh), Some(instances)) = (
+            meshes.into_inner().get(&mesh_handle.0),
+            gpu_batch.instances.buffer(),
+        ) else {
+            return RenderCommandResult::Failure;
+        };
+        let count = gpu_batch.instances.len() as u32;
+
+        pass.set_vertex_buffer(0, gpu_mesh.vertex_buffer.slice(..));
+        pass.set_vertex_buffer(1, instances.slice(..));
+        match &gpu_mesh.buffer_info {
+            GpuBufferInfo::Indexed {
+                buffer,
+                index_format,
+                count: indices,
+            } => {
+                pass.set_index_buffer(buffer.slice(..), 0, *index_format);
+                pass.draw_indexed(0..*indices, 0, 0..count);
+            }
+            GpuBufferInfo::NonIndexed => {
+                pass.draw(0..gpu_mesh.vertex_count, 0..count);
+            }
+        }
+        RenderCommandResult::Success
+    }
+}
@@ -1,7 +1,11 @@
+pub mod checksums;
+pub mod collisions;
 pub mod game_loop;
 pub mod gui;
 pub mod handle;
 pub mod input;
+pub mod instancing;
 pub mod objects;
 pub mod player;
+pub mod rollback;
 pub mod sprites;
@@ -1,257 +1,344 @@
-use bevy::prelude::*;
-use rand::{Rng, SeedableRng};
-use rand_chacha::ChaCha8Rng;
-
-use crate::game_util::{
-    components::{Bolt, Rain},
-    resources::{BoltPool, ClientTick, Objects, RainPool},
+use bevy::{ecs::system::SystemParam, prelude::*};
+use satrunner_sim::{Field, Object, ObjectId, ObjectKind, X_BOUNDS, Y_BOUNDS};
+
+use crate::{
+    game_util::{
+        components::{ObjectBatch, ObjectInstance, ObjectInstances},
+        resources::{ClientTick, Objects, Rollback, CULL_MARGIN, HARMLESS_ALPHA},
+    },
+    network::messages::NewGame,
 };
 
 use super::player::{Enemy, Player};
 
-pub const X_BOUNDS: f32 = 1000.0;
-pub const Y_BOUNDS: f32 = 500.0;
-pub const FALL_SPEED: f32 = 3.0;
-
-#[derive(Debug)]
-pub struct ObjectPos {
-    pub tick: u64,
-    pub pos: Vec3,
+#[allow(clippy::type_complexity)]
+#[derive(SystemParam)]
+pub struct ObjectBatches<'w, 's> {
+    pub cameras: Query<'w, 's, (&'static OrthographicProjection, &'static GlobalTransform)>,
+    pub batches: Query<
+        'w,
+        's,
+        (
+            &'static ObjectBatch,
+            &'static mut ObjectInstances,
+            &'static mut Visibility,
+        ),
+        (Without<Player>, Without<Enemy>),
+    >,
 }
 
-pub fn handle_rain(
-    mut objects: ResMut<Objects>,
-    mut rain_pool: ResMut<RainPool>,
-    mut rain: Query<(&Rain, &mut Visibility, &mut Transform), Without<Player>>,
-    client_tick: ResMut<ClientTick>,
+// One instance per object of `kind`, where it is and how opaque. The buffer is
+// the one from the last tick, so it only grows when the field does.
+pub fn fill_instances(
+    instances: &mut Vec<ObjectInstance>,
+    objects: &[Object],
+    kind: ObjectKind,
+    alpha: impl Fn(ObjectId) -> f32,
 ) {
-    if client_tick.pause == 0 {
-        if let Some(rng_seed) = objects.rng_seed {
-            let seed = rng_seed ^ client_tick.tick.unwrap();
-            let mut rng = ChaCha8Rng::seed_from_u64(seed);
-
-            let x_position: f32 = rng.gen_range(-X_BOUNDS..X_BOUNDS);
-
-            if client_tick.tick.unwrap_or(0) % 5 != 0 {
-                let pos_start = Vec3::new(x_position, Y_BOUNDS, 0.0);
-                let new_pos = ObjectPos {
-                    tick: client_tick.tick.unwrap(),
-                    pos: pos_start,
-                };
-                objects.rain_pos.push(new_pos);
-            }
+    instances.clear();
+    instances.extend(
+        objects
+            .iter()
+            .filter(|object| object.id.kind == kind)
+            .map(|object| ObjectInstance {
+                pos: object.pos,
+                alpha: alpha(object.id),
+            }),
+    );
+}
 
-            for object in objects.rain_pos.iter_mut() {
-                object.pos.y += FALL_SPEED * -1.;
+impl ObjectBatches<'_, '_> {
+    // the part of the arena the camera sees, all of it before there is one
+    fn view(&self) -> ([f32; 2], [f32; 2]) {
+        match self.cameras.iter().next() {
+            Some((projection, transform)) => {
+                let centre = transform.translation().truncate();
+                let min = centre + projection.area.min - CULL_MARGIN;
+                let max = centre + projection.area.max + CULL_MARGIN;
+                (min.into(), max.into())
             }
+            None => ([-X_BOUNDS, -Y_BOUNDS], [X_BOUNDS, Y_BOUNDS]),
+        }
+    }
 
-            objects.rain_pos.retain(|object| {
-                object.pos.y >= -Y_BOUNDS
-                    && object.pos.y <= Y_BOUNDS
-                    && object.pos.x >= -X_BOUNDS
-                    && object.pos.x <= X_BOUNDS
-            });
-
-            let mut pool_iter = rain_pool.0.iter_mut();
-
-            for object in objects.rain_pos.iter() {
-                if let Some(pool) = pool_iter.next() {
-                    match rain.get_mut(*pool) {
-                        Ok((_particles, mut visibility, mut transform)) => {
-                            transform.translation = object.pos;
-                            *visibility = Visibility::Visible;
-                        }
-                        Err(err) => {
-                            info!("Error: {:?}", err);
-                        }
-                    }
+    // Writes each kind's instances from the objects in view, what can't hit a
+    // runner with `score` points is faded out.
+    pub fn show(&mut self, objects: &mut Objects, score: usize) {
+        let curve = objects.field.mode().curve();
+        let (min, max) = self.view();
+        let in_view = objects.field.within(min, max);
+
+        for (batch, mut instances, mut visibility) in self.batches.iter_mut() {
+            fill_instances(&mut instances.0, &in_view, batch.0, |id| {
+                if curve.counts(id, score) {
+                    1.0
+                } else {
+                    HARMLESS_ALPHA
                 }
-            }
+            });
 
-            for pool in pool_iter {
-                if let Ok((_particle, mut visibility, _transform)) = rain.get_mut(*pool) {
-                    *visibility = Visibility::Hidden;
-                }
-            }
+            // with no instances there is nothing to draw
+            *visibility = if instances.0.is_empty() {
+                Visibility::Hidden
+            } else {
+                Visibility::Visible
+            };
         }
     }
 }
 
-pub fn handle_bolt(
+// Starts a new game's objects off on `tick`, the one the client picks up from,
+// rebuilt from the seed and what the server had already taken off the field.
+// The next tick handle_objects steps is the one after it.
+pub fn join_objects(objects: &mut Objects, new_game: &NewGame, tick: u64) {
+    let removed = new_game
+        .removed
+        .iter()
+        .map(|&id| ObjectId::from(id))
+        .collect();
+    objects.rng_seed = Some(new_game.rng_seed);
+    objects.field = Field::at(new_game.mode.into(), new_game.rng_seed, tick, &removed);
+}
+
+pub fn handle_objects(
     mut objects: ResMut<Objects>,
-    mut bolt_pool: ResMut<BoltPool>,
-    mut bolt: Query<(&Bolt, &mut Visibility, &mut Transform), Without<Player>>,
+    mut batches: ObjectBatches,
+    players: Query<&Player>,
     client_tick: Res<ClientTick>,
+    rollback: Res<Rollback>,
 ) {
     if client_tick.pause == 0 {
         if let Some(rng_seed) = objects.rng_seed {
-            let seed = rng_seed ^ (client_tick.tick.unwrap());
-            let mut rng = ChaCha8Rng::seed_from_u64(seed);
-
-            let x_position: f32 = rng.gen_range(-X_BOUNDS..X_BOUNDS);
-
-            if client_tick.tick.unwrap_or(0) % 5 == 0 {
-                let pos_start = Vec3::new(x_position, Y_BOUNDS, 0.0);
-                let new_pos = ObjectPos {
-                    tick: client_tick.tick.unwrap(),
-                    pos: pos_start,
-                };
-                objects.bolt_pos.push(new_pos);
-            }
-
-            for object in objects.bolt_pos.iter_mut() {
-                object.pos.y += FALL_SPEED * -1.;
-            }
-
-            objects.bolt_pos.retain(|object| {
-                object.pos.y >= -Y_BOUNDS
-                    && object.pos.y <= Y_BOUNDS
-                    && object.pos.x >= -X_BOUNDS
-                    && object.pos.x <= X_BOUNDS
-            });
-
-            let mut pool_iter = bolt_pool.0.iter_mut();
-
-            for object in objects.bolt_pos.iter() {
-                if let Some(pool) = pool_iter.next() {
-                    match bolt.get_mut(*pool) {
-                        Ok((_particles, mut visibility, mut transform)) => {
-                            transform.translation = object.pos;
-                            *visibility = Visibility::Visible;
-                        }
-                        Err(err) => {
-                            info!("Error: {:?}", err);
-                        }
-                    }
-                }
-            }
-
-            for pool in pool_iter {
-                if let Ok((_particle, mut visibility, _transform)) = bolt.get_mut(*pool) {
-                    *visibility = Visibility::Hidden;
-                }
+            // in rollback mode the objects come out of the rollback simulation
+            if !rollback.enabled() {
+                objects.field.step(rng_seed, client_tick.tick.unwrap());
             }
+            let score = players.iter().next().map_or(0, |player| player.score);
+            batches.show(&mut objects, score);
         }
     }
 }
 
-#[allow(clippy::type_complexity)]
-pub fn handle_rain_behind(
+// Skips the objects over the `ticks` we are behind by in one go. The current
+// tick has already been stepped, so this picks up on the one after it.
+pub fn handle_objects_behind(
     objects: &mut ResMut<Objects>,
-    rain_pool: &mut ResMut<RainPool>,
-    rain: &mut Query<(&Rain, &mut Visibility, &mut Transform), (Without<Player>, Without<Enemy>)>,
+    batches: &mut ObjectBatches,
     client_tick: &ResMut<ClientTick>,
+    ticks: u64,
+    score: usize,
 ) {
     if client_tick.pause == 0 {
         if let Some(rng_seed) = objects.rng_seed {
-            let seed = rng_seed ^ client_tick.tick.unwrap();
-            let mut rng = ChaCha8Rng::seed_from_u64(seed);
-
-            let x_position: f32 = rng.gen_range(-X_BOUNDS..X_BOUNDS);
-
-            if client_tick.tick.unwrap_or(0) % 5 != 0 {
-                let pos_start = Vec3::new(x_position, Y_BOUNDS, 0.0);
-                let new_pos = ObjectPos {
-                    tick: client_tick.tick.unwrap(),
-                    pos: pos_start,
-                };
-                objects.rain_pos.push(new_pos);
-            }
-
-            for object in objects.rain_pos.iter_mut() {
-                object.pos.y += FALL_SPEED * -1.0;
-            }
-
-            objects.rain_pos.retain(|object| {
-                object.pos.y >= -Y_BOUNDS
-                    && object.pos.y <= Y_BOUNDS
-                    && object.pos.x >= -X_BOUNDS
-                    && object.pos.x <= X_BOUNDS
-            });
-
-            let mut pool_iter = rain_pool.0.iter_mut();
-
-            for object in objects.rain_pos.iter() {
-                if let Some(pool) = pool_iter.next() {
-                    match rain.get_mut(*pool) {
-                        Ok((_particles, mut visibility, mut transform)) => {
-                            transform.translation = object.pos;
-                            *visibility = Visibility::Visible;
-                        }
-                        Err(err) => {
-                            info!("Error: {:?}", err);
-                        }
-                    }
-                }
-            }
-
-            for pool in pool_iter {
-                if let Ok((_particle, mut visibility, _transform)) = rain.get_mut(*pool) {
-                    *visibility = Visibility::Hidden;
-                }
-            }
+            let tick = client_tick.tick.unwrap();
+            objects.field.seek(rng_seed, tick + 1..tick + ticks + 1);
+            batches.show(objects, score);
         }
     }
 }
 
-#[allow(clippy::type_complexity)]
-pub fn handle_bolt_behind(
-    objects: &mut ResMut<Objects>,
-    bolt_pool: &mut ResMut<BoltPool>,
-    bolt: &mut Query<
-        (&Bolt, &mut Visibility, &mut Transform),
-        (Without<Player>, Without<Enemy>, Without<Rain>),
-    >,
-    client_tick: &ResMut<ClientTick>,
-) {
-    if client_tick.pause == 0 {
-        if let Some(rng_seed) = objects.rng_seed {
-            let seed = rng_seed ^ (client_tick.tick.unwrap());
-            let mut rng = ChaCha8Rng::seed_from_u64(seed);
-
-            let x_position: f32 = rng.gen_range(-X_BOUNDS..X_BOUNDS);
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::*;
+    use satrunner_sim::{Field, GameMode, Object, ObjectId, ObjectKind, X_BOUNDS, Y_BOUNDS};
+    use uuid::Uuid;
+
+    use super::{
+        fill_instances, handle_objects, handle_objects_behind, join_objects, ObjectBatches,
+    };
+    use crate::{
+        game_util::{
+            components::{ObjectBatch, ObjectInstance, ObjectInstances},
+            resources::{ClientTick, Objects, Rollback},
+        },
+        network::messages::NewGame,
+    };
+
+    const SEED: u64 = 21;
+
+    fn app(objects: Objects, tick: u64) -> App {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .insert_resource(Rollback::new())
+            .insert_resource(ClientTick {
+                tick: Some(tick),
+                ..ClientTick::new()
+            })
+            .insert_resource(objects);
+        app
+    }
 
-            if client_tick.tick.unwrap_or(0) % 5 == 0 {
-                let pos_start = Vec3::new(x_position, Y_BOUNDS, 0.0);
-                let new_pos = ObjectPos {
-                    tick: client_tick.tick.unwrap(),
-                    pos: pos_start,
-                };
-                objects.bolt_pos.push(new_pos);
-            }
+    fn stepped(mode: GameMode, ticks: std::ops::Range<u64>) -> Field {
+        let mut field = Field::with_mode(mode);
+        for tick in ticks {
+            field.step(SEED, tick);
+        }
+        field
+    }
 
-            for object in objects.bolt_pos.iter_mut() {
-                object.pos.y += FALL_SPEED * -1.0;
-            }
+    #[test]
+    fn new_games_pick_up_on_the_client_tick() {
+        let mode = GameMode::Rush;
+        let mut server = stepped(mode, 0..100);
+        let taken = server.iter().next().unwrap().id;
+        server.remove(taken);
+        for tick in 100..300 {
+            server.step(SEED, tick);
+        }
 
-            objects.bolt_pos.retain(|object| {
-                object.pos.y >= -Y_BOUNDS
-                    && object.pos.y <= Y_BOUNDS
-                    && object.pos.x >= -X_BOUNDS
-                    && object.pos.x <= X_BOUNDS
-            });
+        // three ticks of latency ahead of the server
+        let new_game = NewGame {
+            id: Uuid::nil(),
+            server_tick: 300,
+            rng_seed: SEED,
+            mode: mode.into(),
+            high_scores: Vec::new(),
+            removed: vec![taken.into()],
+            resume_token: 0,
+        };
+        let mut objects = Objects::new();
+        join_objects(&mut objects, &new_game, 303);
+
+        // the next fixed tick moves the clock on, then the objects
+        let mut app = app(objects, 304);
+        app.add_systems(Update, handle_objects);
+        app.update();
+
+        for tick in 300..=304 {
+            server.step(SEED, tick);
+        }
+        assert_eq!(app.world.resource::<Objects>().field, server);
+    }
 
-            let mut pool_iter = bolt_pool.0.iter_mut();
+    #[test]
+    fn catching_up_matches_stepping_every_tick() {
+        let mode = GameMode::Classic;
+        let mut objects = Objects::new();
+        objects.rng_seed = Some(SEED);
+        objects.field = stepped(mode, 0..51);
+
+        let mut app = app(objects, 50);
+        app.add_systems(
+            Update,
+            |mut objects: ResMut<Objects>,
+             mut batches: ObjectBatches,
+             client_tick: ResMut<ClientTick>| {
+                handle_objects_behind(&mut objects, &mut batches, &client_tick, 7, 0);
+            },
+        );
+        app.update();
+
+        let field = &app.world.resource::<Objects>().field;
+        assert_eq!(*field, stepped(mode, 0..58));
+        for kind in ObjectKind::all() {
+            let objects = field.get(kind);
+            assert!(objects.windows(2).all(|pair| pair[0].id < pair[1].id));
+        }
+    }
 
-            for object in objects.bolt_pos.iter() {
-                if let Some(pool) = pool_iter.next() {
-                    match bolt.get_mut(*pool) {
-                        Ok((_particles, mut visibility, mut transform)) => {
-                            transform.translation = object.pos;
-                            *visibility = Visibility::Visible;
-                        }
-                        Err(err) => {
-                            info!("Error: {:?}", err);
-                        }
-                    }
-                }
+    #[test]
+    fn every_object_is_one_instance_of_its_kind() {
+        let objects = [
+            Object {
+                id: ObjectId::new(ObjectKind::Rain, 1, 0),
+                pos: [0.0, 0.0],
+            },
+            Object {
+                id: ObjectId::new(ObjectKind::Bolt, 1, 0),
+                pos: [20.0, 30.0],
+            },
+            Object {
+                id: ObjectId::new(ObjectKind::Rain, 1, 1),
+                pos: [100.0, -50.0],
+            },
+        ];
+        let mut instances = Vec::new();
+        fill_instances(&mut instances, &objects, ObjectKind::Rain, |id| {
+            if id.index == 1 {
+                0.3
+            } else {
+                1.0
             }
+        });
+        assert_eq!(
+            instances,
+            vec![
+                ObjectInstance {
+                    pos: [0.0, 0.0],
+                    alpha: 1.0,
+                },
+                ObjectInstance {
+                    pos: [100.0, -50.0],
+                    alpha: 0.3,
+                },
+            ]
+        );
+
+        fill_instances(&mut instances, &objects, ObjectKind::Shield, |_| 1.0);
+        assert!(instances.is_empty());
+    }
 
-            for pool in pool_iter {
-                if let Ok((_particle, mut visibility, _transform)) = bolt.get_mut(*pool) {
-                    *visibility = Visibility::Hidden;
-                }
-            }
+    #[test]
+    fn instances_keep_their_buffer_between_ticks() {
+        let object = Object {
+            id: ObjectId::new(ObjectKind::Rain, 1, 0),
+            pos: [0.0, 0.0],
+        };
+        let mut instances = Vec::new();
+        fill_instances(&mut instances, &[object; 8], ObjectKind::Rain, |_| 1.0);
+        let buffer = instances.as_ptr();
+
+        fill_instances(&mut instances, &[object; 3], ObjectKind::Rain, |_| 1.0);
+        assert_eq!(instances.as_ptr(), buffer);
+        assert_eq!(instances.len(), 3);
+    }
+
+    #[test]
+    fn a_tick_writes_one_instance_per_object_in_view() {
+        let mode = GameMode::Rush;
+        let mut objects = Objects::new();
+        objects.rng_seed = Some(SEED);
+        objects.field = stepped(mode, 0..200);
+
+        let mut app = app(objects, 200);
+        for kind in ObjectKind::all() {
+            app.world.spawn((
+                ObjectBatch(kind),
+                ObjectInstances::default(),
+                Visibility::Hidden,
+            ));
+        }
+        app.add_systems(Update, handle_objects);
+        app.update();
+
+        // without a camera all of the arena is in view
+        let in_view = app
+            .world
+            .resource_mut::<Objects>()
+            .field
+            .within([-X_BOUNDS, -Y_BOUNDS], [X_BOUNDS, Y_BOUNDS]);
+        let mut batches = app
+            .world
+            .query::<(&ObjectBatch, &ObjectInstances, &Visibility)>();
+        for (batch, instances, visibility) in batches.iter(&app.world) {
+            let positions: Vec<_> = in_view
+                .iter()
+                .filter(|object| object.id.kind == batch.0)
+                .map(|object| object.pos)
+                .collect();
+            assert_eq!(
+                instances
+                    .0
+                    .iter()
+                    .map(|instance| instance.pos)
+                    .collect::<Vec<_>>(),
+                positions
+            );
+            let hidden = *visibility == Visibility::Hidden;
+            assert_eq!(hidden, positions.is_empty());
         }
+        assert!(batches
+            .iter(&app.world)
+            .any(|(_, instances, _)| !instances.0.is_empty()));
     }
 }
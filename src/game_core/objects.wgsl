@@ -0,0 +1,50 @@
+#import bevy_sprite::mesh2d_functions as mesh_functions
+#import bevy_sprite::mesh2d_bindings       mesh
+#import bevy_sprite::mesh2d_view_bindings  view
+
+#ifdef TONEMAP_IN_SHADER
+#import bevy_core_pipeline::tonemapping
+#endif
+
+@group(1) @binding(0)
+var sprite: texture_2d<f32>;
+@group(1) @binding(1)
+var sprite_sampler: sampler;
+
+struct Vertex {
+    // the kind's quad, centred on the origin
+    @location(0) position: vec3<f32>,
+    @location(2) uv: vec2<f32>,
+    @location(4) color: vec4<f32>,
+    // the object it is drawn for
+    @location(5) offset: vec2<f32>,
+    @location(6) alpha: f32,
+};
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) color: vec4<f32>,
+};
+
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    var out: VertexOutput;
+    let position = vertex.position + vec3<f32>(vertex.offset, 0.0);
+    out.position = mesh_functions::mesh2d_position_local_to_clip(
+        mesh.model,
+        vec4<f32>(position, 1.0)
+    );
+    out.uv = vertex.uv;
+    out.color = vec4<f32>(vertex.color.rgb, vertex.color.a * vertex.alpha);
+    return out;
+}
+
+@fragment
+fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
+    var color = in.color * textureSample(sprite, sprite_sampler, in.uv);
+#ifdef TONEMAP_IN_SHADER
+    color = bevy_core_pipeline::tonemapping::tone_mapping(color, view.color_grading);
+#endif
+    return color;
+}
@@ -1,17 +1,15 @@
-use std::collections::VecDeque;
+use std::collections::{BTreeMap, VecDeque};
 
-use bevy::{
-    prelude::*,
-    time::Stopwatch,
-    utils::{HashMap, Instant},
-};
+use bevy::{prelude::*, time::Stopwatch, utils::Instant};
 use uuid::Uuid;
 
-use crate::{game_util::resources::ClientTick, network::messages::PlayerInput};
+use satrunner_sim::{movement, within_bounds, Powers};
 
-use super::objects::{X_BOUNDS, Y_BOUNDS};
+use crate::{
+    game_util::resources::{ClientTick, ENEMY_SNAPSHOTS, MAX_PENDING_INPUTS},
+    network::messages::PlayerInput,
+};
 
-pub const PLAYER_SPEED: f32 = 2.5;
 #[derive(Component)]
 pub struct Player {
     pub target: Vec2,
@@ -22,9 +20,17 @@ pub struct Player {
     pub name: String,
     pub spawn_time: Option<Instant>,
     pub death_time: Option<u64>,
+    pub powers: Powers,
 }
 
 impl Player {
+    // Acks prune these, but servers without input batching never send any.
+    pub fn push_input(&mut self, input: PlayerInput) {
+        self.pending_inputs.push(input);
+        let excess = self.pending_inputs.len().saturating_sub(MAX_PENDING_INPUTS);
+        self.pending_inputs.drain(..excess);
+    }
+
     pub fn server_reconciliation(
         &mut self,
         t: &mut Transform,
@@ -47,36 +53,30 @@ impl Player {
                 self.target.x = tick_input.target[0];
                 self.target.y = tick_input.target[1];
             }
-            self.apply_input(t, client_tick);
+            self.apply_input_at(t, client_tick, sim_tick);
         }
     }
 
     pub fn apply_input(&mut self, t: &mut Transform, client_tick: &ClientTick) {
-        let movement = self.calculate_movement(t);
-        if (t.translation.x + movement.x).abs() <= X_BOUNDS
-            && (t.translation.y + movement.y).abs() <= Y_BOUNDS
+        self.apply_input_at(t, client_tick, client_tick.tick.unwrap_or_default());
+    }
+
+    // `tick` is the one being simulated, behind ours while replaying inputs
+    pub fn apply_input_at(&mut self, t: &mut Transform, client_tick: &ClientTick, tick: u64) {
+        let movement = self.calculate_movement(t, tick);
+        if within_bounds([t.translation.x + movement.x, t.translation.y + movement.y])
             && client_tick.pause == 0
         {
             t.translation += Vec3::new(movement.x, movement.y, 0.2);
         }
     }
 
-    pub fn calculate_movement(&self, t: &Transform) -> Vec2 {
-        let direction = self.target - Vec2::new(t.translation.x, t.translation.y);
-
-        let tolerance = 6.0;
-
-        if direction.length() > tolerance {
-            let mut speed = PLAYER_SPEED;
-
-            if direction.y < 0.0 {
-                speed *= 2.0;
-            }
-
-            direction.normalize() * speed
-        } else {
-            Vec2::ZERO
-        }
+    pub fn calculate_movement(&self, t: &Transform, tick: u64) -> Vec2 {
+        Vec2::from(movement(
+            [t.translation.x, t.translation.y],
+            self.target.into(),
+            self.powers.boost(tick),
+        ))
     }
 }
 
@@ -87,52 +87,129 @@ pub struct Enemy {
     pub score: usize,
     pub name: String,
     pub spawn_time: Stopwatch,
-    pub past_pos: HashMap<u64, Vec3>,
+    pub past_pos: BTreeMap<u64, Vec2>,
     pub pending_inputs: VecDeque<PlayerInput>,
+    pub rtt_ms: u16,
+    pub powers: Powers,
 }
 
 impl Enemy {
+    pub fn record(&mut self, tick: u64, pos: [f32; 2]) {
+        self.past_pos.insert(tick, Vec2::from(pos));
+        while self.past_pos.len() > ENEMY_SNAPSHOTS {
+            self.past_pos.pop_first();
+        }
+    }
+
+    // position at a fractional server tick, lerped between the snapshots either
+    // side of it or carried on from the newest one when nothing newer arrived
+    pub fn sample(&self, render_tick: f32, max_extrapolation: f32) -> Option<Vec2> {
+        let floor = render_tick.max(0.0).floor() as u64;
+        let before = self.past_pos.range(..=floor).next_back();
+        let after = self.past_pos.range(floor + 1..).next();
+
+        match (before, after) {
+            (Some((&t0, &p0)), Some((&t1, &p1))) => {
+                let alpha = (render_tick - t0 as f32) / (t1 - t0) as f32;
+                Some(p0.lerp(p1, alpha.clamp(0.0, 1.0)))
+            }
+            (Some((&t0, &p0)), None) => {
+                let ahead = (render_tick - t0 as f32).min(max_extrapolation);
+                let velocity = match self.past_pos.range(..t0).next_back() {
+                    Some((&prev, &prev_pos)) => (p0 - prev_pos) / (t0 - prev) as f32,
+                    None => Vec2::ZERO,
+                };
+                Some(p0 + velocity * ahead)
+            }
+            (None, Some((_, &p1))) => Some(p1),
+            (None, None) => None,
+        }
+    }
+
     pub fn enemy_reconciliation(
         &mut self,
         t: &mut Transform,
         client_tick: &ClientTick,
         pos: [f32; 2],
         enemy_tick: u64,
+        max_ticks: u64,
     ) {
         t.translation.x = pos[0];
         t.translation.y = pos[1];
 
-        for _ in enemy_tick..client_tick.tick.unwrap() {
+        let ticks = client_tick.tick.unwrap().saturating_sub(enemy_tick);
+        for _ in 0..ticks.min(max_ticks) {
             self.apply_input(t, client_tick);
         }
     }
 
     pub fn apply_input(&mut self, t: &mut Transform, client_tick: &ClientTick) {
-        let movement = self.calculate_movement(t);
+        let movement = self.calculate_movement(t, client_tick.tick.unwrap_or_default());
 
-        if (t.translation.x + movement.x).abs() <= X_BOUNDS
-            && (t.translation.y + movement.y).abs() <= Y_BOUNDS
+        if within_bounds([t.translation.x + movement.x, t.translation.y + movement.y])
             && client_tick.pause == 0
         {
             t.translation += Vec3::new(movement.x, movement.y, 0.0);
         }
     }
 
-    pub fn calculate_movement(&self, t: &Transform) -> Vec2 {
-        let direction = self.target - Vec2::new(t.translation.x, t.translation.y);
+    pub fn calculate_movement(&self, t: &Transform, tick: u64) -> Vec2 {
+        Vec2::from(movement(
+            [t.translation.x, t.translation.y],
+            self.target.into(),
+            self.powers.boost(tick),
+        ))
+    }
+}
 
-        let tolerance = 6.0;
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, VecDeque};
+
+    use bevy::{prelude::*, time::Stopwatch};
+    use uuid::Uuid;
+
+    use super::Enemy;
+    use crate::game_util::resources::ENEMY_SNAPSHOTS;
+
+    fn enemy(snapshots: &[(u64, [f32; 2])]) -> Enemy {
+        let mut enemy = Enemy {
+            target: Vec2::ZERO,
+            id: Uuid::nil(),
+            score: 0,
+            name: String::new(),
+            spawn_time: Stopwatch::new(),
+            past_pos: BTreeMap::new(),
+            pending_inputs: VecDeque::new(),
+            rtt_ms: 0,
+            powers: Default::default(),
+        };
+        for &(tick, pos) in snapshots {
+            enemy.record(tick, pos);
+        }
+        enemy
+    }
 
-        if direction.length() > tolerance {
-            let mut speed = PLAYER_SPEED;
+    #[test]
+    fn interpolates_between_snapshots() {
+        let enemy = enemy(&[(10, [0.0, 0.0]), (12, [5.0, -5.0])]);
+        assert_eq!(enemy.sample(10.0, 3.0), Some(Vec2::new(0.0, 0.0)));
+        assert_eq!(enemy.sample(11.0, 3.0), Some(Vec2::new(2.5, -2.5)));
+        assert_eq!(enemy.sample(12.0, 3.0), Some(Vec2::new(5.0, -5.0)));
+    }
 
-            if direction.y < 0.0 {
-                speed *= 2.0;
-            }
+    #[test]
+    fn extrapolation_is_bounded() {
+        let enemy = enemy(&[(10, [0.0, 0.0]), (11, [2.0, 0.0])]);
+        assert_eq!(enemy.sample(12.5, 3.0), Some(Vec2::new(5.0, 0.0)));
+        assert_eq!(enemy.sample(20.0, 3.0), Some(Vec2::new(8.0, 0.0)));
+    }
 
-            direction.normalize() * speed
-        } else {
-            Vec2::ZERO
-        }
+    #[test]
+    fn buffer_is_bounded() {
+        let snapshots: Vec<_> = (0..100).map(|tick| (tick, [tick as f32, 0.0])).collect();
+        let enemy = enemy(&snapshots);
+        assert_eq!(enemy.past_pos.len(), ENEMY_SNAPSHOTS);
+        assert_eq!(enemy.sample(0.0, 3.0), Some(Vec2::new(80.0, 0.0)));
     }
 }
@@ -0,0 +1,165 @@
+use bevy::prelude::*;
+use satrunner_sim::{Runner, State as SimState};
+use uuid::Uuid;
+
+use crate::{
+    game_util::resources::{ClientTick, Objects, Rollback},
+    network::messages::{NewPos, PlayerInput},
+    GameStage,
+};
+
+use super::player::{Enemy, Player};
+
+// the last input the local player gave on `tick`, remote players keep their target
+fn local_input(
+    inputs: &[PlayerInput],
+    local: Option<Uuid>,
+) -> impl Fn(u64, Uuid) -> Option<[f32; 2]> + '_ {
+    move |tick, id| {
+        if Some(id) != local {
+            return None;
+        }
+        inputs
+            .iter()
+            .rev()
+            .find(|input| input.tick == tick)
+            .map(|input| input.target)
+    }
+}
+
+// Steps the rollback simulation one tick in rollback mode and shows the result.
+// Whatever the ECS knows that the simulation doesn't (players joining or
+// leaving, a respawn, a resync) is folded in before stepping.
+#[allow(clippy::type_complexity)]
+pub fn rollback_step(
+    mut rollback: ResMut<Rollback>,
+    mut query_player: Query<(&mut Player, &mut Transform)>,
+    mut query_enemy: Query<(&Enemy, &mut Transform), Without<Player>>,
+    mut objects: ResMut<Objects>,
+    client_tick: Res<ClientTick>,
+    stage: Res<State<GameStage>>,
+) {
+    if !rollback.enabled() {
+        rollback.history.clear();
+        return;
+    }
+    let (Some(tick), Some(rng_seed)) = (client_tick.tick, objects.rng_seed) else {
+        return;
+    };
+    if client_tick.pause > 0 {
+        return;
+    }
+
+    let in_game = *stage.get() == GameStage::InGame;
+    let mut state = match rollback.history.latest() {
+        Some(state) if state.tick + 1 == tick => state.clone(),
+        _ => {
+            let mut state = SimState::new(tick - 1);
+            state.field = objects.field.clone();
+            state
+        }
+    };
+
+    let mut present = Vec::new();
+    for (player, t) in query_player.iter() {
+        present.push(player.id);
+        let runner = state.runners.entry(player.id).or_insert_with(|| {
+            Runner::new(
+                t.translation.truncate().into(),
+                player.target.into(),
+                player.score,
+            )
+        });
+        if runner.alive != in_game {
+            *runner = Runner::new(
+                t.translation.truncate().into(),
+                player.target.into(),
+                player.score,
+            );
+            runner.alive = in_game;
+        }
+        // the server has the last word on powers, see handle_server
+        runner.powers = player.powers;
+    }
+    for (enemy, t) in query_enemy.iter() {
+        present.push(enemy.id);
+        let runner = state.runners.entry(enemy.id).or_insert_with(|| {
+            Runner::new(
+                t.translation.truncate().into(),
+                enemy.target.into(),
+                enemy.score,
+            )
+        });
+        runner.powers = enemy.powers;
+    }
+    state.runners.retain(|id, _| present.contains(id));
+
+    let local = query_player.iter().next().map(|(player, _)| player.id);
+    let inputs = query_player
+        .iter()
+        .next()
+        .map(|(player, _)| player.pending_inputs.clone())
+        .unwrap_or_default();
+    state.step(rng_seed, local_input(&inputs, local));
+
+    for (mut player, mut t) in query_player.iter_mut() {
+        if let Some(runner) = state.runners.get(&player.id) {
+            t.translation.x = runner.pos[0];
+            t.translation.y = runner.pos[1];
+            player.score = runner.score;
+            player.powers = runner.powers;
+        }
+    }
+    for (enemy, mut t) in query_enemy.iter_mut() {
+        if let Some(runner) = state.runners.get(&enemy.id) {
+            t.translation.x = runner.pos[0];
+            t.translation.y = runner.pos[1];
+        }
+    }
+    objects.field = state.field.clone();
+
+    rollback.history.push(state);
+}
+
+// A server update confirms every player's position and target on its tick:
+// restore that tick, patch them in and re-simulate up to now with our own
+// inputs. False if the tick is no longer in history.
+pub fn confirm(
+    rollback: &mut Rollback,
+    updates: &[NewPos],
+    local: Option<&Player>,
+    now: u64,
+    rng_seed: u64,
+) -> bool {
+    let Some(tick) = updates.first().map(|update| update.tick) else {
+        return false;
+    };
+
+    let inputs = local
+        .map(|player| player.pending_inputs.clone())
+        .unwrap_or_default();
+    let correct = |state: &mut SimState<Uuid>| {
+        for update in updates {
+            if let Some(runner) = state.runners.get_mut(&update.id) {
+                runner.pos = update.pos;
+                runner.target = update.input;
+                runner.alive = true;
+            }
+        }
+    };
+
+    let confirmed = rollback
+        .history
+        .rollback(
+            tick,
+            now,
+            rng_seed,
+            correct,
+            local_input(&inputs, local.map(|player| player.id)),
+        )
+        .is_some();
+    if confirmed {
+        rollback.rollbacks += 1;
+    }
+    confirmed
+}
@@ -1,8 +1,12 @@
-use std::{collections::VecDeque, time::Duration};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    time::Duration,
+};
 
-use bevy::{prelude::*, time::Stopwatch, utils::HashMap};
+use bevy::{prelude::*, render::view::NoFrustumCulling, sprite::Mesh2dHandle, time::Stopwatch};
 
 use bevy_ecs_ldtk::LdtkWorldBundle;
+use satrunner_sim::{Powers, OBJECTS};
 use uuid::Uuid;
 use virtual_joystick::{
     TintColor, VirtualJoystickAxis, VirtualJoystickBundle, VirtualJoystickInteractionArea,
@@ -10,10 +14,7 @@ use virtual_joystick::{
 };
 
 use crate::{
-    game_util::{
-        components::{Bolt, NamePlates, NamePlatesLocal, Rain},
-        resources::{BoltPool, RainPool},
-    },
+    game_util::components::{NamePlates, NamePlatesLocal, ObjectBatch, ObjectInstances},
     keyboard::components::KeyboardNode,
     GameStage, KeyboardState,
 };
@@ -23,8 +24,6 @@ use super::player::{Enemy, Player};
 const FONT_SIZE: f32 = 15.0;
 
 const PLAYER_SIZE: Vec2 = Vec2::new(20.0, 20.0);
-const DOTS_SIZE: Vec2 = Vec2::new(10., 10.);
-const LN_SIZE: Vec2 = Vec2::new(10., 10.);
 
 pub fn spawn_player(
     commands: &mut Commands,
@@ -104,6 +103,7 @@ pub fn spawn_player(
             name: String::new(),
             spawn_time: None,
             death_time: None,
+            powers: Powers::default(),
         })
         .with_children(|parent| {
             parent.spawn(Camera2dBundle {
@@ -182,7 +182,9 @@ pub fn spawn_enemies(
                 name: enemy_name,
                 spawn_time: stopwatch,
                 pending_inputs: VecDeque::new(),
-                past_pos: HashMap::new(),
+                past_pos: BTreeMap::new(),
+                rtt_ms: 0,
+                powers: Powers::default(),
             })
             .with_children(|parent| {
                 parent
@@ -199,51 +201,30 @@ pub fn spawn_enemies(
     }
 }
 
-pub fn pool_rain(
+// Every object of a kind is an instance of one quad, so the whole field takes
+// a draw call per kind however heavy the weather gets.
+pub fn spawn_object_batches(
     mut commands: Commands,
-    mut rain_pool: ResMut<RainPool>,
+    mut meshes: ResMut<Assets<Mesh>>,
     asset_server: Res<AssetServer>,
 ) {
-    let rain_image = asset_server.load("droplet.png");
-
-    for _ in 0..1000 {
-        let rain = commands
-            .spawn(SpriteBundle {
-                sprite: Sprite {
-                    custom_size: Some(DOTS_SIZE),
-                    ..Default::default()
-                },
-                texture: rain_image.clone(),
+    for def in OBJECTS {
+        let tint = Color::rgb(def.tint[0], def.tint[1], def.tint[2]).as_linear_rgba_f32();
+        let mut quad = Mesh::from(shape::Quad::new(Vec2::splat(def.size)));
+        quad.insert_attribute(Mesh::ATTRIBUTE_COLOR, vec![tint; 4]);
+
+        commands.spawn((
+            Mesh2dHandle(meshes.add(quad)),
+            asset_server.load::<Image, _>(def.sprite),
+            SpatialBundle {
+                visibility: Visibility::Hidden,
                 ..Default::default()
-            })
-            .insert(Rain)
-            .insert(Visibility::Hidden)
-            .id();
-        rain_pool.0.push_back(rain);
-    }
-}
-
-pub fn pool_bolt(
-    mut commands: Commands,
-    mut bolt_pool: ResMut<BoltPool>,
-    asset_server: Res<AssetServer>,
-) {
-    let bolt_image = asset_server.load("high-voltage.png");
-
-    for _ in 0..200 {
-        let ln = commands
-            .spawn(SpriteBundle {
-                sprite: Sprite {
-                    custom_size: Some(LN_SIZE),
-                    ..Default::default()
-                },
-                texture: bolt_image.clone(),
-                ..Default::default()
-            })
-            .insert(Bolt)
-            .insert(Visibility::Hidden)
-            .id();
-        bolt_pool.0.push_back(ln);
+            },
+            // the quad sits at the origin, its instances are all over the arena
+            NoFrustumCulling,
+            ObjectBatch(def.kind),
+            ObjectInstances::default(),
+        ));
     }
 }
 
@@ -1,10 +1,29 @@
-use bevy::prelude::*;
+use bevy::{
+    core::{Pod, Zeroable},
+    prelude::*,
+};
+use satrunner_sim::ObjectKind;
 use uuid::Uuid;
 
+// the one quad every object of a kind is drawn with
 #[derive(Component)]
-pub struct Rain;
-#[derive(Component)]
-pub struct Bolt;
+pub struct ObjectBatch(pub ObjectKind);
+
+// where one object of a batch is drawn and how opaque, as the GPU reads it
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(C)]
+pub struct ObjectInstance {
+    pub pos: [f32; 2],
+    pub alpha: f32,
+}
+
+// SAFETY: three f32s laid out in order, no padding and any bits are valid
+unsafe impl Zeroable for ObjectInstance {}
+unsafe impl Pod for ObjectInstance {}
+
+// the objects of a batch in view, rewritten every tick
+#[derive(Component, Default)]
+pub struct ObjectInstances(pub Vec<ObjectInstance>);
 
 #[derive(Component)]
 pub struct NamePlates {
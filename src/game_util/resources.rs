@@ -1,15 +1,58 @@
-use std::collections::VecDeque;
+use std::{
+    collections::{BTreeMap, VecDeque},
+    time::Duration,
+};
 
-use bevy::prelude::*;
+use bevy::{prelude::*, utils::Instant};
 use futures::channel::mpsc::{Receiver, Sender};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use satrunner_protocol::delta::Frame;
+use satrunner_sim::{Effect, Field, History, Object, ObjectId};
 use uuid::Uuid;
 
-use crate::{game_core::objects::ObjectPos, network::messages::ClientMessage};
+use crate::{
+    network::{
+        messages::{
+            ClientMessage, Handshake, NetworkMessage, PlayerInput, CLIENT_CAPABILITIES,
+            PROTOCOL_VERSION,
+        },
+        transport::Transport,
+    },
+    GameStage, TICK_RATE,
+};
+
+pub const PING_INTERVAL: Duration = Duration::from_secs(1);
+pub const RECONNECT_BASE_DELAY: Duration = Duration::from_millis(500);
+pub const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(30);
+pub const SNAPSHOT_HISTORY: usize = 32;
+// the fixed timestep is stretched or squeezed by at most this fraction
+pub const MAX_DILATION: f32 = 0.05;
+pub const DILATION_GAIN: f32 = 0.1;
+// corrections bigger than this many ticks snap instead of converging
+pub const SNAP_THRESHOLD: i64 = 10;
+pub const INPUT_REDUNDANCY: usize = 5;
+// remote players are drawn this many ticks behind the newest snapshot
+pub const INTERPOLATION_DELAY: f32 = 2.0;
+// and extrapolated at most this far past it when snapshots are late
+pub const MAX_EXTRAPOLATION: f32 = 3.0;
+pub const ENEMY_SNAPSHOTS: usize = 20;
+// a predicted collision the server hasn't confirmed this many ticks later is rolled back
+pub const PREDICTION_SLACK: u64 = 2;
+pub const HIT_FLASH_TICKS: u64 = 3;
+// our objects as of the last few checksum ticks, kept to compare with the server's
+pub const CHECKSUM_HISTORY: usize = 8;
+// how far back a late server update can still be rolled back to
+pub const ROLLBACK_HISTORY: usize = 64;
+pub const OUTGOING_BACKLOG: usize = 64;
+// how opaque the drops that can't hit the local player are drawn
+pub const HARMLESS_ALPHA: f32 = 0.3;
+// unacked inputs kept for resending and replay, the oldest go first
+pub const MAX_PENDING_INPUTS: usize = 64;
 
 #[derive(Resource)]
 pub struct Objects {
-    pub rain_pos: Vec<ObjectPos>,
-    pub bolt_pos: Vec<ObjectPos>,
+    pub field: Field,
     pub rng_seed: Option<u64>,
     pub high_scores: Vec<(String, u64)>,
 }
@@ -17,25 +60,80 @@ pub struct Objects {
 impl Objects {
     pub fn new() -> Self {
         Self {
-            rain_pos: Vec::new(),
-            bolt_pos: Vec::new(),
+            field: Field::new(),
             rng_seed: None,
             high_scores: Vec::new(),
         }
     }
 }
 
-#[derive(Resource)]
-pub struct RainPool(pub VecDeque<Entity>);
+// an object the local player collided with before the server said so
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Prediction {
+    pub tick: u64,
+    pub object: Object,
+}
 
 #[derive(Resource)]
-pub struct BoltPool(pub VecDeque<Entity>);
+pub struct Predictions {
+    pub pending: Vec<Prediction>,
+    pub flash: u64,
+}
+
+impl Predictions {
+    pub fn new() -> Self {
+        Self {
+            pending: Vec::new(),
+            flash: 0,
+        }
+    }
+
+    pub fn predict(&mut self, tick: u64, object: Object) {
+        if object.id.kind.def().effect == Effect::Damage {
+            self.flash = HIT_FLASH_TICKS;
+        }
+        self.pending.push(Prediction { tick, object });
+    }
+
+    // the server decided about this object, returns whether we had predicted it
+    pub fn resolve(&mut self, id: ObjectId) -> bool {
+        let before = self.pending.len();
+        self.pending.retain(|p| p.object.id != id);
+        self.pending.len() != before
+    }
+
+    pub fn pending(&self, effect: Effect) -> usize {
+        self.pending
+            .iter()
+            .filter(|p| p.object.id.kind.def().effect == effect)
+            .count()
+    }
+
+    // everything the server simulated past without agreeing with us
+    pub fn expire(&mut self, server_tick: u64) -> Vec<Prediction> {
+        let (expired, pending) = self
+            .pending
+            .drain(..)
+            .partition(|p| p.tick + PREDICTION_SLACK < server_tick);
+        self.pending = pending;
+        expired
+    }
+
+    pub fn clear(&mut self) {
+        self.pending.clear();
+        self.flash = 0;
+    }
+}
+
+// objects this far outside the camera are still drawn
+pub const CULL_MARGIN: f32 = 50.0;
 
 //server
 #[derive(Resource)]
 pub struct NetworkStuff {
     pub write: Option<Sender<ClientMessage>>,
     pub read: Option<Receiver<Vec<u8>>>,
+    pub backlog: VecDeque<ClientMessage>,
 }
 
 impl NetworkStuff {
@@ -43,14 +141,99 @@ impl NetworkStuff {
         Self {
             write: None,
             read: None,
+            backlog: VecDeque::new(),
         }
     }
+
+    // Messages that don't fit in the channel wait in the backlog, in order,
+    // where a newer message of a coalescable kind replaces the queued one.
+    // Past OUTGOING_BACKLOG the oldest inputs are dropped, the handshake and
+    // session messages always wait their turn.
+    pub fn send(&mut self, message: ClientMessage) {
+        let Some(ref mut write) = self.write else {
+            return;
+        };
+
+        let message = if self.backlog.is_empty() {
+            match write.try_send(message) {
+                Ok(()) => return,
+                Err(e) if e.is_full() => e.into_inner(),
+                // the disconnect signal takes it from here
+                Err(_) => return,
+            }
+        } else {
+            message
+        };
+
+        if coalesces(&message) {
+            let kind = std::mem::discriminant(&message);
+            self.backlog
+                .retain(|queued| std::mem::discriminant(queued) != kind);
+        }
+        self.backlog.push_back(message);
+
+        if self.backlog.len() > OUTGOING_BACKLOG {
+            if let Some(index) = self.backlog.iter().position(droppable) {
+                let dropped = self.backlog.remove(index);
+                warn!("outgoing backlog full, dropping {:?}", dropped);
+            }
+        }
+    }
+
+    pub fn flush(&mut self) {
+        let Some(ref mut write) = self.write else {
+            return;
+        };
+
+        while let Some(message) = self.backlog.pop_front() {
+            match write.try_send(message) {
+                Ok(()) => {}
+                Err(e) if e.is_full() => {
+                    self.backlog.push_front(e.into_inner());
+                    break;
+                }
+                Err(_) => {
+                    self.backlog.clear();
+                    break;
+                }
+            }
+        }
+    }
+}
+
+// only the newest batch matters, an InputBatch repeats every unacked input
+fn coalesces(message: &ClientMessage) -> bool {
+    matches!(message, ClientMessage::InputBatch(_))
+}
+
+// a stale input is overtaken by the next one and reconciled away
+fn droppable(message: &ClientMessage) -> bool {
+    matches!(
+        message,
+        ClientMessage::PlayerInput(_) | ClientMessage::InputBatch(_)
+    )
+}
+
+#[derive(Resource)]
+pub struct NetworkTransport(pub Box<dyn Transport>);
+
+impl NetworkTransport {
+    pub fn new() -> Self {
+        #[cfg(target_arch = "wasm32")]
+        let transport = crate::network::websockets::GlooTransport;
+        #[cfg(not(target_arch = "wasm32"))]
+        let transport = crate::network::websockets::TungsteniteTransport;
+
+        Self(Box::new(transport))
+    }
 }
 
 #[derive(Resource)]
 pub struct ClientTick {
     pub tick: Option<u64>,
     pub pause: i64,
+    // ticks we are ahead of where the server wants us, worked off by dilation
+    pub drift: f32,
 }
 
 impl ClientTick {
@@ -58,12 +241,27 @@ impl ClientTick {
         Self {
             tick: None,
             pause: 0,
+            drift: 0.0,
+        }
+    }
+
+    // positive runs the clock slow, negative fast
+    pub fn dilation(&self) -> f32 {
+        if self.drift.abs() < 0.01 {
+            return 0.0;
         }
+        (self.drift * DILATION_GAIN).clamp(-MAX_DILATION, MAX_DILATION)
+    }
+
+    // each dilated tick takes (1 + dilation) ticks of real time
+    pub fn period(&self) -> Duration {
+        Duration::from_secs_f32(TICK_RATE * (1.0 + self.dilation()))
     }
 }
 
 #[derive(Resource)]
 pub struct PingTimer {
+    pub timer: Timer,
     pub disconnected_rx: Option<Receiver<()>>,
     pub disconnected_tx: Option<Sender<()>>,
 }
@@ -71,12 +269,262 @@ pub struct PingTimer {
 impl PingTimer {
     pub fn new() -> Self {
         Self {
+            timer: Timer::new(PING_INTERVAL, TimerMode::Repeating),
             disconnected_rx: None,
             disconnected_tx: None,
         }
     }
 }
 
+#[derive(Resource)]
+pub struct Protocol {
+    pub server: Option<Handshake>,
+    pub decoded: u64,
+    pub undecodable: u64,
+}
+
+impl Protocol {
+    pub fn new() -> Self {
+        Self {
+            server: None,
+            decoded: 0,
+            undecodable: 0,
+        }
+    }
+
+    pub fn is_compatible(&self) -> bool {
+        !matches!(self.server, Some(server) if server.protocol_version != PROTOCOL_VERSION)
+    }
+
+    pub fn supports(&self, capability: u32) -> bool {
+        self.server.is_some_and(|server| {
+            server.capabilities & CLIENT_CAPABILITIES & capability == capability
+        })
+    }
+}
+
+// decoded messages waiting for handle_server, filled by receive_frames
+#[derive(Resource)]
+pub struct Inbox(pub Vec<NetworkMessage>);
+
+impl Inbox {
+    pub fn new() -> Self {
+        Self(Vec::new())
+    }
+}
+
+// recently received snapshots, the server may delta against any we acked
+#[derive(Resource)]
+pub struct SnapshotBaselines {
+    pub frames: VecDeque<(u64, Frame)>,
+}
+
+impl SnapshotBaselines {
+    pub fn new() -> Self {
+        Self {
+            frames: VecDeque::new(),
+        }
+    }
+
+    pub fn get(&self, tick: u64) -> Option<&Frame> {
+        self.frames
+            .iter()
+            .find(|(frame_tick, _)| *frame_tick == tick)
+            .map(|(_, frame)| frame)
+    }
+
+    pub fn push(&mut self, tick: u64, frame: Frame) {
+        self.frames.push_back((tick, frame));
+        while self.frames.len() > SNAPSHOT_HISTORY {
+            self.frames.pop_front();
+        }
+    }
+}
+
+// what the server has acknowledged of Player.pending_inputs
+#[derive(Resource)]
+pub struct InputSync {
+    pub acked: Option<u64>,
+    pub last_sent_tick: Option<u64>,
+    pub dirty: bool,
+}
+
+impl InputSync {
+    pub fn new() -> Self {
+        Self {
+            acked: None,
+            last_sent_tick: None,
+            dirty: false,
+        }
+    }
+
+    pub fn is_acked(&self, input: &PlayerInput) -> bool {
+        self.acked.is_some_and(|acked| input.tick <= acked)
+    }
+}
+
+// smoothed round trip time and its mean deviation, RFC 6298 style
+#[derive(Resource)]
+pub struct Latency {
+    pub srtt_ms: Option<f32>,
+    pub jitter_ms: f32,
+}
+
+impl Latency {
+    pub fn new() -> Self {
+        Self {
+            srtt_ms: None,
+            jitter_ms: 0.0,
+        }
+    }
+
+    pub fn sample(&mut self, rtt_ms: f32) {
+        match self.srtt_ms {
+            Some(srtt) => {
+                self.jitter_ms = 0.75 * self.jitter_ms + 0.25 * (srtt - rtt_ms).abs();
+                self.srtt_ms = Some(0.875 * srtt + 0.125 * rtt_ms);
+            }
+            None => {
+                self.jitter_ms = rtt_ms / 2.0;
+                self.srtt_ms = Some(rtt_ms);
+            }
+        }
+    }
+
+    pub fn one_way_ticks(&self) -> u64 {
+        self.srtt_ms
+            .map(|srtt| (srtt / 2.0 / 1000.0 / TICK_RATE).ceil() as u64)
+            .unwrap_or(0)
+    }
+
+    // how far a remote player may be extrapolated from its last known state
+    pub fn max_extrapolation_ticks(&self) -> u64 {
+        self.srtt_ms
+            .map(|srtt| ((srtt + self.jitter_ms) / 1000.0 / TICK_RATE).ceil() as u64 + 1)
+            .unwrap_or(u64::MAX)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SmoothingMode {
+    Interpolate,
+    Extrapolate,
+}
+
+// how remote players are placed between server updates
+#[derive(Resource)]
+pub struct RemoteSmoothing {
+    pub mode: SmoothingMode,
+    pub delay_ticks: f32,
+    pub max_extrapolation: f32,
+    // client ticks between a snapshot being simulated and it arriving here
+    pub offset: Option<f32>,
+}
+
+impl RemoteSmoothing {
+    pub fn new() -> Self {
+        Self {
+            mode: SmoothingMode::Interpolate,
+            delay_ticks: INTERPOLATION_DELAY,
+            max_extrapolation: MAX_EXTRAPOLATION,
+            offset: None,
+        }
+    }
+
+    pub fn observe(&mut self, client_tick: u64, snapshot_tick: u64) {
+        let sample = client_tick as f32 - snapshot_tick as f32;
+        self.offset = Some(match self.offset {
+            Some(offset) if (sample - offset).abs() < SNAP_THRESHOLD as f32 => {
+                0.9 * offset + 0.1 * sample
+            }
+            _ => sample,
+        });
+    }
+
+    // the server tick remote players are drawn at, `now` is a fractional client tick
+    pub fn render_tick(&self, now: f32) -> Option<f32> {
+        self.offset.map(|offset| now - offset - self.delay_ticks)
+    }
+}
+
+#[derive(Resource)]
+pub struct Checksums {
+    pub recorded: BTreeMap<u64, Field>,
+    // the tick we asked the server's objects for
+    pub pending: Option<u64>,
+    pub desyncs: u64,
+}
+
+impl Checksums {
+    pub fn new() -> Self {
+        Self {
+            recorded: BTreeMap::new(),
+            pending: None,
+            desyncs: 0,
+        }
+    }
+
+    pub fn record(&mut self, tick: u64, field: &Field) {
+        self.recorded.insert(tick, field.clone());
+        while self.recorded.len() > CHECKSUM_HISTORY {
+            self.recorded.pop_first();
+        }
+    }
+
+    // the server removes objects on the tick they are hit, we only hear about it later
+    pub fn remove_object(&mut self, id: ObjectId) {
+        for field in self.recorded.values_mut() {
+            field.remove(id);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.recorded.clear();
+        self.pending = None;
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum NetcodeMode {
+    // snap to the server and replay our own pending inputs
+    Reconcile,
+    // keep the whole simulation per tick and re-simulate from confirmed ticks
+    Rollback,
+}
+
+#[derive(Resource)]
+pub struct Rollback {
+    pub mode: NetcodeMode,
+    pub history: History<Uuid>,
+    pub rollbacks: u64,
+}
+
+impl Rollback {
+    pub fn new() -> Self {
+        Self {
+            mode: NetcodeMode::Reconcile,
+            history: History::new(ROLLBACK_HISTORY),
+            rollbacks: 0,
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.mode == NetcodeMode::Rollback
+    }
+
+    // the server removed an object and maybe settled a score, true for every tick we kept
+    pub fn server_event(&mut self, id: ObjectId, score: Option<(Uuid, usize)>) {
+        for state in self.history.states_mut() {
+            state.field.remove(id);
+            if let Some((id, score)) = score {
+                if let Some(runner) = state.runners.get_mut(&id) {
+                    runner.score = runner.score.max(score);
+                }
+            }
+        }
+    }
+}
+
 #[derive(Resource)]
 pub struct PlayerName {
     pub name: String,
@@ -93,3 +541,145 @@ impl PlayerName {
         }
     }
 }
+
+#[derive(Clone, Copy)]
+pub struct Session {
+    pub id: Uuid,
+    pub resume_token: u64,
+}
+
+#[derive(Resource)]
+pub struct Reconnect {
+    pub attempt: u32,
+    pub retry_at: Option<Instant>,
+    pub resume_stage: Option<GameStage>,
+    pub session: Option<Session>,
+    pub resuming: Option<Session>,
+}
+
+impl Reconnect {
+    pub fn new() -> Self {
+        Self {
+            attempt: 0,
+            retry_at: None,
+            resume_stage: None,
+            session: None,
+            resuming: None,
+        }
+    }
+
+    // exponential backoff with "equal jitter": half the capped delay is fixed,
+    // the other half is random so a server restart doesn't get a thundering herd
+    pub fn schedule(&mut self, seed: u64) {
+        let cap = (RECONNECT_BASE_DELAY * 2u32.pow(self.attempt.min(6))).min(RECONNECT_MAX_DELAY);
+        let mut rng = ChaCha8Rng::seed_from_u64(seed ^ self.attempt as u64);
+        let delay = cap / 2 + cap.mul_f32(rng.gen_range(0.0..0.5));
+
+        self.retry_at = Some(Instant::now() + delay);
+    }
+}
+
+pub struct Region {
+    pub name: String,
+    pub url: String,
+    pub latency: Option<Duration>,
+    pub probing: bool,
+}
+
+impl Region {
+    pub fn new(name: &str, url: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            url: url.to_string(),
+            latency: None,
+            probing: false,
+        }
+    }
+}
+
+#[derive(Resource)]
+pub struct ServerEndpoint {
+    pub regions: Vec<Region>,
+    pub selected: usize,
+    pub pending: Option<usize>,
+}
+
+impl ServerEndpoint {
+    pub fn new(regions: Vec<Region>, selected: usize) -> Self {
+        Self {
+            regions,
+            selected,
+            pending: None,
+        }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.regions[self.selected].url
+    }
+}
+
+pub struct Probe {
+    pub region: usize,
+    pub started: Instant,
+    // held so the probe socket stays open until it is dropped
+    pub _outgoing: Sender<ClientMessage>,
+    pub incoming: Receiver<Vec<u8>>,
+    pub disconnected: Receiver<()>,
+}
+
+#[derive(Resource)]
+pub struct RegionProbes {
+    pub probes: Vec<Probe>,
+    pub refresh: bool,
+}
+
+impl RegionProbes {
+    pub fn new() -> Self {
+        Self {
+            probes: Vec::new(),
+            refresh: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Latency;
+
+    #[test]
+    fn the_first_sample_sets_srtt_and_half_of_it_as_jitter() {
+        let mut latency = Latency::new();
+        assert_eq!(latency.one_way_ticks(), 0);
+        assert_eq!(latency.max_extrapolation_ticks(), u64::MAX);
+
+        latency.sample(100.0);
+        assert_eq!(latency.srtt_ms, Some(100.0));
+        assert_eq!(latency.jitter_ms, 50.0);
+        assert_eq!(latency.one_way_ticks(), 1);
+        assert_eq!(latency.max_extrapolation_ticks(), 3);
+    }
+
+    #[test]
+    fn later_samples_move_jitter_from_the_old_srtt_then_srtt() {
+        let mut latency = Latency::new();
+        latency.sample(100.0);
+        latency.sample(180.0);
+        // jitter uses the srtt from before this sample: 0.75 * 50 + 0.25 * 80
+        assert_eq!(latency.jitter_ms, 57.5);
+        assert_eq!(latency.srtt_ms, Some(110.0));
+
+        latency.sample(110.0);
+        assert_eq!(latency.jitter_ms, 43.125);
+        assert_eq!(latency.srtt_ms, Some(110.0));
+    }
+
+    #[test]
+    fn steady_samples_settle_jitter_towards_zero() {
+        let mut latency = Latency::new();
+        for _ in 0..100 {
+            latency.sample(64.0);
+        }
+        assert_eq!(latency.srtt_ms, Some(64.0));
+        assert!(latency.jitter_ms < 0.001);
+    }
+}
@@ -3,20 +3,34 @@ use bevy_ecs_ldtk::prelude::*;
 
 use bevy_egui::EguiPlugin;
 use game_core::{
-    game_loop::{enemy_loop, player_loop, tick},
-    gui::{check_disconnected, disconnected, game_over, score_board, setup_menu},
+    checksums::record_checksum,
+    collisions::predict_collisions,
+    game_loop::{enemy_loop, interpolate_enemies, player_loop, tick},
+    gui::{
+        check_disconnected, disconnected, game_over, out_of_date, power_hud, score_board,
+        setup_menu,
+    },
     handle::handle_server,
-    input::{input, update_joystick},
-    objects::{handle_bolt, handle_rain},
-    sprites::{pool_bolt, pool_rain, spawn_ldtk},
+    input::{input, send_inputs, update_joystick},
+    instancing::ObjectInstancingPlugin,
+    objects::handle_objects,
+    rollback::rollback_step,
+    sprites::{spawn_ldtk, spawn_object_batches},
 };
 
 use game_util::resources::{
-    BoltPool, ClientTick, NetworkStuff, Objects, PingTimer, PlayerName, RainPool,
+    Checksums, ClientTick, Inbox, InputSync, Latency, NetworkStuff, NetworkTransport, Objects,
+    PingTimer, PlayerName, Predictions, Protocol, Reconnect, RegionProbes, RemoteSmoothing,
+    Rollback, SnapshotBaselines,
 };
 use keyboard::KeyboardPlugin;
-use network::websockets::websocket;
-use std::collections::VecDeque;
+#[cfg(debug_assertions)]
+use network::conditioner::NetworkSimulatorPlugin;
+use network::{
+    endpoint::{poll_probes, resolve_endpoint, start_probes, switch_region},
+    receive::receive_frames,
+    websockets::{flush_outgoing, reconnect, send_ping, websocket},
+};
 use virtual_joystick::VirtualJoystickPlugin;
 
 mod game_core;
@@ -24,62 +38,120 @@ mod game_util;
 mod keyboard;
 mod network;
 
-pub const TICK_RATE: f32 = 1. / 10.;
+pub use satrunner_sim::TICK_RATE;
 
 fn main() {
-    App::new()
-        .add_plugins((
-            DefaultPlugins
-                .set(WindowPlugin {
-                    primary_window: Some(Window {
-                        title: "rain.run".to_string(),
-                        fit_canvas_to_parent: true,
-                        prevent_default_event_handling: false,
-                        ..default()
-                    }),
+    let mut app = App::new();
+    app.add_plugins((
+        DefaultPlugins
+            .set(WindowPlugin {
+                primary_window: Some(Window {
+                    title: "rain.run".to_string(),
+                    fit_canvas_to_parent: true,
+                    prevent_default_event_handling: false,
                     ..default()
-                })
-                .set(ImagePlugin::default_nearest()),
-            EguiPlugin,
-            LdtkPlugin,
-            KeyboardPlugin,
-            VirtualJoystickPlugin::<String>::default(),
-        ))
-        .insert_resource(LevelSelection::Index(0))
-        .insert_resource(LdtkSettings {
-            level_spawn_behavior: LevelSpawnBehavior::UseWorldTranslation {
-                load_level_neighbors: false,
-            },
-            level_background: LevelBackground::Nonexistent,
-            ..Default::default()
-        })
-        .register_ldtk_entity::<MyBundle>("background")
-        .add_state::<GameStage>()
-        .add_state::<KeyboardState>()
-        .add_systems(Startup, (spawn_ldtk, pool_rain, pool_bolt, websocket))
-        .add_systems(Update, setup_menu.run_if(in_state(GameStage::Menu)))
-        .add_systems(Update, (handle_server, score_board, check_disconnected))
-        .add_systems(FixedUpdate, (tick, enemy_loop, handle_rain, handle_bolt))
-        .add_systems(Update, (input).run_if(in_state(GameStage::InGame)))
-        .add_systems(
-            Update,
-            (disconnected).run_if(in_state(GameStage::Disconnected)),
-        )
-        .add_systems(Update, (game_over).run_if(in_state(GameStage::GameOver)))
-        .add_systems(
-            FixedUpdate,
-            (player_loop).run_if(in_state(GameStage::InGame)),
+                }),
+                ..default()
+            })
+            .set(ImagePlugin::default_nearest()),
+        EguiPlugin,
+        LdtkPlugin,
+        KeyboardPlugin,
+        ObjectInstancingPlugin,
+        VirtualJoystickPlugin::<String>::default(),
+    ))
+    .insert_resource(LevelSelection::Index(0))
+    .insert_resource(LdtkSettings {
+        level_spawn_behavior: LevelSpawnBehavior::UseWorldTranslation {
+            load_level_neighbors: false,
+        },
+        level_background: LevelBackground::Nonexistent,
+        ..Default::default()
+    })
+    .register_ldtk_entity::<MyBundle>("background")
+    .add_state::<GameStage>()
+    .add_state::<KeyboardState>()
+    .add_systems(
+        Startup,
+        (spawn_ldtk, spawn_object_batches, websocket, start_probes),
+    )
+    .add_systems(Update, setup_menu.run_if(in_state(GameStage::Menu)))
+    .add_systems(
+        Update,
+        (
+            poll_probes,
+            switch_region,
+            start_probes.run_if(|probes: Res<RegionProbes>| probes.refresh),
+        ),
+    )
+    .add_systems(
+        Update,
+        (
+            receive_frames.before(handle_server),
+            handle_server,
+            interpolate_enemies.after(handle_server),
+            score_board,
+            check_disconnected,
+            send_ping,
+            send_inputs.after(input),
+            flush_outgoing.after(send_inputs).after(send_ping),
+        ),
+    )
+    .add_systems(
+        FixedUpdate,
+        (
+            tick,
+            enemy_loop,
+            rollback_step.after(tick).before(handle_objects),
+            handle_objects,
+            record_checksum
+                .after(handle_objects)
+                .after(predict_collisions),
+        ),
+    )
+    .add_systems(
+        Update,
+        (input, power_hud).run_if(in_state(GameStage::InGame)),
+    )
+    .add_systems(
+        Update,
+        (disconnected, reconnect).run_if(in_state(GameStage::Disconnected)),
+    )
+    .add_systems(Update, (game_over).run_if(in_state(GameStage::GameOver)))
+    .add_systems(Update, out_of_date.run_if(in_state(GameStage::OutOfDate)))
+    .add_systems(
+        FixedUpdate,
+        (
+            player_loop,
+            predict_collisions.after(player_loop).after(handle_objects),
         )
-        .insert_resource(FixedTime::new_from_secs(TICK_RATE))
-        .insert_resource(ClearColor(Color::BLACK))
-        .insert_resource(Objects::new())
-        .insert_resource(RainPool(VecDeque::new()))
-        .insert_resource(BoltPool(VecDeque::new()))
-        .insert_resource(NetworkStuff::new())
-        .insert_resource(ClientTick::new())
-        .insert_resource(PlayerName::new())
-        .insert_resource(PingTimer::new())
-        .run();
+            .run_if(in_state(GameStage::InGame)),
+    )
+    .insert_resource(FixedTime::new_from_secs(TICK_RATE))
+    .insert_resource(ClearColor(Color::BLACK))
+    .insert_resource(Objects::new())
+    .insert_resource(NetworkStuff::new())
+    .insert_resource(NetworkTransport::new())
+    .insert_resource(resolve_endpoint())
+    .insert_resource(RegionProbes::new())
+    .insert_resource(ClientTick::new())
+    .insert_resource(PlayerName::new())
+    .insert_resource(PingTimer::new())
+    .insert_resource(Latency::new())
+    .insert_resource(Protocol::new())
+    .insert_resource(Inbox::new())
+    .insert_resource(InputSync::new())
+    .insert_resource(SnapshotBaselines::new())
+    .insert_resource(RemoteSmoothing::new())
+    .insert_resource(Predictions::new())
+    .insert_resource(Rollback::new())
+    .insert_resource(Checksums::new())
+    .insert_resource(Reconnect::new());
+
+    #[cfg(debug_assertions)]
+    app.add_plugins(NetworkSimulatorPlugin);
+
+    app.run();
 }
 
 #[derive(States, Clone, Eq, PartialEq, Debug, Hash, Default)]
@@ -89,6 +161,7 @@ pub enum GameStage {
     InGame,
     Disconnected,
     GameOver,
+    OutOfDate,
 }
 
 #[derive(Clone, Copy, Default, Eq, PartialEq, Debug, Hash, States)]
@@ -0,0 +1,439 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use bevy::{prelude::*, utils::Instant};
+use bevy_egui::{egui, EguiContexts};
+use futures::channel::mpsc::{channel, Receiver, Sender};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+use crate::game_util::resources::{
+    NetcodeMode, NetworkTransport, RemoteSmoothing, Rollback, SmoothingMode,
+};
+
+use super::{
+    messages::ClientMessage,
+    transport::{Transport, TransportChannels, TransportError},
+};
+
+// Debug plugin that sits between the game and the real transport and degrades
+// the link in both directions. Everything random is drawn from a seeded rng so
+// a given set of conditions replays the same way. Toggle the panel with F3.
+pub struct NetworkSimulatorPlugin;
+
+impl Plugin for NetworkSimulatorPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(NetworkSimulator::new())
+            .add_systems(PreUpdate, pump_links)
+            .add_systems(Update, simulator_panel);
+    }
+
+    fn finish(&self, app: &mut App) {
+        let inner = app
+            .world
+            .remove_resource::<NetworkTransport>()
+            .unwrap_or_else(NetworkTransport::new);
+        let state = app.world.resource::<NetworkSimulator>().state.clone();
+
+        app.insert_resource(NetworkTransport(Box::new(ConditionedTransport {
+            inner: inner.0,
+            state,
+        })));
+    }
+}
+
+#[derive(Clone, PartialEq)]
+pub struct NetworkConditions {
+    pub enabled: bool,
+    pub latency_ms: u32,
+    pub jitter_ms: u32,
+    pub reorder: f32,
+    pub duplicate: f32,
+    pub loss: f32,
+    pub seed: u64,
+}
+
+impl NetworkConditions {
+    pub fn new() -> Self {
+        Self {
+            enabled: false,
+            latency_ms: 100,
+            jitter_ms: 20,
+            reorder: 0.0,
+            duplicate: 0.0,
+            loss: 0.0,
+            seed: 0,
+        }
+    }
+}
+
+#[derive(Default, Clone, Copy)]
+pub struct LinkStats {
+    pub delivered: u64,
+    pub dropped: u64,
+    pub duplicated: u64,
+    pub reordered: u64,
+}
+
+pub struct DelayQueue<T> {
+    queue: Vec<(Instant, T)>,
+    last_release: Option<Instant>,
+    pub stats: LinkStats,
+}
+
+impl<T: Clone> DelayQueue<T> {
+    pub fn new() -> Self {
+        Self {
+            queue: Vec::new(),
+            last_release: None,
+            stats: LinkStats::default(),
+        }
+    }
+
+    pub fn push(
+        &mut self,
+        item: T,
+        now: Instant,
+        conditions: &NetworkConditions,
+        rng: &mut ChaCha8Rng,
+    ) {
+        if !conditions.enabled {
+            self.enqueue(item, now);
+            return;
+        }
+
+        if rng.gen::<f32>() < conditions.loss {
+            self.stats.dropped += 1;
+            return;
+        }
+
+        let copies = if rng.gen::<f32>() < conditions.duplicate {
+            self.stats.duplicated += 1;
+            2
+        } else {
+            1
+        };
+
+        for _ in 0..copies {
+            let jitter = rng.gen_range(0..=conditions.jitter_ms);
+            let mut release_at =
+                now + Duration::from_millis((conditions.latency_ms + jitter) as u64);
+
+            if rng.gen::<f32>() < conditions.reorder {
+                // held back past whatever is sent next
+                let hold = rng.gen_range(0..=conditions.latency_ms + conditions.jitter_ms);
+                release_at += Duration::from_millis(hold as u64);
+                self.stats.reordered += 1;
+            } else if let Some(last) = self.last_release {
+                // websockets are ordered, jitter alone must not overtake
+                release_at = release_at.max(last);
+            }
+
+            self.enqueue(item.clone(), release_at);
+        }
+    }
+
+    fn enqueue(&mut self, item: T, release_at: Instant) {
+        self.last_release = Some(self.last_release.map_or(release_at, |l| l.max(release_at)));
+        let index = self.queue.partition_point(|(at, _)| *at <= release_at);
+        self.queue.insert(index, (release_at, item));
+    }
+
+    pub fn pop_due(&mut self, now: Instant) -> Vec<T> {
+        let due = self.queue.partition_point(|(at, _)| *at <= now);
+        self.stats.delivered += due as u64;
+        self.queue.drain(..due).map(|(_, item)| item).collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
+
+struct Link {
+    outgoing: Receiver<ClientMessage>,
+    transport_outgoing: Sender<ClientMessage>,
+    transport_incoming: Receiver<Vec<u8>>,
+    incoming: Sender<Vec<u8>>,
+    send_queue: DelayQueue<ClientMessage>,
+    receive_queue: DelayQueue<Vec<u8>>,
+    transport_closed: bool,
+}
+
+pub struct Simulation {
+    pub conditions: NetworkConditions,
+    pub send_stats: LinkStats,
+    pub receive_stats: LinkStats,
+    rng: ChaCha8Rng,
+    links: Vec<Link>,
+}
+
+impl Simulation {
+    pub fn apply(&mut self, conditions: NetworkConditions) {
+        self.rng = ChaCha8Rng::seed_from_u64(conditions.seed);
+        self.conditions = conditions;
+    }
+}
+
+#[derive(Resource)]
+pub struct NetworkSimulator {
+    pub state: Arc<Mutex<Simulation>>,
+    pub panel_open: bool,
+}
+
+impl NetworkSimulator {
+    pub fn new() -> Self {
+        let conditions = NetworkConditions::new();
+        Self {
+            state: Arc::new(Mutex::new(Simulation {
+                rng: ChaCha8Rng::seed_from_u64(conditions.seed),
+                conditions,
+                send_stats: LinkStats::default(),
+                receive_stats: LinkStats::default(),
+                links: Vec::new(),
+            })),
+            panel_open: false,
+        }
+    }
+}
+
+struct ConditionedTransport {
+    inner: Box<dyn Transport>,
+    state: Arc<Mutex<Simulation>>,
+}
+
+impl Transport for ConditionedTransport {
+    fn open(&self, url: &str, channels: TransportChannels) -> Result<(), TransportError> {
+        let (transport_outgoing, send_rx) = channel::<ClientMessage>(1000);
+        let (read_tx, transport_incoming) = channel::<Vec<u8>>(20000);
+
+        self.inner.open(
+            url,
+            TransportChannels {
+                outgoing: send_rx,
+                incoming: read_tx,
+                disconnected: channels.disconnected,
+            },
+        )?;
+
+        self.state.lock().unwrap().links.push(Link {
+            outgoing: channels.outgoing,
+            transport_outgoing,
+            transport_incoming,
+            incoming: channels.incoming,
+            send_queue: DelayQueue::new(),
+            receive_queue: DelayQueue::new(),
+            transport_closed: false,
+        });
+
+        Ok(())
+    }
+}
+
+pub fn pump_links(simulator: Res<NetworkSimulator>) {
+    let now = Instant::now();
+    let mut state = simulator.state.lock().unwrap();
+    let Simulation {
+        conditions,
+        rng,
+        links,
+        ..
+    } = &mut *state;
+
+    links.retain_mut(|link| {
+        loop {
+            match link.outgoing.try_next() {
+                Ok(Some(message)) => link.send_queue.push(message, now, conditions, rng),
+                // the game dropped this connection, dropping the link closes the transport
+                Ok(None) => return false,
+                Err(_) => break,
+            }
+        }
+        loop {
+            match link.transport_incoming.try_next() {
+                Ok(Some(frame)) => link.receive_queue.push(frame, now, conditions, rng),
+                Ok(None) => {
+                    link.transport_closed = true;
+                    break;
+                }
+                Err(_) => break,
+            }
+        }
+
+        for message in link.send_queue.pop_due(now) {
+            if let Err(e) = link.transport_outgoing.try_send(message) {
+                error!("Error sending message: {} CHANNEL FULL???", e);
+            }
+        }
+        for frame in link.receive_queue.pop_due(now) {
+            if let Err(e) = link.incoming.try_send(frame) {
+                error!("Error sending message: {} CHANNEL FULL???", e);
+            }
+        }
+
+        !(link.transport_closed && link.receive_queue.is_empty())
+    });
+
+    let (send_stats, receive_stats) = links.iter().fold(
+        (LinkStats::default(), LinkStats::default()),
+        |(send, receive), link| {
+            (
+                sum(send, link.send_queue.stats),
+                sum(receive, link.receive_queue.stats),
+            )
+        },
+    );
+    state.send_stats = send_stats;
+    state.receive_stats = receive_stats;
+}
+
+fn sum(a: LinkStats, b: LinkStats) -> LinkStats {
+    LinkStats {
+        delivered: a.delivered + b.delivered,
+        dropped: a.dropped + b.dropped,
+        duplicated: a.duplicated + b.duplicated,
+        reordered: a.reordered + b.reordered,
+    }
+}
+
+pub fn simulator_panel(
+    mut contexts: EguiContexts,
+    mut simulator: ResMut<NetworkSimulator>,
+    mut smoothing: ResMut<RemoteSmoothing>,
+    mut rollback: ResMut<Rollback>,
+    keys: Res<Input<KeyCode>>,
+) {
+    if keys.just_pressed(KeyCode::F3) {
+        simulator.panel_open = !simulator.panel_open;
+    }
+    if !simulator.panel_open {
+        return;
+    }
+
+    let mut state = simulator.state.lock().unwrap();
+    let mut conditions = state.conditions.clone();
+
+    egui::Window::new("network simulator")
+        .resizable(false)
+        .anchor(egui::Align2::RIGHT_TOP, egui::Vec2::new(-10.0, 10.0))
+        .show(contexts.ctx_mut(), |ui| {
+            ui.checkbox(&mut conditions.enabled, "enabled");
+            ui.add(egui::Slider::new(&mut conditions.latency_ms, 0..=1000).text("latency ms"));
+            ui.add(egui::Slider::new(&mut conditions.jitter_ms, 0..=500).text("jitter ms"));
+            ui.add(egui::Slider::new(&mut conditions.reorder, 0.0..=1.0).text("reorder"));
+            ui.add(egui::Slider::new(&mut conditions.duplicate, 0.0..=1.0).text("duplicate"));
+            ui.add(egui::Slider::new(&mut conditions.loss, 0.0..=1.0).text("loss"));
+            ui.add(egui::DragValue::new(&mut conditions.seed).prefix("seed "));
+
+            for (label, stats) in [("send", state.send_stats), ("receive", state.receive_stats)] {
+                ui.label(format!(
+                    "{}: {} delivered, {} dropped, {} duplicated, {} reordered",
+                    label, stats.delivered, stats.dropped, stats.duplicated, stats.reordered
+                ));
+            }
+
+            ui.separator();
+            ui.horizontal(|ui| {
+                ui.label("netcode");
+                ui.radio_value(&mut rollback.mode, NetcodeMode::Reconcile, "reconcile");
+                ui.radio_value(&mut rollback.mode, NetcodeMode::Rollback, "rollback");
+            });
+            ui.label(format!("{} rollbacks", rollback.rollbacks));
+            ui.horizontal(|ui| {
+                ui.label("remote players");
+                ui.radio_value(
+                    &mut smoothing.mode,
+                    SmoothingMode::Interpolate,
+                    "interpolate",
+                );
+                ui.radio_value(
+                    &mut smoothing.mode,
+                    SmoothingMode::Extrapolate,
+                    "extrapolate",
+                );
+            });
+            ui.add(egui::Slider::new(&mut smoothing.delay_ticks, 0.0..=10.0).text("delay ticks"));
+            ui.add(
+                egui::Slider::new(&mut smoothing.max_extrapolation, 0.0..=10.0)
+                    .text("max extrapolation ticks"),
+            );
+        });
+
+    // any change restarts the rng so a scenario can be replayed from its seed
+    if conditions != state.conditions {
+        state.apply(conditions);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bevy::utils::Instant;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use super::{DelayQueue, NetworkConditions};
+
+    fn run(conditions: &NetworkConditions, items: u32) -> Vec<u32> {
+        let start = Instant::now();
+        let mut rng = ChaCha8Rng::seed_from_u64(conditions.seed);
+        let mut queue = DelayQueue::new();
+        for item in 0..items {
+            queue.push(item, start, conditions, &mut rng);
+        }
+        queue.pop_due(start + Duration::from_secs(10))
+    }
+
+    #[test]
+    fn disabled_passes_through_immediately() {
+        let conditions = NetworkConditions {
+            loss: 1.0,
+            ..NetworkConditions::new()
+        };
+        let start = Instant::now();
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let mut queue = DelayQueue::new();
+        queue.push(1, start, &conditions, &mut rng);
+        assert_eq!(queue.pop_due(start), vec![1]);
+    }
+
+    #[test]
+    fn latency_holds_frames_back_in_order() {
+        let conditions = NetworkConditions {
+            enabled: true,
+            latency_ms: 100,
+            jitter_ms: 50,
+            ..NetworkConditions::new()
+        };
+        let start = Instant::now();
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        let mut queue = DelayQueue::new();
+        for item in 0..20 {
+            queue.push(item, start, &conditions, &mut rng);
+        }
+
+        assert!(queue.pop_due(start + Duration::from_millis(99)).is_empty());
+        let delivered = queue.pop_due(start + Duration::from_millis(150));
+        assert_eq!(delivered, (0..20).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn same_seed_replays_identically() {
+        let conditions = NetworkConditions {
+            enabled: true,
+            reorder: 0.3,
+            duplicate: 0.2,
+            loss: 0.2,
+            seed: 42,
+            ..NetworkConditions::new()
+        };
+
+        let first = run(&conditions, 200);
+        let second = run(&conditions, 200);
+        assert_eq!(first, second);
+        assert_ne!(first, (0..200).collect::<Vec<_>>());
+    }
+}
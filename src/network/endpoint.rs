@@ -0,0 +1,359 @@
+use std::time::Duration;
+
+use bevy::{prelude::*, utils::Instant};
+use futures::channel::mpsc::channel;
+
+use crate::{
+    game_util::resources::{
+        NetworkStuff, NetworkTransport, PingTimer, Probe, Protocol, Reconnect, Region,
+        RegionProbes, ServerEndpoint,
+    },
+    GameStage,
+};
+
+use super::{
+    messages::{ClientMessage, Handshake},
+    transport::TransportChannels,
+    websockets::connect,
+};
+
+pub const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+const DEFAULT_REGIONS: &[(&str, &str)] = &[
+    #[cfg(debug_assertions)]
+    ("local", "ws://0.0.0.0:3030/run"),
+    ("satrunner.gg", "wss://satrunner.gg/run"),
+];
+
+#[derive(Default, Clone)]
+struct Overrides {
+    config: Option<String>,
+    server: Option<String>,
+    region: Option<String>,
+}
+
+// Lowest to highest precedence: built-in regions, config file, env vars, then
+// CLI args (native) or URL query parameters (wasm).
+pub fn resolve_endpoint() -> ServerEndpoint {
+    endpoint_from(overrides())
+}
+
+fn endpoint_from(overrides: Overrides) -> ServerEndpoint {
+    let mut regions = overrides
+        .config
+        .as_deref()
+        .map(parse_regions)
+        .unwrap_or_default();
+    if regions.is_empty() {
+        regions = DEFAULT_REGIONS
+            .iter()
+            .map(|(name, url)| Region::new(name, url))
+            .collect();
+    }
+
+    let mut selected = 0;
+    if let Some(name) = overrides.region {
+        match regions.iter().position(|region| region.name == name) {
+            Some(index) => selected = index,
+            None => warn!("unknown region {}", name),
+        }
+    }
+    if let Some(url) = overrides.server {
+        regions.push(Region::new("custom", &url));
+        selected = regions.len() - 1;
+    }
+
+    ServerEndpoint::new(regions, selected)
+}
+
+// one region per line as `name = url`, `#` starts a comment at the start of a
+// line or after whitespace so urls can still have fragments
+pub fn parse_regions(config: &str) -> Vec<Region> {
+    config
+        .lines()
+        .map(|line| strip_comment(line).trim())
+        .filter_map(|line| line.split_once('='))
+        .map(|(name, url)| (name.trim(), url.trim()))
+        .filter(|(name, url)| !name.is_empty() && !url.is_empty())
+        .map(|(name, url)| Region::new(name, url))
+        .collect()
+}
+
+fn strip_comment(line: &str) -> &str {
+    let comment = line.char_indices().find(|&(index, c)| {
+        c == '#'
+            && line[..index]
+                .chars()
+                .next_back()
+                .is_none_or(char::is_whitespace)
+    });
+    comment.map_or(line, |(index, _)| &line[..index])
+}
+
+#[cfg(target_arch = "wasm32")]
+fn overrides() -> Overrides {
+    let params = web_sys::window()
+        .and_then(|window| window.location().search().ok())
+        .and_then(|search| web_sys::UrlSearchParams::new_with_str(&search).ok());
+
+    match params {
+        Some(params) => Overrides {
+            config: None,
+            server: params.get("server"),
+            region: params.get("region"),
+        },
+        None => Overrides::default(),
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn overrides() -> Overrides {
+    let config_path =
+        std::env::var("SATRUNNER_CONFIG").unwrap_or_else(|_| "satrunner.cfg".to_string());
+
+    Overrides {
+        config: std::fs::read_to_string(config_path).ok(),
+        server: std::env::var("SATRUNNER_SERVER").ok(),
+        region: std::env::var("SATRUNNER_REGION").ok(),
+    }
+    .with_args(std::env::args().skip(1))
+}
+
+impl Overrides {
+    // CLI args win over whatever was set before them: a `--region` there picks
+    // that region even if the environment named a server, unless the args
+    // name a server as well
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+    fn with_args(mut self, args: impl IntoIterator<Item = String>) -> Self {
+        let mut args = args.into_iter();
+        let mut server_arg = false;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--server" => {
+                    if let Some(server) = args.next() {
+                        self.server = Some(server);
+                        server_arg = true;
+                    }
+                }
+                "--region" => {
+                    if let Some(region) = args.next() {
+                        self.region = Some(region);
+                        self.server = self.server.filter(|_| server_arg);
+                    }
+                }
+                _ => {}
+            }
+        }
+        self
+    }
+}
+
+pub fn start_probes(
+    mut probes: ResMut<RegionProbes>,
+    mut endpoint: ResMut<ServerEndpoint>,
+    transport: Res<NetworkTransport>,
+) {
+    probes.refresh = false;
+    probes.probes.clear();
+
+    for (index, region) in endpoint.regions.iter_mut().enumerate() {
+        let (mut outgoing, send_rx) = channel::<ClientMessage>(1);
+        let (read_tx, incoming) = channel::<Vec<u8>>(16);
+        let (cancel_tx, disconnected) = channel::<()>(1);
+
+        let channels = TransportChannels {
+            outgoing: send_rx,
+            incoming: read_tx,
+            disconnected: cancel_tx,
+        };
+
+        region.latency = None;
+        region.probing = true;
+
+        // the server answers a probe's handshake with Welcome and hangs up, the
+        // Welcome is all a probe waits for
+        let _ = outgoing.try_send(ClientMessage::Hello(Handshake::probe()));
+
+        match transport.0.open(&region.url, channels) {
+            Ok(()) => probes.probes.push(Probe {
+                region: index,
+                started: Instant::now(),
+                _outgoing: outgoing,
+                incoming,
+                disconnected,
+            }),
+            Err(e) => {
+                error!("{}", e);
+                region.probing = false;
+            }
+        }
+    }
+}
+
+pub fn poll_probes(mut probes: ResMut<RegionProbes>, mut endpoint: ResMut<ServerEndpoint>) {
+    // dropping a probe closes its outgoing channel, which shuts the socket
+    probes.probes.retain_mut(|probe| {
+        let region = &mut endpoint.regions[probe.region];
+
+        if let Ok(Some(_)) = probe.incoming.try_next() {
+            region.latency = Some(probe.started.elapsed());
+            region.probing = false;
+            return false;
+        }
+
+        if let Ok(Some(_)) = probe.disconnected.try_next() {
+            region.probing = false;
+            return false;
+        }
+
+        if probe.started.elapsed() > PROBE_TIMEOUT {
+            region.probing = false;
+            return false;
+        }
+
+        true
+    });
+}
+
+pub fn switch_region(
+    mut endpoint: ResMut<ServerEndpoint>,
+    mut network_stuff: ResMut<NetworkStuff>,
+    mut ping: ResMut<PingTimer>,
+    mut protocol: ResMut<Protocol>,
+    mut reconnect: ResMut<Reconnect>,
+    transport: Res<NetworkTransport>,
+    state: Res<State<GameStage>>,
+) {
+    if let Some(index) = endpoint.pending.take() {
+        if index != endpoint.selected && index < endpoint.regions.len() {
+            endpoint.selected = index;
+            info!("switching to {}", endpoint.url());
+
+            reconnect.resume_stage = Some(state.get().clone());
+            connect(
+                &mut network_stuff,
+                &mut ping,
+                &mut protocol,
+                transport.0.as_ref(),
+                endpoint.url(),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{endpoint_from, parse_regions, Overrides, DEFAULT_REGIONS};
+
+    const CONFIG: &str = "
+# regions we run
+eu = wss://eu.example.com/run   # closest to most players
+
+us=wss://us.example.com/run#lobby
+not a region
+empty =
+= wss://nameless.example.com/run
+";
+
+    fn names(config: &str) -> Vec<(String, String)> {
+        parse_regions(config)
+            .into_iter()
+            .map(|region| (region.name, region.url))
+            .collect()
+    }
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn config_skips_comments_blank_and_malformed_lines() {
+        assert_eq!(
+            names(CONFIG),
+            vec![
+                ("eu".to_string(), "wss://eu.example.com/run".to_string()),
+                (
+                    "us".to_string(),
+                    "wss://us.example.com/run#lobby".to_string()
+                ),
+            ]
+        );
+        assert!(parse_regions("# eu = wss://eu.example.com/run\n\n").is_empty());
+    }
+
+    #[test]
+    fn the_config_replaces_the_built_in_regions() {
+        let endpoint = endpoint_from(Overrides::default());
+        assert_eq!(endpoint.regions.len(), DEFAULT_REGIONS.len());
+        assert_eq!(endpoint.url(), DEFAULT_REGIONS[0].1);
+
+        let endpoint = endpoint_from(Overrides {
+            config: Some(CONFIG.to_string()),
+            ..Default::default()
+        });
+        assert_eq!(endpoint.regions.len(), 2);
+        assert_eq!(endpoint.url(), "wss://eu.example.com/run");
+
+        // a config with no regions in it falls back to the built-in ones
+        let endpoint = endpoint_from(Overrides {
+            config: Some("# nothing here".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(endpoint.regions.len(), DEFAULT_REGIONS.len());
+    }
+
+    #[test]
+    fn the_most_explicit_choice_wins() {
+        let endpoint = endpoint_from(Overrides {
+            config: Some(CONFIG.to_string()),
+            region: Some("us".to_string()),
+            server: None,
+        });
+        assert_eq!(endpoint.url(), "wss://us.example.com/run#lobby");
+
+        // an unknown region keeps the first one
+        let endpoint = endpoint_from(Overrides {
+            config: Some(CONFIG.to_string()),
+            region: Some("moon".to_string()),
+            server: None,
+        });
+        assert_eq!(endpoint.selected, 0);
+
+        let env = Overrides {
+            config: Some(CONFIG.to_string()),
+            region: Some("us".to_string()),
+            server: Some("ws://env.example.com/run".to_string()),
+        };
+        // a server wins over a region from the same place
+        let endpoint = endpoint_from(env.clone());
+        assert_eq!(endpoint.regions.len(), 3);
+        assert_eq!(endpoint.regions[2].name, "custom");
+        assert_eq!(endpoint.url(), "ws://env.example.com/run");
+
+        // but a region picked on the command line wins over the environment
+        let endpoint = endpoint_from(env.clone().with_args(args(&["--region", "eu"])));
+        assert_eq!(endpoint.regions.len(), 2);
+        assert_eq!(endpoint.url(), "wss://eu.example.com/run");
+
+        let both = args(&["--server", "ws://cli.example.com/run", "--region", "eu"]);
+        let endpoint = endpoint_from(env.with_args(both));
+        assert_eq!(endpoint.url(), "ws://cli.example.com/run");
+
+        let env = Overrides {
+            region: Some("us".to_string()),
+            server: Some("ws://env.example.com/run".to_string()),
+            ..Default::default()
+        };
+        let overrides = env.with_args(args(&[
+            "--verbose",
+            "--server",
+            "ws://cli.example.com/run",
+            "--region",
+        ]));
+        assert_eq!(
+            overrides.server.as_deref(),
+            Some("ws://cli.example.com/run")
+        );
+        assert_eq!(overrides.region.as_deref(), Some("us"));
+    }
+}
@@ -0,0 +1,255 @@
+use std::sync::{Arc, Mutex};
+
+use speedy::{Readable, Writable};
+
+use super::{
+    messages::{ClientMessage, NetworkMessage},
+    transport::{Transport, TransportChannels, TransportError},
+};
+
+// In-memory transport for tests: whatever the client opens is handed to the
+// paired `LoopbackServer`, which plays the part of the remote end.
+pub struct LoopbackTransport {
+    peer: Arc<Mutex<Option<TransportChannels>>>,
+}
+
+pub struct LoopbackServer {
+    peer: Arc<Mutex<Option<TransportChannels>>>,
+}
+
+pub fn loopback() -> (LoopbackTransport, LoopbackServer) {
+    let peer = Arc::new(Mutex::new(None));
+    (
+        LoopbackTransport { peer: peer.clone() },
+        LoopbackServer { peer },
+    )
+}
+
+impl Transport for LoopbackTransport {
+    fn open(&self, _url: &str, channels: TransportChannels) -> Result<(), TransportError> {
+        *self.peer.lock().unwrap() = Some(channels);
+        Ok(())
+    }
+}
+
+impl LoopbackServer {
+    pub fn is_connected(&self) -> bool {
+        self.peer.lock().unwrap().is_some()
+    }
+
+    pub fn recv(&self) -> Option<ClientMessage> {
+        let mut peer = self.peer.lock().unwrap();
+        let channels = peer.as_mut()?;
+        match channels.outgoing.try_next() {
+            Ok(Some(message)) => {
+                let bytes = message.write_to_vec().unwrap();
+                Some(ClientMessage::read_from_buffer(&bytes).unwrap())
+            }
+            _ => None,
+        }
+    }
+
+    pub fn send(&self, message: &NetworkMessage) -> bool {
+        let mut peer = self.peer.lock().unwrap();
+        match peer.as_mut() {
+            Some(channels) => channels
+                .incoming
+                .try_send(message.write_to_vec().unwrap())
+                .is_ok(),
+            None => false,
+        }
+    }
+
+    pub fn disconnect(&self) {
+        if let Some(mut channels) = self.peer.lock().unwrap().take() {
+            let _ = channels.disconnected.try_send(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{prelude::*, utils::Instant};
+    use speedy::Readable;
+    use uuid::Uuid;
+
+    use super::loopback;
+    use crate::{
+        game_util::resources::{
+            NetworkStuff, NetworkTransport, PingTimer, Protocol, Reconnect, Region, ServerEndpoint,
+            OUTGOING_BACKLOG,
+        },
+        network::{
+            messages::{ClientMessage, Handshake, NetworkMessage, PlayerInput, ResumeRequest},
+            websockets::{reconnect, websocket},
+        },
+    };
+
+    fn app() -> (App, super::LoopbackServer) {
+        let (transport, server) = loopback();
+        let mut app = App::new();
+        app.insert_resource(NetworkStuff::new())
+            .insert_resource(PingTimer::new())
+            .insert_resource(Protocol::new())
+            .insert_resource(NetworkTransport(Box::new(transport)))
+            .insert_resource(Reconnect::new())
+            .insert_resource(ServerEndpoint::new(
+                vec![Region::new("loopback", "loopback")],
+                0,
+            ))
+            .add_systems(Startup, websocket)
+            .add_systems(Update, reconnect);
+        app.update();
+        (app, server)
+    }
+
+    #[test]
+    fn client_messages_reach_server() {
+        let (mut app, server) = app();
+        assert!(server.is_connected());
+
+        let id = Uuid::from_u128(7);
+        let mut network_stuff = app.world.resource_mut::<NetworkStuff>();
+        network_stuff
+            .write
+            .as_mut()
+            .unwrap()
+            .try_send(ClientMessage::PlayerInput(PlayerInput::new(
+                [1.0, 2.0],
+                id,
+                42,
+                true,
+            )))
+            .unwrap();
+
+        assert!(matches!(
+            server.recv(),
+            Some(ClientMessage::Hello(hello)) if hello == Handshake::client()
+        ));
+        match server.recv() {
+            Some(ClientMessage::PlayerInput(input)) => {
+                assert_eq!(input.id, id);
+                assert_eq!(input.tick, 42);
+                assert_eq!(input.target, [1.0, 2.0]);
+            }
+            other => panic!("unexpected message: {:?}", other),
+        }
+        assert!(server.recv().is_none());
+    }
+
+    #[test]
+    fn server_frames_reach_client() {
+        let (mut app, server) = app();
+        assert!(server.send(&NetworkMessage::Ping(5)));
+
+        let mut network_stuff = app.world.resource_mut::<NetworkStuff>();
+        let frame = network_stuff.read.as_mut().unwrap().try_next().unwrap();
+        assert!(matches!(
+            NetworkMessage::read_from_buffer(&frame.unwrap()),
+            Ok(NetworkMessage::Ping(5))
+        ));
+    }
+
+    #[test]
+    fn disconnect_is_signalled() {
+        let (mut app, server) = app();
+        server.disconnect();
+        assert!(!server.is_connected());
+
+        let mut ping = app.world.resource_mut::<PingTimer>();
+        let signal = ping.disconnected_rx.as_mut().unwrap().try_next();
+        assert!(matches!(signal, Ok(Some(()))));
+    }
+
+    #[test]
+    fn reconnect_reopens_transport_when_due() {
+        let (mut app, server) = app();
+        server.disconnect();
+
+        app.world.resource_mut::<Reconnect>().schedule(1);
+        app.world.resource_mut::<Protocol>().server = Some(Handshake::client());
+        app.update();
+        assert!(!server.is_connected());
+        assert!(app.world.resource::<Protocol>().server.is_some());
+
+        app.world.resource_mut::<Reconnect>().retry_at = Some(Instant::now());
+        app.update();
+        assert!(server.is_connected());
+        // the new server has to say what it supports all over again
+        assert!(app.world.resource::<Protocol>().server.is_none());
+        assert_eq!(app.world.resource::<Reconnect>().attempt, 1);
+        assert!(app.world.resource::<Reconnect>().retry_at.is_none());
+    }
+
+    #[test]
+    fn full_channel_coalesces_into_backlog() {
+        let (mut app, server) = app();
+        let mut network_stuff = app.world.resource_mut::<NetworkStuff>();
+
+        // Hello already took one slot, the sender has one more of its own
+        for tick in 0..1000 {
+            network_stuff.send(ClientMessage::Ping(tick));
+        }
+        assert!(network_stuff.backlog.is_empty());
+
+        let input = |tick| PlayerInput::new([0.0, 0.0], Uuid::from_u128(1), tick, true);
+        network_stuff.send(ClientMessage::InputBatch(vec![input(1)]));
+        network_stuff.send(ClientMessage::Resume(ResumeRequest {
+            id: Uuid::from_u128(1),
+            resume_token: 3,
+        }));
+        network_stuff.send(ClientMessage::InputBatch(vec![input(1), input(2)]));
+        assert_eq!(network_stuff.backlog.len(), 2);
+
+        for _ in 0..1001 {
+            server.recv().unwrap();
+        }
+        network_stuff.flush();
+        assert!(network_stuff.backlog.is_empty());
+        assert!(matches!(server.recv(), Some(ClientMessage::Resume(_))));
+        match server.recv() {
+            Some(ClientMessage::InputBatch(batch)) => assert_eq!(batch.len(), 2),
+            other => panic!("unexpected message: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn full_backlog_drops_inputs_but_not_the_session() {
+        let (mut app, _server) = app();
+        let mut network_stuff = app.world.resource_mut::<NetworkStuff>();
+        for tick in 0..1000 {
+            network_stuff.send(ClientMessage::Ping(tick));
+        }
+
+        network_stuff.send(ClientMessage::Resume(ResumeRequest {
+            id: Uuid::from_u128(1),
+            resume_token: 3,
+        }));
+        network_stuff.send(ClientMessage::PlayerName("a".to_string()));
+        for tick in 0..100 {
+            let input = PlayerInput::new([0.0, 0.0], Uuid::from_u128(1), tick, true);
+            network_stuff.send(ClientMessage::PlayerInput(input));
+        }
+        network_stuff.send(ClientMessage::PlayerName("b".to_string()));
+
+        let backlog = &network_stuff.backlog;
+        assert_eq!(backlog.len(), OUTGOING_BACKLOG);
+        assert!(matches!(backlog[0], ClientMessage::Resume(_)));
+        assert!(matches!(&backlog[1], ClientMessage::PlayerName(name) if name == "a"));
+        assert!(
+            matches!(&backlog[OUTGOING_BACKLOG - 1], ClientMessage::PlayerName(name) if name == "b")
+        );
+        // the newest inputs made it
+        let ticks: Vec<_> = backlog
+            .iter()
+            .filter_map(|message| match message {
+                ClientMessage::PlayerInput(input) => Some(input.tick),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            ticks,
+            (100 - (OUTGOING_BACKLOG as u64 - 3)..100).collect::<Vec<_>>()
+        );
+    }
+}
@@ -1,2 +1,9 @@
+#[cfg(debug_assertions)]
+pub mod conditioner;
+pub mod endpoint;
+#[cfg(test)]
+pub mod loopback;
 pub mod messages;
+pub mod receive;
+pub mod transport;
 pub mod websockets;
@@ -0,0 +1,119 @@
+use bevy::prelude::*;
+use speedy::Readable;
+
+use crate::{
+    game_core::player::Player,
+    game_util::resources::{Inbox, InputSync, Latency, NetworkStuff, Protocol, SnapshotBaselines},
+    GameStage,
+};
+
+use satrunner_protocol::delta;
+
+use super::messages::{ClientMessage, NetworkMessage, PROTOCOL_VERSION};
+
+// Decodes every frame from the transport. Connection level messages are
+// answered here, snapshots are expanded back into GameState/GameUpdate and the
+// rest is queued for handle_server.
+#[allow(clippy::too_many_arguments)]
+pub fn receive_frames(
+    mut incoming: ResMut<NetworkStuff>,
+    mut protocol: ResMut<Protocol>,
+    mut latency: ResMut<Latency>,
+    mut baselines: ResMut<SnapshotBaselines>,
+    mut inbox: ResMut<Inbox>,
+    mut next_state: ResMut<NextState<GameStage>>,
+    mut input_sync: ResMut<InputSync>,
+    mut query_player: Query<&mut Player>,
+    time: Res<Time>,
+) {
+    let incoming = &mut *incoming;
+    let Some(ref mut receive_rx) = incoming.read else {
+        return;
+    };
+    let mut replies = Vec::new();
+
+    while let Ok(Some(message)) = receive_rx.try_next() {
+        if !protocol.is_compatible() {
+            continue;
+        }
+
+        let decoded = NetworkMessage::read_from_buffer(&message);
+        match decoded {
+            Ok(_) => protocol.decoded += 1,
+            Err(ref e) => {
+                protocol.undecodable += 1;
+                warn!(
+                    "undecodable frame #{} ({} bytes): {}",
+                    protocol.undecodable,
+                    message.len(),
+                    e
+                );
+            }
+        }
+
+        let reply = match decoded {
+            Ok(NetworkMessage::Welcome(welcome)) => {
+                if welcome.protocol_version != PROTOCOL_VERSION {
+                    error!(
+                        "protocol mismatch: client {} server {}",
+                        PROTOCOL_VERSION, welcome.protocol_version
+                    );
+                    next_state.set(GameStage::OutOfDate);
+                }
+                // a new connection, nothing acked on the old one counts
+                baselines.frames.clear();
+                input_sync.acked = None;
+                input_sync.dirty = true;
+                protocol.server = Some(welcome);
+                None
+            }
+            Ok(NetworkMessage::Ping(timestamp)) => Some(ClientMessage::Pong(timestamp)),
+            Ok(NetworkMessage::Pong(timestamp)) => {
+                let now = time.raw_elapsed().as_millis() as u64;
+                latency.sample(now.saturating_sub(timestamp) as f32);
+                None
+            }
+            Ok(NetworkMessage::Snapshot(snapshot)) => {
+                let baseline = snapshot.baseline.and_then(|tick| baselines.get(tick));
+                match delta::apply(baseline, &snapshot) {
+                    Some(frame) => {
+                        let players = frame.values().map(|p| p.to_player_state()).collect();
+                        inbox.0.push(NetworkMessage::GameState(players));
+                        inbox.0.push(NetworkMessage::GameUpdate(delta::positions(
+                            snapshot.tick,
+                            &frame,
+                        )));
+                        baselines.push(snapshot.tick, frame);
+                        Some(ClientMessage::SnapshotAck(snapshot.tick))
+                    }
+                    None => {
+                        // not acking makes the server fall back to a full snapshot
+                        warn!(
+                            "snapshot {} references missing baseline {:?}",
+                            snapshot.tick, snapshot.baseline
+                        );
+                        None
+                    }
+                }
+            }
+            Ok(NetworkMessage::InputAck(tick)) => {
+                input_sync.acked = input_sync.acked.max(Some(tick));
+                for mut player in query_player.iter_mut() {
+                    player.pending_inputs.retain(|input| input.tick > tick);
+                }
+                None
+            }
+            Ok(message) => {
+                inbox.0.push(message);
+                None
+            }
+            Err(_) => None,
+        };
+
+        replies.extend(reply);
+    }
+
+    for reply in replies {
+        incoming.send(reply);
+    }
+}
@@ -0,0 +1,31 @@
+use std::fmt;
+
+use futures::channel::mpsc::{Receiver, Sender};
+
+use super::messages::ClientMessage;
+
+pub struct TransportChannels {
+    pub outgoing: Receiver<ClientMessage>,
+    pub incoming: Sender<Vec<u8>>,
+    pub disconnected: Sender<()>,
+}
+
+#[derive(Debug)]
+pub enum TransportError {
+    Open(String),
+}
+
+impl fmt::Display for TransportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransportError::Open(e) => write!(f, "failed to open transport: {}", e),
+        }
+    }
+}
+
+// A transport pumps encoded client messages out of `outgoing`, pushes every
+// binary frame it receives into `incoming` and signals `disconnected` once the
+// connection is gone. Backends should shut down when `outgoing` is closed.
+pub trait Transport: Send + Sync {
+    fn open(&self, url: &str, channels: TransportChannels) -> Result<(), TransportError>;
+}
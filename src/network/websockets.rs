@@ -1,82 +1,265 @@
-use bevy::prelude::*;
-use futures::{SinkExt, StreamExt};
-use gloo_net::websocket::WebSocketError;
-use gloo_net::websocket::{futures::WebSocket, Message};
+use bevy::{prelude::*, utils::Instant};
 
-use speedy::Writable;
-use wasm_bindgen_futures::spawn_local;
+use crate::game_util::resources::{
+    NetworkStuff, NetworkTransport, PingTimer, Protocol, Reconnect, ServerEndpoint,
+};
 
-use crate::game_util::resources::{NetworkStuff, PingTimer};
+use super::{
+    messages::{ClientMessage, Handshake, CAP_RTT},
+    transport::{Transport, TransportChannels},
+};
 
-use super::messages::ClientMessage;
+#[cfg(target_arch = "wasm32")]
+pub use self::gloo::GlooTransport;
+#[cfg(not(target_arch = "wasm32"))]
+pub use self::tungstenite::TungsteniteTransport;
 
-// pub const DELAY: u32 = 500;
-// use gloo_timers::future::TimeoutFuture;
+pub fn websocket(
+    mut network_stuff: ResMut<NetworkStuff>,
+    mut ping: ResMut<PingTimer>,
+    mut protocol: ResMut<Protocol>,
+    transport: Res<NetworkTransport>,
+    endpoint: Res<ServerEndpoint>,
+) {
+    connect(
+        &mut network_stuff,
+        &mut ping,
+        &mut protocol,
+        transport.0.as_ref(),
+        endpoint.url(),
+    );
+}
+
+pub fn reconnect(
+    mut network_stuff: ResMut<NetworkStuff>,
+    mut ping: ResMut<PingTimer>,
+    mut protocol: ResMut<Protocol>,
+    transport: Res<NetworkTransport>,
+    mut reconnect: ResMut<Reconnect>,
+    endpoint: Res<ServerEndpoint>,
+) {
+    if let Some(retry_at) = reconnect.retry_at {
+        if Instant::now() >= retry_at {
+            reconnect.retry_at = None;
+            reconnect.attempt += 1;
+            info!("reconnecting (attempt {})", reconnect.attempt);
+
+            connect(
+                &mut network_stuff,
+                &mut ping,
+                &mut protocol,
+                transport.0.as_ref(),
+                endpoint.url(),
+            );
+        }
+    }
+}
+
+pub fn send_ping(
+    mut ping: ResMut<PingTimer>,
+    mut network_stuff: ResMut<NetworkStuff>,
+    time: Res<Time>,
+    protocol: Res<Protocol>,
+) {
+    if ping.timer.tick(time.delta()).just_finished() && protocol.supports(CAP_RTT) {
+        let timestamp = time.raw_elapsed().as_millis() as u64;
+        network_stuff.send(ClientMessage::Ping(timestamp));
+    }
+}
 
-pub fn websocket(mut network_stuff: ResMut<NetworkStuff>, mut ping: ResMut<PingTimer>) {
-    #[cfg(debug_assertions)]
-    let ws = WebSocket::open("ws://0.0.0.0:3030/run").unwrap();
-    #[cfg(not(debug_assertions))]
-    let ws = WebSocket::open("wss://satrunner.gg/run").unwrap();
-    let (mut write, mut read) = ws.split();
+pub fn flush_outgoing(mut network_stuff: ResMut<NetworkStuff>) {
+    network_stuff.flush();
+}
 
-    let (send_tx, mut send_rx) = futures::channel::mpsc::channel::<ClientMessage>(1000);
-    let (mut read_tx, read_rx) = futures::channel::mpsc::channel::<Vec<u8>>(20000);
+pub fn connect(
+    network_stuff: &mut NetworkStuff,
+    ping: &mut PingTimer,
+    protocol: &mut Protocol,
+    transport: &dyn Transport,
+    url: &str,
+) {
+    let (mut send_tx, send_rx) = futures::channel::mpsc::channel::<ClientMessage>(1000);
+    let (read_tx, read_rx) = futures::channel::mpsc::channel::<Vec<u8>>(20000);
 
     let (cancel_tx, cancel_rx) = futures::channel::mpsc::channel::<()>(1);
-    let mut cancel_tx_clone = cancel_tx.clone();
+
+    let channels = TransportChannels {
+        outgoing: send_rx,
+        incoming: read_tx,
+        disconnected: cancel_tx.clone(),
+    };
+
+    // always first on the socket, the server drops anything that comes before
+    // it. The rest goes out without waiting for Welcome.
+    let _ = send_tx.try_send(ClientMessage::Hello(Handshake::client()));
+    // this may be a different server, it supports nothing until it says so
+    protocol.server = None;
 
     network_stuff.write = Some(send_tx);
     network_stuff.read = Some(read_rx);
+    network_stuff.backlog.clear();
     ping.disconnected_rx = Some(cancel_rx);
     ping.disconnected_tx = Some(cancel_tx);
 
-    spawn_local(async move {
-        while let Some(message) = send_rx.next().await {
-            let message = message.write_to_vec().unwrap();
+    if let Err(e) = transport.open(url, channels) {
+        error!("{}", e);
+        if let Some(ref mut disconnected) = ping.disconnected_tx {
+            let _ = disconnected.try_send(());
+        }
+    }
+}
 
-            // TimeoutFuture::new(DELAY).await;
+#[cfg(target_arch = "wasm32")]
+mod gloo {
+    use bevy::prelude::*;
+    use futures::{SinkExt, StreamExt};
+    use gloo_net::websocket::WebSocketError;
+    use gloo_net::websocket::{futures::WebSocket, Message};
+    use speedy::Writable;
+    use wasm_bindgen_futures::spawn_local;
 
-            let send = write.send(Message::Bytes(message)).await;
+    use crate::network::transport::{Transport, TransportChannels, TransportError};
 
-            match send {
-                Ok(_) => {}
-                Err(e) => {
-                    info!("{:?}", e)
+    pub struct GlooTransport;
+
+    impl Transport for GlooTransport {
+        fn open(&self, url: &str, channels: TransportChannels) -> Result<(), TransportError> {
+            let ws = WebSocket::open(url).map_err(|e| TransportError::Open(e.to_string()))?;
+            let (mut write, mut read) = ws.split();
+
+            let TransportChannels {
+                outgoing: mut send_rx,
+                incoming: mut read_tx,
+                disconnected: mut cancel_tx,
+            } = channels;
+
+            spawn_local(async move {
+                while let Some(message) = send_rx.next().await {
+                    let message = message.write_to_vec().unwrap();
+
+                    let send = write.send(Message::Bytes(message)).await;
+
+                    match send {
+                        Ok(_) => {}
+                        Err(e) => {
+                            info!("{:?}", e)
+                        }
+                    }
                 }
-            }
+                let _ = write.close().await;
+            });
+
+            spawn_local(async move {
+                while let Some(result) = read.next().await {
+                    match result {
+                        Ok(Message::Bytes(msg)) => match read_tx.try_send(msg) {
+                            Ok(()) => {}
+                            Err(e) => error!("Error sending message: {} CHANNEL FULL???", e),
+                        },
+
+                        Ok(Message::Text(_)) => {}
+
+                        Err(e) => match e {
+                            WebSocketError::ConnectionError => {
+                                error!("connection error: {:?}", e);
+                                let _ = cancel_tx.send(()).await;
+                                break;
+                            }
+                            WebSocketError::ConnectionClose(_) => {
+                                error!("connection closed error: {:?}", e);
+                                let _ = cancel_tx.send(()).await;
+                                break;
+                            }
+                            WebSocketError::MessageSendError(_) => {
+                                error!("msg send error: {:?}", e);
+                            }
+                            _ => {}
+                        },
+                    }
+                }
+            });
+
+            Ok(())
         }
-    });
+    }
+}
 
-    spawn_local(async move {
-        while let Some(result) = read.next().await {
-            // TimeoutFuture::new(DELAY).await;
+#[cfg(not(target_arch = "wasm32"))]
+mod tungstenite {
+    use bevy::prelude::*;
+    use futures::{future, SinkExt, StreamExt};
+    use speedy::Writable;
+    use tokio_tungstenite::{connect_async, tungstenite::Message};
 
-            match result {
-                Ok(Message::Bytes(msg)) => match read_tx.try_send(msg) {
-                    Ok(()) => {}
-                    Err(e) => error!("Error sending message: {} CHANNEL FULL???", e),
-                },
+    use crate::network::transport::{Transport, TransportChannels, TransportError};
 
-                Ok(Message::Text(_)) => {}
+    pub struct TungsteniteTransport;
 
-                Err(e) => match e {
-                    WebSocketError::ConnectionError => {
-                        error!("connection error: {:?}", e);
-                        cancel_tx_clone.send(()).await.unwrap();
+    impl Transport for TungsteniteTransport {
+        fn open(&self, url: &str, channels: TransportChannels) -> Result<(), TransportError> {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .map_err(|e| TransportError::Open(e.to_string()))?;
+            let url = url.to_string();
+
+            std::thread::Builder::new()
+                .name("satrunner-websocket".to_string())
+                .spawn(move || runtime.block_on(run(url, channels)))
+                .map_err(|e| TransportError::Open(e.to_string()))?;
+
+            Ok(())
+        }
+    }
+
+    async fn run(url: String, channels: TransportChannels) {
+        let TransportChannels {
+            outgoing: mut send_rx,
+            incoming: mut read_tx,
+            disconnected: mut cancel_tx,
+        } = channels;
+
+        let ws = match connect_async(url.as_str()).await {
+            Ok((ws, _)) => ws,
+            Err(e) => {
+                error!("connection error: {:?}", e);
+                let _ = cancel_tx.send(()).await;
+                return;
+            }
+        };
+        let (mut write, mut read) = ws.split();
+
+        let writer = Box::pin(async move {
+            while let Some(message) = send_rx.next().await {
+                let message = message.write_to_vec().unwrap();
+
+                if let Err(e) = write.send(Message::Binary(message)).await {
+                    info!("{:?}", e)
+                }
+            }
+        });
+
+        let reader = Box::pin(async move {
+            while let Some(result) = read.next().await {
+                match result {
+                    Ok(Message::Binary(msg)) => match read_tx.try_send(msg) {
+                        Ok(()) => {}
+                        Err(e) => error!("Error sending message: {} CHANNEL FULL???", e),
+                    },
+                    Ok(Message::Close(_)) => {
+                        error!("connection closed");
                         break;
                     }
-                    WebSocketError::ConnectionClose(_) => {
-                        error!("connection closed error: {:?}", e);
-                        cancel_tx_clone.send(()).await.unwrap();
+                    Ok(_) => {}
+                    Err(e) => {
+                        error!("connection error: {:?}", e);
                         break;
                     }
-                    WebSocketError::MessageSendError(_) => {
-                        error!("msg send error: {:?}", e);
-                    }
-                    _ => {}
-                },
+                }
             }
-        }
-    });
+            let _ = cancel_tx.send(()).await;
+        });
+
+        future::select(writer, reader).await;
+    }
 }